@@ -4,7 +4,7 @@ use std::io;
 use std::io::{Read, Write};
 use std::fs;
 use std::fs::{DirEntry, File};
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
 use std::env;
 
 fn main() {
@@ -23,6 +23,7 @@ fn generate_tests() -> io::Result<()> {
 use parser;
 use runtime::Interpreter;
 use ast_walk_interpreter::AstWalkInterpreter;
+use bytecode::BytecodeInterpreter;
 use typechecker::TypeChecker;
 ")?;
     let mut tests = Vec::new();
@@ -43,24 +44,36 @@ fn generate_run_pass_tests() -> io::Result<Vec<String>> {
         let mut file = File::open(entry.path()).unwrap();
         let mut content = String::new();
         file.read_to_string(&mut content)?;
-        tests.push(make_run_pass_test_fn(&test_name, &content));
+        tests.push(make_run_pass_test_fn(&test_name, &entry.path(), &content));
     }
     Ok(tests)
 }
 
-fn make_run_pass_test_fn(name: &str, code: &str) -> String {
+// Every run-pass file is run on both the AST walker and the bytecode VM
+fn make_run_pass_test_fn(name: &str, path: &Path, code: &str) -> String {
     format!("
 #[test]
 fn {name}() {{
     let code = r#\"{code}\"#;
     let ast = parser::program(code).unwrap();
-    let mut ast_walk_interpreter = AstWalkInterpreter::new();
+    let mut ast_walk_interpreter = AstWalkInterpreter::for_file({path:?});
     ast_walk_interpreter
         .run_ast_as_program(&ast)
         .unwrap();
 }}
+
+#[test]
+fn {name}_vm() {{
+    let code = r#\"{code}\"#;
+    let ast = parser::program(code).unwrap();
+    let mut bytecode_interpreter = BytecodeInterpreter::for_file({path:?});
+    bytecode_interpreter
+        .run_ast_as_program(&ast)
+        .unwrap();
+}}
 ",
             name = name,
+            path = path,
             code = code)
 }
 
@@ -74,18 +87,23 @@ fn generate_run_fail_tests() -> io::Result<Vec<String>> {
         let content = read_file(entry.path());
         let expected_err_to_str = read_file(entry.path().with_extension("err"));
         let test_name = test_name_from_entry(&entry, "run_fail");
-        tests.push(make_run_fail_test_fn(&test_name, &content, &expected_err_to_str.trim()));
+        tests.push(make_run_fail_test_fn(&test_name,
+                                         &entry.path(),
+                                         &content,
+                                         &expected_err_to_str.trim()));
     }
     Ok(tests)
 }
 
-fn make_run_fail_test_fn(name: &str, code: &str, expected_err_str: &str) -> String {
+// Every run-fail file is run on both the AST walker and the bytecode VM, which
+// have to fail with the same error
+fn make_run_fail_test_fn(name: &str, path: &Path, code: &str, expected_err_str: &str) -> String {
     format!("
 #[test]
 fn {name}() {{
     let code = r#\"{code}\"#;
     let ast = parser::program(code).unwrap();
-    let mut ast_walk_interpreter = AstWalkInterpreter::new();
+    let mut ast_walk_interpreter = AstWalkInterpreter::for_file({path:?});
     let err = ast_walk_interpreter
         .run_ast_as_program(&ast)
         .unwrap_err();
@@ -94,8 +112,23 @@ fn {name}() {{
         r#\"{expected_err_str}\"#
     );
 }}
+
+#[test]
+fn {name}_vm() {{
+    let code = r#\"{code}\"#;
+    let ast = parser::program(code).unwrap();
+    let mut bytecode_interpreter = BytecodeInterpreter::for_file({path:?});
+    let err = bytecode_interpreter
+        .run_ast_as_program(&ast)
+        .unwrap_err();
+    assert_eq!(
+        format!(\"{{:?}}\", err),
+        r#\"{expected_err_str}\"#
+    );
+}}
 ",
             name = name,
+            path = path,
             code = code,
             expected_err_str = expected_err_str)
 }
@@ -110,18 +143,25 @@ fn generate_typecheck_fail_tests() -> io::Result<Vec<String>> {
         let content = read_file(entry.path());
         let expected_err_to_str = read_file(entry.path().with_extension("err"));
         let test_name = test_name_from_entry(&entry, "typecheck_fail");
-        tests.push(make_typecheck_fail_test_fn(&test_name, &content, &expected_err_to_str.trim()));
+        tests.push(make_typecheck_fail_test_fn(&test_name,
+                                               &entry.path(),
+                                               &content,
+                                               &expected_err_to_str.trim()));
     }
     Ok(tests)
 }
 
-fn make_typecheck_fail_test_fn(name: &str, code: &str, expected_err_str: &str) -> String {
+fn make_typecheck_fail_test_fn(name: &str,
+                               path: &Path,
+                               code: &str,
+                               expected_err_str: &str)
+                               -> String {
     format!("
 #[test]
 fn {name}() {{
     let code = r#\"{code}\"#;
     let ast = parser::program(code).unwrap();
-    let mut checker = TypeChecker::new();
+    let mut checker = TypeChecker::for_file({path:?});
     checker.check_program(&ast);
     let issues = checker.get_issues();
     assert!(!issues.is_empty());
@@ -132,6 +172,7 @@ fn {name}() {{
 }}
 ",
             name = name,
+            path = path,
             code = code,
             expected_err_str = expected_err_str)
 }
@@ -145,18 +186,18 @@ fn generate_typecheck_pass_tests() -> io::Result<Vec<String>> {
         }
         let content = read_file(entry.path());
         let test_name = test_name_from_entry(&entry, "typecheck_pass");
-        tests.push(make_typecheck_pass_test_fn(&test_name, &content));
+        tests.push(make_typecheck_pass_test_fn(&test_name, &entry.path(), &content));
     }
     Ok(tests)
 }
 
-fn make_typecheck_pass_test_fn(name: &str, code: &str) -> String {
+fn make_typecheck_pass_test_fn(name: &str, path: &Path, code: &str) -> String {
     format!("
 #[test]
 fn {name}() {{
     let code = r#\"{code}\"#;
     let ast = parser::program(code).unwrap();
-    let mut checker = TypeChecker::new();
+    let mut checker = TypeChecker::for_file({path:?});
     checker.check_program(&ast);
     let issues = checker.get_issues();
     println!(\"{{:?}}\", issues);
@@ -164,6 +205,7 @@ fn {name}() {{
 }}
 ",
             name = name,
+            path = path,
             code = code)
 }
 
@@ -1,5 +1,7 @@
 use std::fmt;
 
+use bigint::BigInt;
+
 pub type OffsetSpan = (usize, usize);
 
 #[derive(Debug, Clone, PartialEq)]
@@ -20,6 +22,13 @@ pub enum BinOp {
     Gt,
     Gte,
     Eq,
+    Neq,
+    Pow,
+    BitAnd,
+    BitOr,
+    BitXor,
+    Shl,
+    Shr,
 }
 
 #[derive(Debug, Clone, PartialEq)]
@@ -51,6 +60,13 @@ impl fmt::Display for BinOp {
             BinOp::Gt => write!(f, ">"),
             BinOp::Gte => write!(f, ">="),
             BinOp::Eq => write!(f, "=="),
+            BinOp::Neq => write!(f, "!="),
+            BinOp::Pow => write!(f, "**"),
+            BinOp::BitAnd => write!(f, "&"),
+            BinOp::BitOr => write!(f, "|"),
+            BinOp::BitXor => write!(f, "^"),
+            BinOp::Shl => write!(f, "<<"),
+            BinOp::Shr => write!(f, ">>"),
         }
     }
 }
@@ -80,81 +96,278 @@ impl fmt::Display for LogicalUnOp {
     }
 }
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, PartialEq)]
 pub enum Literal {
     Integer(i64),
     Float(f64),
+    BigInt(BigInt),
     Bool(bool),
     String(String),
 }
 
 pub type LiteralNode = Spanned<Literal>;
 
-#[derive(Debug, Clone)]
+/// Where the AST walker keeps a variable, as worked out by the resolver
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum VarAddress {
+    /// A variable that is looked up by name in the frames and then in the top
+    /// level, which is how the parser leaves every variable. The resolver leaves
+    /// the variables that a function refers to before their declaration.
+    Unresolved,
+    /// A variable of the top level of a program, which is looked up by name
+    Global,
+    /// A variable of a block or function, as the number of frames out from the
+    /// innermost one and the slot in that frame
+    Local(usize, usize),
+}
+
+#[derive(Debug, Clone, PartialEq)]
 pub enum LhsExpr {
-    Identifier(String),
+    Identifier(String, VarAddress),
+    /// `obj.field`
+    Field(Box<LhsExprNode>, String),
+    /// `obj[index]`
+    Index(Box<LhsExprNode>, Box<ExprNode>),
 }
 
 pub type LhsExprNode = Spanned<LhsExpr>;
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, PartialEq)]
 pub enum Variable {
-    Identifier(BindingType, String),
+    Identifier(BindingType, String, Option<ConstraintType>, VarAddress),
+    /// A destructuring declaration, like `var (a, b) = pair;`
+    Pattern(BindingType, PatternNode),
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Pattern {
+    /// `_`, which matches anything without binding it
+    Wildcard,
+    /// A name, which matches anything and binds it
+    Binding(String, VarAddress),
+    Literal(Literal),
+    Tuple(Vec<PatternNode>),
+}
+
+impl Pattern {
+    /// The first name the pattern binds more than once, if any
+    pub fn find_duplicate_binding(&self) -> Option<&String> {
+        let mut names = Vec::new();
+        self.collect_binding_names(&mut names);
+        names
+            .iter()
+            .enumerate()
+            .find(|&(i, name)| names[..i].contains(name))
+            .map(|(_, name)| *name)
+    }
+
+    fn collect_binding_names<'a>(&'a self, names: &mut Vec<&'a String>) {
+        match *self {
+            Pattern::Binding(ref name, _) => names.push(name),
+            Pattern::Tuple(ref patterns) => {
+                for pattern in patterns {
+                    pattern.data.collect_binding_names(names);
+                }
+            }
+            Pattern::Wildcard |
+            Pattern::Literal(_) => {}
+        }
+    }
 }
 
-#[derive(Debug, Clone)]
+pub type PatternNode = Spanned<Pattern>;
+
+/// `pattern if guard => body`
+#[derive(Debug, Clone, PartialEq)]
+pub struct MatchArm {
+    pub pattern: PatternNode,
+    pub guard: Option<ExprNode>,
+    pub body: ExprNode,
+    /// The number of variables the arm declares, as counted by the resolver
+    pub num_slots: usize,
+}
+
+pub type MatchArmNode = Spanned<MatchArm>;
+
+#[derive(Debug, Clone, PartialEq)]
 pub enum BindingType {
     Mutable,
+    Immutable,
+}
+
+/// A type written in an annotation, as in `var x: Number = 5;`
+#[derive(Debug, Clone, PartialEq)]
+pub enum ConstraintType {
+    Number,
+    Bool,
+    String,
+    Function,
+    Tuple,
+    List,
+    Map,
+    Result,
+    Any,
+}
+
+/// The annotation following `->` in a function definition
+#[derive(Debug, Clone, PartialEq)]
+pub enum ReturnConstraint {
+    Void,
+    Type(ConstraintType),
 }
 
-#[derive(Debug, Clone)]
+impl fmt::Display for ConstraintType {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            ConstraintType::Number => write!(f, "Number"),
+            ConstraintType::Bool => write!(f, "Bool"),
+            ConstraintType::String => write!(f, "String"),
+            ConstraintType::Function => write!(f, "Function"),
+            ConstraintType::Tuple => write!(f, "Tuple"),
+            ConstraintType::List => write!(f, "List"),
+            ConstraintType::Map => write!(f, "Map"),
+            ConstraintType::Result => write!(f, "Result"),
+            ConstraintType::Any => write!(f, "any"),
+        }
+    }
+}
+
+impl fmt::Display for ReturnConstraint {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            ReturnConstraint::Void => write!(f, "void"),
+            ReturnConstraint::Type(ref constraint) => write!(f, "{}", constraint),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct FnParam {
+    pub name: String,
+    pub constraint: Option<ConstraintType>,
+    /// The value of the parameter when no argument is given for it
+    pub default: Option<ExprNode>,
+    /// Whether this is a `...rest` parameter, which is bound to a tuple of the
+    /// arguments after the other parameters
+    pub is_rest: bool,
+}
+
+#[derive(Debug, Clone, PartialEq)]
 pub struct FnDefExpr {
     pub maybe_id: Option<String>,
-    pub params: Vec<String>,
+    pub params: Vec<FnParam>,
+    pub ret_constraint: Option<ReturnConstraint>,
     pub body: Box<StmtNode>,
+    /// Where the function is declared under its name, if it has one
+    pub address: VarAddress,
+    /// The number of variables of the frame that the parameters are declared in
+    pub num_slots: usize,
 }
 
-#[derive(Debug, Clone)]
+impl FnDefExpr {
+    /// The first parameter without a default value that comes after one with a
+    /// default value, if any
+    pub fn find_required_param_after_default(&self) -> Option<&String> {
+        let first_default = self.params.iter().position(|param| param.default.is_some())?;
+        self.params[first_default..]
+            .iter()
+            .find(|param| param.default.is_none() && !param.is_rest)
+            .map(|param| &param.name)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
 pub enum Expr {
     Literal(LiteralNode),
-    Identifier(String),
+    Identifier(String, VarAddress),
     Binary(Box<ExprNode>, BinOp, Box<ExprNode>),
     BinaryLogical(Box<ExprNode>, LogicalBinOp, Box<ExprNode>),
     Unary(UnOp, Box<ExprNode>),
     UnaryLogical(LogicalUnOp, Box<ExprNode>),
     FnDef(FnDefExpr),
-    FnCall(Box<ExprNode>, Vec<ExprNode>),
+    /// `f(positional, args, name = named_arg)`
+    FnCall(Box<ExprNode>, Vec<ExprNode>, Vec<(String, ExprNode)>),
     Tuple(Vec<ExprNode>),
+    /// `[a, b, c]`
+    List(Vec<ExprNode>),
+    /// `{ "key": expr, ... }`
+    Map(Vec<(String, ExprNode)>),
     MemberByIdx(Box<ExprNode>, Box<ExprNode>),
+    FieldAccess(Box<ExprNode>, String),
+    /// A `loop` used as an expression, evaluating to the value it is broken with
+    Loop(Option<String>, Box<StmtNode>),
+    /// An `if` with an `else` used as an expression, evaluating to the value of
+    /// the branch that is taken
+    IfThen(Box<IfThenStmt>),
+    /// A block used as an expression, evaluating to the value of its last statement.
+    /// Like a block statement, it has the number of variables it declares.
+    Block(Vec<StmtNode>, usize),
+    Match(Box<ExprNode>, Vec<MatchArmNode>),
+    /// `expr?`, which unwraps an `Ok` or returns an `Err` from the enclosing function
+    Try(Box<ExprNode>),
 }
 
 // Only for parser convenience
 pub enum ExprSuffix {
-    ListInParens(Vec<ExprNode>),
+    ListInParens(Vec<ExprNode>, Vec<(String, ExprNode)>),
     InSquareBrackets(ExprNode),
+    Field(String),
+    QuestionMark,
 }
 
 pub type ExprNode = Spanned<Expr>;
 
-#[derive(Debug, Clone)]
+/// `struct Name { field1, field2 }`
+#[derive(Debug, Clone, PartialEq)]
+pub struct StructDecl {
+    pub name: String,
+    pub fields: Vec<String>,
+    pub address: VarAddress,
+}
+
+impl StructDecl {
+    /// The first field that is declared more than once, if any
+    pub fn find_duplicate_field(&self) -> Option<&String> {
+        self.fields
+            .iter()
+            .enumerate()
+            .find(|&(i, field)| self.fields[..i].contains(field))
+            .map(|(_, field)| field)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
 pub struct IfThenStmt {
     pub cond: ExprNode,
     pub then_block: Box<StmtNode>,
     pub maybe_else_block: Option<Box<StmtNode>>,
 }
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, PartialEq)]
 pub enum Stmt {
     Assign(LhsExprNode, ExprNode),
     AssignOp(LhsExprNode, BinOp, ExprNode),
     VarDecl(Variable, ExprNode),
+    StructDecl(StructDecl),
     Expr(ExprNode),
-    Block(Vec<StmtNode>),
+    /// The statements of a block, and the number of variables they declare, as
+    /// counted by the resolver
+    Block(Vec<StmtNode>, usize),
     IfThen(IfThenStmt),
-    Loop(Box<StmtNode>),
+    /// Loops carry an optional label, as in `'outer: loop { ... }`
+    Loop(Option<String>, Box<StmtNode>),
+    While(Option<String>, ExprNode, Box<StmtNode>),
+    /// `for name in expr { ... }`
+    ForIn(Option<String>, String, ExprNode, Box<StmtNode>),
     Return(Option<ExprNode>),
-    Break,
-    Continue,
+    Throw(ExprNode),
+    /// `try { ... } catch name { ... }`
+    TryCatch(Box<StmtNode>, String, Box<StmtNode>),
+    /// `import "path" as name;`, only allowed at the top level of a program
+    Import(String, String),
+    /// `break 'label expr;`, where both the label and the value are optional
+    Break(Option<String>, Option<ExprNode>),
+    Continue(Option<String>),
     Empty,
 }
 
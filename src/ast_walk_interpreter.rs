@@ -2,55 +2,144 @@ use std::rc::Rc;
 use std::cell::RefCell;
 use std::usize;
 
+use linear_map::LinearMap;
+
 use ast::*;
 use value::*;
 use operations;
-use environment::Environment;
+use environment::{Environment, Frame};
 use function::*;
+use module::{self, ModuleLoader, ModulePath};
 use runtime::*;
-use typechecker::Type;
+use error::describe_runtime_error;
+use bytecode;
+use resolver;
+
+/// The number of nested function calls after which a call fails with
+/// `RuntimeError::StackOverflow`, unless set otherwise. Every nested call takes
+/// up to `STACK_PER_CALL` bytes of the native stack of the thread the AST walker
+/// runs on, so that thread needs more stack than a thread gets by default.
+pub const DEFAULT_MAX_CALL_DEPTH: usize = 1000;
+
+/// The native stack that a nested function call of the AST walker can take up,
+/// with room to spare for nested expressions in a debug build
+pub const STACK_PER_CALL: usize = 64 * 1024;
+
+/// The largest maximum call depth that can be set, since the native stack for
+/// it is reserved before the program runs
+pub const MAX_CALL_DEPTH_LIMIT: usize = 50_000;
 
 #[derive(Clone)]
 struct Context {
     pub in_loop: bool,
+    // labels of the loops being evaluated, innermost last
+    pub loop_labels: Vec<String>,
     pub in_func: bool,
+    // calls in a `try` block are never tail calls, since the `catch` block
+    // has to see their errors
+    pub in_try: bool,
+    pub ret_constraint: Option<ReturnConstraint>,
+    // the number of function calls being evaluated
+    pub call_depth: usize,
+    pub max_call_depth: usize,
 }
 
 impl Context {
     pub fn root() -> Context {
         Context {
             in_loop: false,
+            loop_labels: Vec::new(),
             in_func: false,
+            in_try: false,
+            ret_constraint: None,
+            call_depth: 0,
+            max_call_depth: DEFAULT_MAX_CALL_DEPTH,
+        }
+    }
+
+    // The context for the body of a function called from this context
+    fn for_call(&self, ret_constraint: &Option<ReturnConstraint>) -> Context {
+        Context {
+            in_loop: false,
+            loop_labels: Vec::new(),
+            in_func: true,
+            in_try: false,
+            ret_constraint: ret_constraint.clone(),
+            call_depth: self.call_depth + 1,
+            max_call_depth: self.max_call_depth,
         }
     }
 }
 
 pub struct AstWalkInterpreter {
     env: Rc<RefCell<Environment>>,
+    // the frame of the innermost block or function being evaluated, which is
+    // `None` at the top level
+    frame: Option<Rc<RefCell<Frame>>>,
     context: Context,
+    // the file being run, which imports are relative to
+    file: Option<String>,
+    modules: Rc<RefCell<ModuleLoader<Value>>>,
 }
 
 impl AstWalkInterpreter {
     pub fn new() -> AstWalkInterpreter {
         AstWalkInterpreter {
             env: Environment::new_root(),
+            frame: None,
+            context: Context::root(),
+            file: None,
+            modules: Rc::new(RefCell::new(ModuleLoader::new(&None))),
+        }
+    }
+
+    pub fn for_file(file_name: &str) -> AstWalkInterpreter {
+        let file = Some(file_name.to_owned());
+        AstWalkInterpreter {
+            env: Environment::new_root(),
+            frame: None,
             context: Context::root(),
+            modules: Rc::new(RefCell::new(ModuleLoader::new(&file))),
+            file: file,
         }
     }
 
+    /// Sets the number of nested function calls after which a call fails with
+    /// `RuntimeError::StackOverflow`. The thread running the interpreter needs
+    /// `STACK_PER_CALL` bytes of stack for each of them.
+    pub fn set_max_call_depth(&mut self, max_call_depth: usize) {
+        self.context.max_call_depth = max_call_depth;
+    }
+
     fn with_environment_and_context(env: Rc<RefCell<Environment>>,
+                                    frame: Option<Rc<RefCell<Frame>>>,
                                     context: Context)
                                     -> AstWalkInterpreter {
+        // imports only appear at the top level, so function bodies never load modules
         AstWalkInterpreter {
             env: env,
+            frame: frame,
             context: context,
+            file: None,
+            modules: Rc::new(RefCell::new(ModuleLoader::new(&None))),
         }
     }
 
     fn interpret_program(&mut self,
                          program: &[StmtNode])
                          -> Result<Option<StmtResult>, RuntimeErrorWithPosition> {
-        let result = self.eval_stmts(program)?;
+        let mut program = program.to_vec();
+        resolver::resolve_program(&mut program, &self.env.borrow())?;
+        let result = self.eval_stmts(&program)?;
+        Ok(result)
+    }
+
+    fn interpret_statements(&mut self,
+                            statements: &[StmtNode])
+                            -> Result<Option<StmtResult>, RuntimeErrorWithPosition> {
+        let mut statements = statements.to_vec();
+        resolver::resolve_statements(&mut statements);
+        let result = self.eval_stmts(&statements)?;
         Ok(result)
     }
 
@@ -65,13 +154,23 @@ impl AstWalkInterpreter {
     }
 
     fn eval_stmt(&mut self, s: &StmtNode) -> Result<StmtResult, RuntimeErrorWithPosition> {
+        match self.eval_stmt_data(s) {
+            // an expression in this statement was left by a `break`, `continue`
+            // or `return`, which now applies to the statement itself
+            Err((RuntimeError::Unwind(result), _)) => Ok(*result),
+            result => result,
+        }
+    }
+
+    fn eval_stmt_data(&mut self, s: &StmtNode) -> Result<StmtResult, RuntimeErrorWithPosition> {
         match s.data {
             Stmt::VarDecl(ref variable, ref expr) => self.eval_stmt_var_decl(variable, expr),
+            Stmt::StructDecl(ref struct_decl) => self.eval_stmt_struct_decl(struct_decl, s),
             Stmt::Assign(ref lhs_expr, ref expr) => self.eval_stmt_assign(lhs_expr, expr),
             Stmt::AssignOp(ref lhs_expr, ref op, ref expr) => {
                 self.eval_stmt_assign_with_op(lhs_expr, op, expr, s)
             }
-            Stmt::Block(ref statements) => self.eval_stmt_block(statements),
+            Stmt::Block(ref statements, num_slots) => self.eval_stmt_block(statements, num_slots),
             Stmt::Expr(ref expr) => {
                 let val = self.eval_expr(expr)?;
                 match val {
@@ -80,34 +179,45 @@ impl AstWalkInterpreter {
                 }
             }
             Stmt::IfThen(ref if_then_stmt) => self.eval_stmt_if_then(if_then_stmt),
-            Stmt::Loop(ref block) => self.eval_stmt_loop(block),
+            Stmt::Loop(ref label, ref block) => {
+                self.eval_stmt_loop(label, block).map(discard_loop_value)
+            }
+            Stmt::While(ref label, ref cond, ref block) => {
+                self.eval_stmt_while(label, cond, block).map(discard_loop_value)
+            }
+            Stmt::ForIn(ref label, ref var_name, ref iterable_expr, ref block) => {
+                self.eval_stmt_for_in(label, var_name, iterable_expr, block)
+                    .map(discard_loop_value)
+            }
             Stmt::Return(ref possible_expr) => self.eval_stmt_return(possible_expr, s),
-            Stmt::Break => self.eval_stmt_break(s),
-            Stmt::Continue => self.eval_stmt_continue(s),
+            Stmt::Import(ref path, ref name) => self.eval_stmt_import(path, name, s),
+            Stmt::Throw(ref expr) => {
+                let val = self.eval_expr_as_value(expr)?;
+                Err((RuntimeError::Thrown(val), s.pos))
+            }
+            Stmt::TryCatch(ref try_block, ref error_name, ref catch_block) => {
+                self.eval_stmt_try_catch(try_block, error_name, catch_block)
+            }
+            Stmt::Break(ref label, ref possible_expr) => {
+                self.eval_stmt_break(label, possible_expr, s)
+            }
+            Stmt::Continue(ref label) => self.eval_stmt_continue(label, s),
             Stmt::Empty => Ok(StmtResult::None),
         }
     }
 
     fn eval_expr_as_value(&mut self, expr: &ExprNode) -> Result<Value, RuntimeErrorWithPosition> {
         let possible_val = self.eval_expr(expr)?;
-        if possible_val.is_none() {
-            if let Expr::FnCall(ref f_expr, _) = expr.data {
-                if let Expr::Identifier(ref id) = f_expr.data {
-                    return Err((RuntimeError::NoneError(Some(id.clone())), expr.pos));
-                }
-                return Err((RuntimeError::NoneError(None), expr.pos));
-            } else {
-                unreachable!();
-            }
-        }
-        Ok(possible_val.clone().unwrap())
+        possible_val.ok_or_else(|| none_error(expr))
     }
 
     fn eval_expr(&mut self, e: &ExprNode) -> Result<Option<Value>, RuntimeErrorWithPosition> {
         match e.data {
             Expr::Literal(ref x) => Ok(Some(Value::from(x.data.clone()))),
-            Expr::Identifier(ref id) => wrap(self.eval_expr_identifier(id, e)),
+            Expr::Identifier(ref id, address) => wrap(self.eval_expr_identifier(id, address, e)),
             Expr::Tuple(ref elems) => wrap(self.eval_expr_tuple(elems)),
+            Expr::List(ref elems) => wrap(self.eval_expr_list(elems)),
+            Expr::Map(ref entries) => wrap(self.eval_expr_map(entries)),
             Expr::Unary(ref op, ref expr) => wrap(self.eval_expr_unary(op, expr, e)),
             Expr::UnaryLogical(ref op, ref expr) => wrap(self.eval_expr_unary_logical(op, expr)),
             Expr::Binary(ref expr1, ref op, ref expr2) => {
@@ -119,8 +229,28 @@ impl AstWalkInterpreter {
             Expr::MemberByIdx(ref object_expr, ref index_expr) => {
                 wrap(self.eval_expr_member_by_idx(object_expr, index_expr, e))
             }
-            Expr::FnDef(ref fn_def_expr) => wrap(self.eval_expr_fn_def(fn_def_expr)),
-            Expr::FnCall(ref expr, ref args) => self.eval_expr_fn_call(expr, args, e),
+            Expr::Loop(ref label, ref block) => {
+                let result = self.eval_stmt_loop(label, block)?;
+                expr_value_of_stmt_result(result, e)
+            }
+            Expr::IfThen(ref if_then_stmt) => {
+                let result = self.eval_stmt_if_then(if_then_stmt)?;
+                expr_value_of_stmt_result(result, e)
+            }
+            Expr::Block(ref statements, num_slots) => {
+                let result = self.eval_stmt_block(statements, num_slots)?;
+                expr_value_of_stmt_result(result, e)
+            }
+            Expr::Match(ref scrutinee, ref arms) => self.eval_expr_match(scrutinee, arms),
+            Expr::Try(ref result_expr) => wrap(self.eval_expr_try(result_expr, e)),
+            Expr::FieldAccess(ref object_expr, ref field) => {
+                let object = self.eval_expr_as_value(object_expr)?;
+                wrap(get_field(&object, field).map_err(|err| (err, e.pos)))
+            }
+            Expr::FnDef(ref fn_def_expr) => wrap(self.eval_expr_fn_def(fn_def_expr, e)),
+            Expr::FnCall(ref expr, ref args, ref named_args) => {
+                self.eval_expr_fn_call(expr, args, named_args, e)
+            }
         }
     }
 
@@ -130,26 +260,154 @@ impl AstWalkInterpreter {
                           -> Result<StmtResult, RuntimeErrorWithPosition> {
         let val = self.eval_expr_as_value(expr)?;
         match *variable {
-            Variable::Identifier(_, ref name) => {
-                self.env.borrow_mut().declare(name, &val);
+            Variable::Identifier(ref binding_type, ref name, ref possible_constraint, address) => {
+                if let Some(ref constraint) = *possible_constraint {
+                    if !val.get_type().satisfies_constraint(constraint) {
+                        return Err((RuntimeError::BindingTypeError(name.clone(),
+                                                                   constraint.clone(),
+                                                                   val.get_type()),
+                                    expr.pos));
+                    }
+                }
+                self.declare(name,
+                             address,
+                             &val,
+                             binding_type.clone(),
+                             possible_constraint.clone());
+            }
+            Variable::Pattern(ref binding_type, ref pattern) => {
+                if let Some(name) = pattern.data.find_duplicate_binding() {
+                    return Err((RuntimeError::DuplicateBinding(name.clone()), pattern.pos));
+                }
+                let mut bindings = Vec::new();
+                if !match_pattern(&pattern.data, &val, &mut bindings) {
+                    return Err((RuntimeError::NoMatchingPattern(val.get_type()), expr.pos));
+                }
+                for (name, address, val) in bindings {
+                    self.declare(name, address, &val, binding_type.clone(), None);
+                }
             }
         };
         Ok(StmtResult::None)
     }
 
+    fn eval_stmt_struct_decl(&mut self,
+                             struct_decl: &StructDecl,
+                             struct_decl_stmt: &StmtNode)
+                             -> Result<StmtResult, RuntimeErrorWithPosition> {
+        let &StructDecl {
+                 ref name,
+                 ref fields,
+                 address,
+             } = struct_decl;
+        if let Some(field) = struct_decl.find_duplicate_field() {
+            return Err((RuntimeError::DuplicateField(name.clone(), field.clone()),
+                        struct_decl_stmt.pos));
+        }
+        let constructor = Function::StructConstructor {
+            call_sign: CallSign {
+                min_params: fields.len(),
+                max_params: Some(fields.len()),
+            },
+            name: name.clone(),
+            fields: fields.clone(),
+        };
+        self.declare(name,
+                     address,
+                     &Value::Function(Box::new(constructor)),
+                     BindingType::Mutable,
+                     None);
+        Ok(StmtResult::None)
+    }
+
     fn eval_stmt_assign(&mut self,
                         lhs_expr: &LhsExprNode,
                         expr: &ExprNode)
                         -> Result<StmtResult, RuntimeErrorWithPosition> {
         let val = self.eval_expr_as_value(expr)?;
+        self.assign_to_lhs_expr(lhs_expr, val)?;
+        Ok(StmtResult::None)
+    }
+
+    /// Gets the current value of the place that `lhs_expr` refers to
+    fn eval_lhs_expr(&mut self, lhs_expr: &LhsExprNode) -> Result<Value, RuntimeErrorWithPosition> {
         match lhs_expr.data {
-            LhsExpr::Identifier(ref id) => {
-                if !self.env.borrow_mut().set(id, val) {
-                    return Err((RuntimeError::UndeclaredAssignment(id.clone()), lhs_expr.pos));
+            LhsExpr::Identifier(ref id, address) => {
+                match self.get_variable(id, address) {
+                    Some(v) => Ok(v),
+                    None => Err((RuntimeError::ReferenceError(id.to_owned()), lhs_expr.pos)),
                 }
             }
-        };
-        Ok(StmtResult::None)
+            LhsExpr::Field(ref object_lhs_expr, ref field) => {
+                let object = self.eval_lhs_expr(object_lhs_expr)?;
+                get_field(&object, field).map_err(|e| (e, lhs_expr.pos))
+            }
+            LhsExpr::Index(ref object_lhs_expr, ref index_expr) => {
+                let object = self.eval_lhs_expr(object_lhs_expr)?;
+                let index = self.eval_expr_as_value(index_expr)?;
+                get_element(&object,
+                            &index,
+                            object_lhs_expr.pos,
+                            index_expr.pos,
+                            lhs_expr.pos)
+            }
+        }
+    }
+
+    /// Stores `val` in the place that `lhs_expr` refers to. Since values other
+    /// than lists are not shared, assigning to a part of them rebuilds the object
+    /// and assigns it back.
+    fn assign_to_lhs_expr(&mut self,
+                          lhs_expr: &LhsExprNode,
+                          val: Value)
+                          -> Result<(), RuntimeErrorWithPosition> {
+        match lhs_expr.data {
+            LhsExpr::Identifier(ref id, address) => {
+                if let Err(e) = self.set_variable(id, address, val) {
+                    return Err((e, lhs_expr.pos));
+                }
+                Ok(())
+            }
+            LhsExpr::Field(ref object_lhs_expr, ref field) => {
+                let mut object = self.eval_lhs_expr(object_lhs_expr)?;
+                if let Err(e) = set_field(&mut object, field, val) {
+                    return Err((e, lhs_expr.pos));
+                }
+                self.assign_to_lhs_expr(object_lhs_expr, object)
+            }
+            LhsExpr::Index(ref object_lhs_expr, ref index_expr) => {
+                let mut object = self.eval_lhs_expr(object_lhs_expr)?;
+                let index = self.eval_expr_as_value(index_expr)?;
+                match object {
+                    Value::List(ref l) => {
+                        let idx = to_element_index(&index,
+                                                   l.borrow().len(),
+                                                   index_expr.pos,
+                                                   lhs_expr.pos)?;
+                        l.borrow_mut()[idx] = val;
+                        // the list is shared, so there is nothing to assign back
+                        return Ok(());
+                    }
+                    Value::Map(ref mut m) => {
+                        match index {
+                            Value::String(key) => {
+                                m.insert(key, val);
+                            }
+                            non_string_index => {
+                                return Err((RuntimeError::NonStringMapKey(non_string_index
+                                                                              .get_type()),
+                                            index_expr.pos));
+                            }
+                        }
+                    }
+                    ref other => {
+                        return Err((RuntimeError::IndexAssignmentOnImmutable(other.get_type()),
+                                    object_lhs_expr.pos));
+                    }
+                }
+                self.assign_to_lhs_expr(object_lhs_expr, object)
+            }
+        }
     }
 
     fn eval_stmt_assign_with_op(&mut self,
@@ -159,49 +417,43 @@ impl AstWalkInterpreter {
                                 stmt: &StmtNode)
                                 -> Result<StmtResult, RuntimeErrorWithPosition> {
         let val = self.eval_expr_as_value(expr)?;
+        let prev_expr_val = self.eval_lhs_expr(lhs_expr)?;
+        let retval = operations::binary(op, prev_expr_val, val);
+        let new_val = match retval {
+            Ok(val) => val,
+            Err(e) => {
+                return Err((e, stmt.pos));
+            }
+        };
         match lhs_expr.data {
-            LhsExpr::Identifier(ref id) => {
-                let prev_expr_val = match self.env.borrow_mut().get_value(id) {
-                    Some(v) => v,
-                    None => {
-                        return Err((RuntimeError::ReferenceError(id.to_owned()), lhs_expr.pos));
-                    }
-                };
-                let retval = match *op {
-                    BinOp::Add => operations::add(prev_expr_val, val),
-                    BinOp::Sub => operations::subtract(prev_expr_val, val),
-                    BinOp::Mul => operations::multiply(prev_expr_val, val),
-                    BinOp::Div => operations::divide(prev_expr_val, val),
-                    BinOp::Mod => operations::modulo(prev_expr_val, val),
-                    BinOp::Lt | BinOp::Lte | BinOp::Gt | BinOp::Gte | BinOp::Eq => unreachable!(),
-                };
-                let new_val = match retval {
-                    Ok(val) => val,
-                    Err(e) => {
-                        return Err((e, stmt.pos));
-                    }
-                };
-                // id must exist, because it was checked above
-                self.env.borrow_mut().set(id, new_val);
+            LhsExpr::Identifier(ref id, address) => {
+                if let Err(e) = self.set_variable(id, address, new_val) {
+                    return Err((e, stmt.pos));
+                }
             }
+            LhsExpr::Field(..) |
+            LhsExpr::Index(..) => self.assign_to_lhs_expr(lhs_expr, new_val)?,
         };
         Ok(StmtResult::None)
     }
 
     fn eval_stmt_block(&mut self,
-                       statements: &[StmtNode])
+                       statements: &[StmtNode],
+                       num_slots: usize)
                        -> Result<StmtResult, RuntimeErrorWithPosition> {
-        let child_env = Environment::create_child(self.env.clone());
         let mut last_result = Ok(StmtResult::None);
-        let current_env = self.env.clone();
-        self.env = child_env;
+        let current_frame = self.frame.clone();
+        // a block that declares nothing needs no frame of its own
+        if num_slots > 0 {
+            self.frame = Some(Frame::create_child(&current_frame, num_slots));
+        }
         for statement in statements.iter() {
             last_result = self.eval_stmt(statement);
             if last_result.is_err() || last_result.clone().unwrap().is_block_terminating() {
                 break;
             }
         }
-        self.env = current_env;
+        self.frame = current_frame;
         last_result
     }
 
@@ -214,53 +466,127 @@ impl AstWalkInterpreter {
                  ref maybe_else_block,
              } = if_then_stmt;
         let val = self.eval_expr_as_value(cond)?;
+        // the result of the branch taken is the value of an `if` expression
         if val.is_truthy() {
-            let result = self.eval_stmt(then_block)?;
-            if let StmtResult::Break = result {
-                return Ok(StmtResult::Break);
-            } else if let StmtResult::Return(_) = result {
-                return Ok(result);
-            }
-        } else if maybe_else_block.is_some() {
-            let else_block = maybe_else_block.clone().unwrap();
-            let result = self.eval_stmt(&else_block)?;
-            if let StmtResult::Break = result {
-                return Ok(StmtResult::Break);
-            } else if let StmtResult::Return(_) = result {
-                return Ok(result);
-            }
+            self.eval_stmt(then_block)
+        } else if let Some(ref else_block) = *maybe_else_block {
+            self.eval_stmt(else_block)
+        } else {
+            Ok(StmtResult::None)
         }
-        Ok(StmtResult::None)
     }
 
-    fn eval_stmt_loop(&mut self, block: &StmtNode) -> Result<StmtResult, RuntimeErrorWithPosition> {
-        let old_in_loop = self.context.in_loop;
-        self.context.in_loop = true;
-        let mut last_result;
+    fn eval_stmt_loop(&mut self,
+                      label: &Option<String>,
+                      block: &StmtNode)
+                      -> Result<StmtResult, RuntimeErrorWithPosition> {
+        let old_context = self.enter_loop(label);
+        let last_result = loop {
+            match self.eval_stmt(block) {
+                Ok(result) => {
+                    if let Some(result) = result.after_loop_iteration(label) {
+                        break Ok(result);
+                    }
+                }
+                Err(e) => break Err(e),
+            }
+        };
+        self.context = old_context;
+        last_result
+    }
+
+    fn eval_stmt_while(&mut self,
+                       label: &Option<String>,
+                       cond: &ExprNode,
+                       block: &StmtNode)
+                       -> Result<StmtResult, RuntimeErrorWithPosition> {
+        let old_context = self.enter_loop(label);
+        let mut last_result = Ok(StmtResult::None);
         loop {
-            last_result = self.eval_stmt(block);
-            if last_result.is_err() {
-                break;
+            match self.eval_expr_as_value(cond) {
+                Ok(ref val) if val.is_truthy() => {}
+                Ok(_) => break,
+                Err(e) => {
+                    last_result = Err(e);
+                    break;
+                }
             }
-            match last_result {
-                Ok(StmtResult::None) |
-                Ok(StmtResult::Value(_)) => {}
-                Ok(StmtResult::Break) |
-                Ok(StmtResult::Return(_)) |
-                Err(_) => {
+            match self.eval_stmt(block) {
+                Ok(result) => {
+                    if let Some(result) = result.after_loop_iteration(label) {
+                        last_result = Ok(result);
+                        break;
+                    }
+                }
+                Err(e) => {
+                    last_result = Err(e);
                     break;
                 }
-                Ok(StmtResult::Continue) => {
-                    continue;
+            }
+        }
+        self.context = old_context;
+        last_result
+    }
+
+    fn eval_stmt_for_in(&mut self,
+                        label: &Option<String>,
+                        var_name: &str,
+                        iterable_expr: &ExprNode,
+                        block: &StmtNode)
+                        -> Result<StmtResult, RuntimeErrorWithPosition> {
+        let items: Box<dyn Iterator<Item = Value>> = match iterable_expr.data {
+            Expr::FnCall(ref f_expr, ref args, ref named_args) => {
+                let (func, args) =
+                    self.eval_callee_and_args(f_expr, args, named_args, iterable_expr)?;
+                if is_native_range(&func) {
+                    // count through the range instead of building the tuple
+                    let args: Vec<Value> = args.into_iter().flatten().collect();
+                    let range = Range::from_args(&args).map_err(|err| (err, iterable_expr.pos))?;
+                    Box::new(range.map(|i| Value::Number(Number::Integer(i))))
+                } else {
+                    let possible_val = self.call_evaluated_func(&func, args, iterable_expr)?;
+                    let iterable = possible_val.ok_or_else(|| none_error(iterable_expr))?;
+                    Box::new(get_items(iterable, iterable_expr)?.into_iter())
+                }
+            }
+            _ => {
+                let iterable = self.eval_expr_as_value(iterable_expr)?;
+                Box::new(get_items(iterable, iterable_expr)?.into_iter())
+            }
+        };
+        let old_context = self.enter_loop(label);
+        let mut last_result = Ok(StmtResult::None);
+        let current_frame = self.frame.clone();
+        for item in items {
+            // each iteration gets its own binding, so closures capture the current item
+            self.frame = Some(Frame::create_child(&current_frame, 1));
+            self.declare(var_name, VarAddress::Local(0, 0), &item, BindingType::Mutable, None);
+            match self.eval_stmt(block) {
+                Ok(result) => {
+                    if let Some(result) = result.after_loop_iteration(label) {
+                        last_result = Ok(result);
+                        break;
+                    }
+                }
+                Err(e) => {
+                    last_result = Err(e);
+                    break;
                 }
             }
         }
-        self.context.in_loop = old_in_loop;
-        if let Ok(StmtResult::Break) = last_result {
-            Ok(StmtResult::None)
-        } else {
-            last_result
+        self.frame = current_frame;
+        self.context = old_context;
+        last_result
+    }
+
+    // Updates the context for evaluating the body of a loop, returning the old context
+    fn enter_loop(&mut self, label: &Option<String>) -> Context {
+        let old_context = self.context.clone();
+        self.context.in_loop = true;
+        if let Some(ref label) = *label {
+            self.context.loop_labels.push(label.clone());
         }
+        old_context
     }
 
     fn eval_stmt_return(&mut self,
@@ -270,43 +596,281 @@ impl AstWalkInterpreter {
         if !self.context.in_func {
             return Err((RuntimeError::ReturnOutsideFunction, return_stmt.pos));
         }
-        match *possible_expr {
-            Some(ref expr) => {
-                let val = self.eval_expr_as_value(expr)?;
-                Ok(StmtResult::Return(Some(val)))
+        if let Some(ref expr) = *possible_expr {
+            if let Expr::FnCall(ref f_expr, ref args, ref named_args) = expr.data {
+                if !self.context.in_try {
+                    return self.eval_tail_call(f_expr, args, named_args, expr);
+                }
             }
-            None => Ok(StmtResult::Return(None)),
         }
+        let possible_val = match *possible_expr {
+            Some(ref expr) => Some(self.eval_expr_as_value(expr)?),
+            None => None,
+        };
+        if let Err(e) = check_return_constraint(&self.context.ret_constraint, &possible_val) {
+            return Err((e, return_stmt.pos));
+        }
+        Ok(StmtResult::Return(possible_val))
+    }
+
+    fn eval_stmt_try_catch(&mut self,
+                           try_block: &StmtNode,
+                           error_name: &str,
+                           catch_block: &StmtNode)
+                           -> Result<StmtResult, RuntimeErrorWithPosition> {
+        let old_in_try = self.context.in_try;
+        self.context.in_try = true;
+        let try_result = self.eval_stmt(try_block);
+        self.context.in_try = old_in_try;
+        let err = match try_result {
+            Err((err, _)) if err.is_catchable() => err,
+            result => return result,
+        };
+        let current_frame = self.frame.clone();
+        self.frame = Some(Frame::create_child(&current_frame, 1));
+        self.declare(error_name,
+                     VarAddress::Local(0, 0),
+                     &caught_error_value(err),
+                     BindingType::Mutable,
+                     None);
+        let result = self.eval_stmt(catch_block);
+        self.frame = current_frame;
+        result
+    }
+
+    fn eval_stmt_import(&mut self,
+                        import_path: &str,
+                        name: &str,
+                        import_stmt: &StmtNode)
+                        -> Result<StmtResult, RuntimeErrorWithPosition> {
+        let module_path = module::resolve_import(&self.file, import_path)
+            .map_err(|e| (e, import_stmt.pos))?;
+        let possible_module = self.modules.borrow().get(&module_path);
+        let module = match possible_module {
+            Some(module) => module,
+            None => {
+                self.modules
+                    .borrow_mut()
+                    .start_loading(&module_path)
+                    .map_err(|e| (e, import_stmt.pos))?;
+                let result = self.load_module(&module_path);
+                self.modules
+                    .borrow_mut()
+                    .finish_loading(&module_path, result.as_ref().ok().cloned());
+                result.map_err(|e| (e, import_stmt.pos))?
+            }
+        };
+        self.env
+            .borrow_mut()
+            .declare_binding(name, &module, BindingType::Immutable, None);
+        Ok(StmtResult::None)
+    }
+
+    fn load_module(&self, module_path: &ModulePath) -> Result<Value, RuntimeError> {
+        let ast = module::parse_module(module_path)?;
+        let module_env = Environment::create_child(Environment::new_root());
+        let mut machine = AstWalkInterpreter {
+            env: module_env.clone(),
+            frame: None,
+            context: Context::root(),
+            file: Some(module_path.display_path.clone()),
+            modules: self.modules.clone(),
+        };
+        if let Err(e) = machine.interpret_program(&ast) {
+            return Err(RuntimeError::InsideModule(module_path.display_path.clone(), Box::new(e)));
+        }
+        Ok(Value::Module(module_path.display_path.clone(), module_env))
     }
 
     fn eval_stmt_break(&mut self,
+                       label: &Option<String>,
+                       possible_expr: &Option<ExprNode>,
                        break_stmt: &StmtNode)
                        -> Result<StmtResult, RuntimeErrorWithPosition> {
         if !self.context.in_loop {
             return Err((RuntimeError::BreakOutsideLoop, break_stmt.pos));
         }
-        Ok(StmtResult::Break)
+        self.check_loop_label(label, break_stmt)?;
+        let possible_val = match *possible_expr {
+            Some(ref expr) => Some(self.eval_expr_as_value(expr)?),
+            None => None,
+        };
+        Ok(StmtResult::Break(label.clone(), possible_val))
     }
 
     fn eval_stmt_continue(&mut self,
+                          label: &Option<String>,
                           continue_stmt: &StmtNode)
                           -> Result<StmtResult, RuntimeErrorWithPosition> {
         if !self.context.in_loop {
             return Err((RuntimeError::ContinueOutsideLoop, continue_stmt.pos));
         }
-        Ok(StmtResult::Continue)
+        self.check_loop_label(label, continue_stmt)?;
+        Ok(StmtResult::Continue(label.clone()))
+    }
+
+    fn check_loop_label(&self,
+                        label: &Option<String>,
+                        stmt: &StmtNode)
+                        -> Result<(), RuntimeErrorWithPosition> {
+        match *label {
+            Some(ref label) if !self.context.loop_labels.contains(label) => {
+                Err((RuntimeError::UnknownLabel(label.clone()), stmt.pos))
+            }
+            _ => Ok(()),
+        }
+    }
+
+    fn eval_expr_try(&mut self,
+                     result_expr: &ExprNode,
+                     try_expr: &ExprNode)
+                     -> Result<Value, RuntimeErrorWithPosition> {
+        let val = self.eval_expr_as_value(result_expr)?;
+        match val {
+            Value::Result(Ok(ok_val)) => Ok(*ok_val),
+            Value::Result(Err(_)) => {
+                if !self.context.in_func {
+                    return Err((RuntimeError::ReturnOutsideFunction, try_expr.pos));
+                }
+                let possible_val = Some(val);
+                if let Err(e) = check_return_constraint(&self.context.ret_constraint,
+                                                        &possible_val) {
+                    return Err((e, try_expr.pos));
+                }
+                // the `Err` is returned from the enclosing function
+                Err((RuntimeError::Unwind(Box::new(StmtResult::Return(possible_val))),
+                     try_expr.pos))
+            }
+            other => Err((RuntimeError::TryOnNonResult(other.get_type()), result_expr.pos)),
+        }
+    }
+
+    fn eval_expr_match(&mut self,
+                       scrutinee: &ExprNode,
+                       arms: &[MatchArmNode])
+                       -> Result<Option<Value>, RuntimeErrorWithPosition> {
+        for arm in arms {
+            let pattern = &arm.data.pattern;
+            if let Some(name) = pattern.data.find_duplicate_binding() {
+                return Err((RuntimeError::DuplicateBinding(name.clone()), pattern.pos));
+            }
+        }
+        let val = self.eval_expr_as_value(scrutinee)?;
+        for arm in arms {
+            let mut bindings = Vec::new();
+            if !match_pattern(&arm.data.pattern.data, &val, &mut bindings) {
+                continue;
+            }
+            let current_frame = self.frame.clone();
+            if arm.data.num_slots > 0 {
+                self.frame = Some(Frame::create_child(&current_frame, arm.data.num_slots));
+            }
+            for (name, address, val) in bindings {
+                self.declare(name, address, &val, BindingType::Mutable, None);
+            }
+            let guard_result = match arm.data.guard {
+                Some(ref guard) => self.eval_expr_as_value(guard).map(|val| val.is_truthy()),
+                None => Ok(true),
+            };
+            let result = match guard_result {
+                Ok(true) => Some(self.eval_expr(&arm.data.body)),
+                Ok(false) => None,
+                Err(e) => Some(Err(e)),
+            };
+            self.frame = current_frame;
+            if let Some(result) = result {
+                return result;
+            }
+        }
+        Err((RuntimeError::NoMatchingPattern(val.get_type()), scrutinee.pos))
     }
 
     fn eval_expr_identifier(&mut self,
                             id: &str,
+                            address: VarAddress,
                             id_expr: &ExprNode)
                             -> Result<Value, RuntimeErrorWithPosition> {
-        match self.env.borrow_mut().get_value(id) {
+        match self.get_variable(id, address) {
             Some(v) => Ok(v),
             None => Err((RuntimeError::ReferenceError(id.to_owned()), id_expr.pos)),
         }
     }
 
+    // Gets the value of the variable at `address`, which is `None` while the
+    // variable is not declared yet
+    fn get_variable(&self, id: &str, address: VarAddress) -> Option<Value> {
+        match address {
+            VarAddress::Local(depth, slot) => {
+                self.frame
+                    .as_ref()
+                    .unwrap()
+                    .borrow()
+                    .get_value(depth, slot)
+            }
+            VarAddress::Global => self.env.borrow_mut().get_value(id),
+            VarAddress::Unresolved => {
+                let address = match self.find_in_frames(id) {
+                    Some((depth, slot)) => VarAddress::Local(depth, slot),
+                    None => VarAddress::Global,
+                };
+                self.get_variable(id, address)
+            }
+        }
+    }
+
+    fn set_variable(&self, id: &str, address: VarAddress, val: Value) -> Result<(), RuntimeError> {
+        match address {
+            VarAddress::Local(depth, slot) => {
+                self.frame
+                    .as_ref()
+                    .unwrap()
+                    .borrow_mut()
+                    .set(depth, slot, id, val)
+            }
+            VarAddress::Global => self.env.borrow_mut().set(id, val),
+            VarAddress::Unresolved => {
+                let address = match self.find_in_frames(id) {
+                    Some((depth, slot)) => VarAddress::Local(depth, slot),
+                    None => VarAddress::Global,
+                };
+                self.set_variable(id, address, val)
+            }
+        }
+    }
+
+    // Finds the innermost variable named `id` that is declared in a frame
+    fn find_in_frames(&self, id: &str) -> Option<(usize, usize)> {
+        match self.frame {
+            Some(ref frame) => frame.borrow().find(id),
+            None => None,
+        }
+    }
+
+    // Declares a variable at the address the resolver gave its declaration,
+    // which is in the innermost frame unless it is at the top level
+    fn declare(&mut self,
+               id: &str,
+               address: VarAddress,
+               val: &Value,
+               binding_type: BindingType,
+               constraint: Option<ConstraintType>) {
+        match address {
+            VarAddress::Local(_, slot) => {
+                self.frame
+                    .as_ref()
+                    .unwrap()
+                    .borrow_mut()
+                    .declare_binding(slot, id, val, binding_type, constraint)
+            }
+            VarAddress::Global => {
+                self.env
+                    .borrow_mut()
+                    .declare_binding(id, val, binding_type, constraint)
+            }
+            VarAddress::Unresolved => unreachable!(),
+        }
+    }
+
     fn eval_expr_tuple(&mut self, elems: &[ExprNode]) -> Result<Value, RuntimeErrorWithPosition> {
         let mut values = Vec::new();
         for elem_expr in elems {
@@ -316,6 +880,26 @@ impl AstWalkInterpreter {
         Ok(Value::Tuple(values))
     }
 
+    fn eval_expr_list(&mut self, elems: &[ExprNode]) -> Result<Value, RuntimeErrorWithPosition> {
+        let mut values = Vec::new();
+        for elem_expr in elems {
+            let val = self.eval_expr_as_value(elem_expr)?;
+            values.push(val);
+        }
+        Ok(Value::List(Rc::new(RefCell::new(values))))
+    }
+
+    fn eval_expr_map(&mut self,
+                     entries: &[(String, ExprNode)])
+                     -> Result<Value, RuntimeErrorWithPosition> {
+        let mut map = LinearMap::new();
+        for &(ref key, ref value_expr) in entries {
+            let val = self.eval_expr_as_value(value_expr)?;
+            map.insert(key.clone(), val);
+        }
+        Ok(Value::Map(map))
+    }
+
     fn eval_expr_unary(&mut self,
                        op: &UnOp,
                        expr: &ExprNode,
@@ -350,18 +934,7 @@ impl AstWalkInterpreter {
                         -> Result<Value, RuntimeErrorWithPosition> {
         let val1 = self.eval_expr_as_value(expr1)?;
         let val2 = self.eval_expr_as_value(expr2)?;
-        let retval = match *op {
-            BinOp::Add => operations::add(val1, val2),
-            BinOp::Sub => operations::subtract(val1, val2),
-            BinOp::Mul => operations::multiply(val1, val2),
-            BinOp::Div => operations::divide(val1, val2),
-            BinOp::Mod => operations::modulo(val1, val2),
-            BinOp::Lt => operations::less_than(val1, val2),
-            BinOp::Lte => operations::less_than_or_equal(val1, val2),
-            BinOp::Gt => operations::greater_than(val1, val2),
-            BinOp::Gte => operations::greater_than_or_equal(val1, val2),
-            BinOp::Eq => Ok(Value::Bool(val1 == val2)),
-        };
+        let retval = operations::binary(op, val1, val2);
         match retval {
             Ok(v) => Ok(v),
             Err(err) => Err((err, binary_expr.pos)),
@@ -400,66 +973,41 @@ impl AstWalkInterpreter {
                                -> Result<Value, RuntimeErrorWithPosition> {
         let object = self.eval_expr_as_value(object_expr)?;
         let index = self.eval_expr_as_value(index_expr)?;
-        match object {
-            Value::Tuple(ref v) => {
-                match index {
-                    Value::Number(n) => {
-                        let idx;
-                        if let Number::Float(f) = n {
-                            if f.fract() == 0.0 {
-                                idx = f.trunc() as i64;
-                            } else {
-                                return Err((RuntimeError::NonIntegralSubscript(Type::Number),
-                                            index_expr.pos));
-                            }
-                        } else if let Number::Integer(i) = n {
-                            idx = i;
-                        } else {
-                            unreachable!();
-                        }
-                        if idx < 0 {
-                            return Err((RuntimeError::IndexOutOfBounds(idx),
-                                        member_access_expr.pos));
-                        }
-                        match v.get(idx as usize) {
-                            Some(x) => Ok(x.clone()),
-                            None => {
-                                Err((RuntimeError::IndexOutOfBounds(idx), member_access_expr.pos))
-                            }
-                        }
-                    }
-                    non_num_index => {
-                        Err((RuntimeError::NonIntegralSubscript(non_num_index.get_type()),
-                             index_expr.pos))
-                    }
-                }
-            }
-            obj => {
-                Err((RuntimeError::SubscriptOnNonSubscriptable(obj.get_type()), object_expr.pos))
-            }
-        }
+        get_element(&object,
+                    &index,
+                    object_expr.pos,
+                    index_expr.pos,
+                    member_access_expr.pos)
     }
 
     fn eval_expr_fn_def(&mut self,
-                        fn_def_expr: &FnDefExpr)
+                        fn_def_expr: &FnDefExpr,
+                        fn_expr: &ExprNode)
                         -> Result<Value, RuntimeErrorWithPosition> {
         let &FnDefExpr {
                  ref maybe_id,
                  ref params,
+                 ref ret_constraint,
                  ref body,
+                 address,
+                 num_slots,
              } = fn_def_expr;
+        if let Some(param) = fn_def_expr.find_required_param_after_default() {
+            return Err((RuntimeError::RequiredParameterAfterDefault(param.clone()), fn_expr.pos));
+        }
         let func = Function::User {
-            call_sign: CallSign {
-                num_params: params.len(),
-                variadic: false,
-            },
-            param_names: params.clone(),
+            call_sign: CallSign::from_params(params),
+            maybe_id: maybe_id.clone(),
+            params: params.clone(),
+            ret_constraint: ret_constraint.clone(),
             body: body.clone(),
+            num_slots: num_slots,
             env: self.env.clone(),
+            frame: self.frame.clone(),
         };
         let func_val = Value::Function(Box::new(func));
         if let Some(ref id) = *maybe_id {
-            self.env.borrow_mut().declare(id, &func_val);
+            self.declare(id, address, &func_val, BindingType::Mutable, None);
         }
         Ok(func_val)
     }
@@ -467,17 +1015,66 @@ impl AstWalkInterpreter {
     fn eval_expr_fn_call(&mut self,
                          expr: &ExprNode,
                          args: &[ExprNode],
+                         named_args: &[(String, ExprNode)],
                          fn_call_expr: &ExprNode)
                          -> Result<Option<Value>, RuntimeErrorWithPosition> {
+        let (func, args) = self.eval_callee_and_args(expr, args, named_args, fn_call_expr)?;
+        self.call_evaluated_func(&func, args, fn_call_expr)
+    }
+
+    // Calls a function whose callee and arguments are already evaluated
+    fn call_evaluated_func(&mut self,
+                           func: &Function,
+                           args: Vec<Option<Value>>,
+                           fn_call_expr: &ExprNode)
+                           -> Result<Option<Value>, RuntimeErrorWithPosition> {
+        if self.context.call_depth >= self.context.max_call_depth {
+            return Err((RuntimeError::StackOverflow(self.context.max_call_depth),
+                        fn_call_expr.pos));
+        }
+        let call_func_result = call_func_with_args(func, args, &self.context);
+        match call_func_result {
+            Ok(possible_val) => Ok(possible_val),
+            Err(runtime_error) => Err((runtime_error, fn_call_expr.pos)),
+        }
+    }
+
+    // Evaluates `return f(args);`. Calls of user functions are left to the
+    // caller of the current function, which makes them in place of the current
+    // call, so that tail recursion does not grow the stack.
+    fn eval_tail_call(&mut self,
+                      expr: &ExprNode,
+                      args: &[ExprNode],
+                      named_args: &[(String, ExprNode)],
+                      fn_call_expr: &ExprNode)
+                      -> Result<StmtResult, RuntimeErrorWithPosition> {
+        let (func, args) = self.eval_callee_and_args(expr, args, named_args, fn_call_expr)?;
+        if let Function::User { .. } = *func {
+            return Ok(StmtResult::TailCall(Value::Function(func), args));
+        }
+        let possible_val = call_func_with_args(&func, args, &self.context)
+            .map_err(|err| (err, fn_call_expr.pos))?;
+        if let Err(e) = check_return_constraint(&self.context.ret_constraint, &possible_val) {
+            return Err((e, fn_call_expr.pos));
+        }
+        Ok(StmtResult::Return(possible_val))
+    }
+
+    // Evaluates the function and the arguments of a call, with the arguments in
+    // the order of the parameters they are given for
+    fn eval_callee_and_args(&mut self,
+                            expr: &ExprNode,
+                            args: &[ExprNode],
+                            named_args: &[(String, ExprNode)],
+                            fn_call_expr: &ExprNode)
+                            -> Result<(Box<Function>, Vec<Option<Value>>),
+                                      RuntimeErrorWithPosition> {
         let val = self.eval_expr_as_value(expr)?;
         let func = match val {
             Value::Function(f) => f,
             v => {
-                if let Expr::Identifier(ref id) = expr.data {
-                    return Err((RuntimeError::CallToNonFunction(Some(id.clone()), v.get_type()),
-                                expr.pos));
-                }
-                return Err((RuntimeError::CallToNonFunction(None, v.get_type()), expr.pos));
+                return Err((RuntimeError::CallToNonFunction(get_fn_name(expr), v.get_type()),
+                            expr.pos));
             }
         };
         let mut arg_vals = Vec::new();
@@ -485,56 +1082,279 @@ impl AstWalkInterpreter {
             let val = self.eval_expr_as_value(arg)?;
             arg_vals.push(val);
         }
+        let mut named_arg_vals = Vec::new();
+        for &(ref name, ref arg) in named_args.iter() {
+            let val = self.eval_expr_as_value(arg)?;
+            named_arg_vals.push((name.clone(), val));
+        }
 
         let call_sign = func.get_call_sign();
-        check_args_compat(&arg_vals, &call_sign, expr, fn_call_expr)?;
+        check_args_compat(arg_vals.len() + named_arg_vals.len(),
+                          &call_sign,
+                          expr,
+                          fn_call_expr)?;
+        let args = arrange_args(&func, arg_vals, named_arg_vals, expr)
+            .map_err(|err| (err, fn_call_expr.pos))?;
+        Ok((func, args))
+    }
+}
 
-        let call_func_result = call_func(&func, &arg_vals);
-        match call_func_result {
-            Ok(possible_val) => Ok(possible_val),
-            Err(runtime_error) => Err((runtime_error, fn_call_expr.pos)),
+// The error for an expression that gives no value where one is needed
+fn none_error(expr: &ExprNode) -> RuntimeErrorWithPosition {
+    if let Expr::FnCall(ref f_expr, ..) = expr.data {
+        if let Expr::Identifier(ref id, _) = f_expr.data {
+            return (RuntimeError::NoneError(Some(id.clone())), expr.pos);
         }
     }
+    // a call to an anonymous function, or a loop broken out of without a value
+    (RuntimeError::NoneError(None), expr.pos)
+}
+
+// The items that `for ... in` goes through
+fn get_items(iterable: Value,
+             iterable_expr: &ExprNode)
+             -> Result<Vec<Value>, RuntimeErrorWithPosition> {
+    match iterable {
+        Value::Tuple(v) => Ok(v),
+        // iterate over the elements the list had when the loop started
+        Value::List(l) => Ok(l.borrow().clone()),
+        Value::String(s) => Ok(s.chars().map(|c| Value::String(c.to_string())).collect()),
+        other => Err((RuntimeError::NonIterable(other.get_type()), iterable_expr.pos)),
+    }
 }
 
 pub fn call_func(func: &Function, arg_vals: &[Value]) -> Result<Option<Value>, RuntimeError> {
+    call_func_with_args(func,
+                        arg_vals.iter().cloned().map(Some).collect(),
+                        &Context::root())
+}
+
+/// Calls `func` with an argument for every parameter in order, or `None` for
+/// the parameters that get their default value, from code that is already
+/// evaluating `call_depth` nested function calls
+pub fn call_func_with_arranged_args(func: &Function,
+                                    args: Vec<Option<Value>>,
+                                    call_depth: usize,
+                                    max_call_depth: usize)
+                                    -> Result<Option<Value>, RuntimeError> {
+    let caller = Context {
+        call_depth: call_depth,
+        max_call_depth: max_call_depth,
+        ..Context::root()
+    };
+    call_func_with_args(func, args, &caller)
+}
+
+// Puts every named argument at the position of the parameter with its name.
+// Parameters that are given no argument are left as `None`.
+fn arrange_args(func: &Function,
+                arg_vals: Vec<Value>,
+                named_arg_vals: Vec<(String, Value)>,
+                expr: &ExprNode)
+                -> Result<Vec<Option<Value>>, RuntimeError> {
+    let mut args: Vec<Option<Value>> = arg_vals.into_iter().map(Some).collect();
+    if named_arg_vals.is_empty() {
+        return Ok(args);
+    }
+    let param_names = func.get_param_names();
+    for (name, val) in named_arg_vals {
+        let idx = match param_names.iter().position(|param| *param == name) {
+            Some(idx) => idx,
+            None => return Err(RuntimeError::UnknownParameter(get_fn_name(expr), name)),
+        };
+        if idx >= args.len() {
+            args.resize(idx + 1, None);
+        }
+        if args[idx].is_some() {
+            return Err(RuntimeError::DuplicateArgument(name));
+        }
+        args[idx] = Some(val);
+    }
+    Ok(args)
+}
+
+/// Records that an error at `pos` occurred inside the function `maybe_id`, below
+/// the calls it was made from
+pub fn add_stack_frame(maybe_id: &Option<String>,
+                       error_with_position: RuntimeErrorWithPosition)
+                       -> RuntimeError {
+    let (err, pos) = error_with_position;
+    let frame = StackFrame {
+        fn_name: maybe_id
+            .clone()
+            .unwrap_or_else(|| "<anonymous>".to_owned()),
+        pos: pos,
+    };
+    match err {
+        // the error occurred in a call made by the function, at `pos`
+        RuntimeError::InsideFunctionCall(err, mut frames) => {
+            frames.push(frame);
+            RuntimeError::InsideFunctionCall(err, frames)
+        }
+        err => RuntimeError::InsideFunctionCall(Box::new(err), vec![frame]),
+    }
+}
+
+fn eval_default_arg(env: &Rc<RefCell<Environment>>,
+                    function_frame: &Option<Rc<RefCell<Frame>>>,
+                    default: &ExprNode,
+                    caller: &Context)
+                    -> Result<Value, RuntimeErrorWithPosition> {
+    let mut machine = AstWalkInterpreter::with_environment_and_context(env.clone(),
+                                                                      function_frame.clone(),
+                                                                      caller.clone());
+    machine.eval_expr_as_value(default)
+}
+
+// `args` holds an argument for every parameter in order, or `None` for the
+// parameters that get their default value. The tail calls the function ends
+// with are made here in turn, instead of inside one another.
+fn call_func_with_args(func: &Function,
+                       args: Vec<Option<Value>>,
+                       caller: &Context)
+                       -> Result<Option<Value>, RuntimeError> {
+    let mut result = call_func_once(func, args, caller)?;
+    let mut ret_constraint = get_ret_constraint(func);
+    // the return annotations of the functions that were replaced by a tail
+    // call, which the value returned in the end has to satisfy
+    let mut pending_ret_constraints: Vec<ReturnConstraint> = Vec::new();
+    loop {
+        match result {
+            StmtResult::TailCall(Value::Function(next_func), next_args) => {
+                if let Some(constraint) = ret_constraint {
+                    if pending_ret_constraints.last() != Some(&constraint) {
+                        pending_ret_constraints.push(constraint);
+                    }
+                }
+                result = call_func_once(&next_func, next_args, caller)?;
+                ret_constraint = get_ret_constraint(&next_func);
+            }
+            StmtResult::Return(possible_val) => {
+                for constraint in pending_ret_constraints {
+                    check_return_constraint(&Some(constraint), &possible_val)?;
+                }
+                return Ok(possible_val);
+            }
+            _ => unreachable!(),
+        }
+    }
+}
+
+fn get_ret_constraint(func: &Function) -> Option<ReturnConstraint> {
+    match *func {
+        Function::User { ref ret_constraint, .. } => ret_constraint.clone(),
+        _ => None,
+    }
+}
+
+// Returns the value of the call as `StmtResult::Return`, or the tail call the
+// function ends with as `StmtResult::TailCall`
+fn call_func_once(func: &Function,
+                  args: Vec<Option<Value>>,
+                  caller: &Context)
+                  -> Result<StmtResult, RuntimeError> {
     match *func {
         Function::NativeVoid(_, ref native_fn) => {
-            native_fn(arg_vals.to_vec())?;
-            Ok(None)
+            native_fn(args.into_iter().flatten().collect())?;
+            Ok(StmtResult::Return(None))
+        }
+        Function::NativeReturning(_, ref native_fn) => {
+            Ok(StmtResult::Return(Some(native_fn(args.into_iter().flatten().collect())?)))
+        }
+        Function::StructConstructor {
+            ref name,
+            ref fields,
+            ..
+        } => {
+            let mut field_vals = LinearMap::new();
+            for (field, arg) in fields.iter().zip(args) {
+                match arg {
+                    Some(val) => field_vals.insert(field.clone(), val),
+                    None => return Err(RuntimeError::MissingArgument(field.clone())),
+                };
+            }
+            Ok(StmtResult::Return(Some(Value::Struct(name.clone(), field_vals))))
+        }
+        Function::Compiled(ref closure) => {
+            let result = bytecode::call_closure(closure,
+                                                args,
+                                                caller.call_depth + 1,
+                                                caller.max_call_depth)?;
+            Ok(StmtResult::Return(result))
         }
-        Function::NativeReturning(_, ref native_fn) => Ok(Some(native_fn(arg_vals.to_vec())?)),
         Function::User {
-            ref param_names,
+            ref maybe_id,
+            ref params,
+            ref ret_constraint,
             ref body,
+            num_slots,
             ref env,
+            ref frame,
             ..
         } => {
-            // TODO: returning
-            let function_env = Environment::create_child(env.clone());
-            for (param, arg) in param_names.iter().zip(arg_vals.iter()) {
-                function_env.borrow_mut().declare(param, arg);
-            }
-            let inner_env = Environment::create_child(function_env);
-            let fn_context = Context {
-                in_func: true,
-                in_loop: false,
+            // the parameters are declared in a frame of their own, in the slots
+            // of their positions
+            let function_frame = if num_slots > 0 {
+                Some(Frame::create_child(frame, num_slots))
+            } else {
+                frame.clone()
             };
-            let mut machine = AstWalkInterpreter::with_environment_and_context(inner_env,
+            let fn_context = caller.for_call(ret_constraint);
+            let mut args = args.into_iter();
+            for (slot, param) in params.iter().enumerate() {
+                let arg = if param.is_rest {
+                    Value::Tuple(args.by_ref().flatten().collect())
+                } else {
+                    match (args.next(), &param.default) {
+                        (Some(Some(val)), _) => val,
+                        // defaults are evaluated on every call, and can refer to
+                        // the parameters before them
+                        (_, &Some(ref default)) => {
+                            match eval_default_arg(env, &function_frame, default, &fn_context) {
+                                Ok(val) => val,
+                                // a `return` in the default, like the one `?`
+                                // makes, returns from the function
+                                Err((RuntimeError::Unwind(result), _)) => return Ok(*result),
+                                Err(err) => return Err(add_stack_frame(maybe_id, err)),
+                            }
+                        }
+                        (_, &None) => {
+                            return Err(RuntimeError::MissingArgument(param.name.clone()));
+                        }
+                    }
+                };
+                if let Some(ref constraint) = param.constraint {
+                    if !arg.get_type().satisfies_constraint(constraint) {
+                        return Err(RuntimeError::BindingTypeError(param.name.clone(),
+                                                                  constraint.clone(),
+                                                                  arg.get_type()));
+                    }
+                }
+                function_frame
+                    .as_ref()
+                    .unwrap()
+                    .borrow_mut()
+                    .declare_binding(slot,
+                                     &param.name,
+                                     &arg,
+                                     BindingType::Mutable,
+                                     param.constraint.clone());
+            }
+            let mut machine = AstWalkInterpreter::with_environment_and_context(env.clone(),
+                                                                               function_frame,
                                                                                fn_context);
             let result = machine.eval_stmt(body);
             match result {
-                Err(error_with_position) => {
-                    Err(RuntimeError::InsideFunctionCall(Box::new(error_with_position)))
-                }
+                Err(error_with_position) => Err(add_stack_frame(maybe_id, error_with_position)),
                 Ok(statement_result) => {
-                    if let StmtResult::Return(possible_val) = statement_result {
-                        match possible_val {
-                            Some(val) => Ok(Some(val)),
-                            None => Ok(None),
+                    match statement_result {
+                        StmtResult::Return(_) |
+                        StmtResult::TailCall(..) => Ok(statement_result),
+                        _ => {
+                            // the function body ended without a return statement
+                            check_return_constraint(ret_constraint, &None)?;
+                            Ok(StmtResult::Return(None))
                         }
-                    } else {
-                        Ok(None)
                     }
                 }
             }
@@ -542,20 +1362,202 @@ pub fn call_func(func: &Function, arg_vals: &[Value]) -> Result<Option<Value>, R
     }
 }
 
-fn check_args_compat(arg_vals: &[Value],
+/// The value `catch` binds for an error: the thrown value for `throw`, and an
+/// `Error` struct with the kind and message of the error otherwise
+pub fn caught_error_value(err: RuntimeError) -> Value {
+    match err {
+        RuntimeError::InsideFunctionCall(err, _) => caught_error_value(*err),
+        RuntimeError::InsideModule(_, error_with_position) => {
+            caught_error_value(error_with_position.0)
+        }
+        RuntimeError::Thrown(val) => val,
+        err => {
+            let (_, message) = describe_runtime_error(&err);
+            let mut fields = LinearMap::new();
+            fields.insert("kind".to_owned(), Value::String(err.kind().to_owned()));
+            fields.insert("message".to_owned(), Value::String(message));
+            Value::Struct("Error".to_owned(), fields)
+        }
+    }
+}
+
+/// Checks whether `val` matches `pattern`, collecting the values bound by it
+/// with the names and addresses of their variables
+pub fn match_pattern<'a>(pattern: &'a Pattern,
+                         val: &Value,
+                         bindings: &mut Vec<(&'a str, VarAddress, Value)>)
+                         -> bool {
+    match *pattern {
+        Pattern::Wildcard => true,
+        Pattern::Binding(ref name, address) => {
+            bindings.push((name, address, val.clone()));
+            true
+        }
+        Pattern::Literal(ref literal) => Value::from(literal.clone()) == *val,
+        Pattern::Tuple(ref patterns) => {
+            match *val {
+                Value::Tuple(ref elems) if elems.len() == patterns.len() => {
+                    patterns
+                        .iter()
+                        .zip(elems.iter())
+                        .all(|(pattern, elem)| match_pattern(&pattern.data, elem, bindings))
+                }
+                _ => false,
+            }
+        }
+    }
+}
+
+// The value of a loop, `if` or block used as an expression
+fn expr_value_of_stmt_result(result: StmtResult,
+                             expr: &ExprNode)
+                             -> Result<Option<Value>, RuntimeErrorWithPosition> {
+    match result {
+        StmtResult::Value(val) => Ok(Some(val)),
+        StmtResult::None => Ok(None),
+        // a `return`, or a `break` or `continue` for an enclosing loop
+        result => Err((RuntimeError::Unwind(Box::new(result)), expr.pos)),
+    }
+}
+
+// A loop statement's value, given by `break expr`, goes unused
+fn discard_loop_value(result: StmtResult) -> StmtResult {
+    match result {
+        StmtResult::Value(_) => StmtResult::None,
+        result => result,
+    }
+}
+
+pub fn get_element(object: &Value,
+                   index: &Value,
+                   object_pos: OffsetSpan,
+                   index_pos: OffsetSpan,
+                   access_pos: OffsetSpan)
+                   -> Result<Value, RuntimeErrorWithPosition> {
+    match *object {
+        Value::Tuple(ref v) => {
+            let idx = to_element_index(index, v.len(), index_pos, access_pos)?;
+            Ok(v[idx].clone())
+        }
+        Value::List(ref l) => {
+            let l = l.borrow();
+            let idx = to_element_index(index, l.len(), index_pos, access_pos)?;
+            Ok(l[idx].clone())
+        }
+        // indexing a string gives the char at that index, as a string
+        Value::String(ref s) => {
+            let idx = to_element_index(index, s.chars().count(), index_pos, access_pos)?;
+            Ok(Value::String(s.chars().nth(idx).unwrap().to_string()))
+        }
+        Value::Map(ref m) => {
+            match *index {
+                Value::String(ref key) => {
+                    match m.get(key) {
+                        Some(x) => Ok(x.clone()),
+                        None => Err((RuntimeError::KeyNotFound(key.clone()), access_pos)),
+                    }
+                }
+                ref non_string_index => {
+                    Err((RuntimeError::NonStringMapKey(non_string_index.get_type()), index_pos))
+                }
+            }
+        }
+        ref obj => Err((RuntimeError::SubscriptOnNonSubscriptable(obj.get_type()), object_pos)),
+    }
+}
+
+pub fn to_element_index(index: &Value,
+                        len: usize,
+                        index_pos: OffsetSpan,
+                        access_pos: OffsetSpan)
+                        -> Result<usize, RuntimeErrorWithPosition> {
+    let idx = match index.as_index() {
+        Some(idx) => idx,
+        None => return Err((RuntimeError::NonIntegralSubscript(index.get_type()), index_pos)),
+    };
+    if idx < 0 || idx as usize >= len {
+        return Err((RuntimeError::IndexOutOfBounds(idx), access_pos));
+    }
+    Ok(idx as usize)
+}
+
+pub fn get_field(object: &Value, field: &str) -> Result<Value, RuntimeError> {
+    match *object {
+        Value::Struct(ref name, ref fields) => {
+            match fields.get(field) {
+                Some(val) => Ok(val.clone()),
+                None => Err(RuntimeError::UnknownField(name.clone(), field.to_owned())),
+            }
+        }
+        Value::Module(ref path, ref env) => {
+            match env.borrow().get_own_value(field) {
+                Some(val) => Ok(val),
+                None => Err(RuntimeError::UnknownModuleMember(path.clone(), field.to_owned())),
+            }
+        }
+        ref other => Err(RuntimeError::FieldAccessOnNonStruct(other.get_type())),
+    }
+}
+
+pub fn set_field(object: &mut Value, field: &str, val: Value) -> Result<(), RuntimeError> {
+    match *object {
+        Value::Struct(ref name, ref mut fields) => {
+            match fields.get_mut(field) {
+                Some(field_val) => {
+                    *field_val = val;
+                    Ok(())
+                }
+                None => Err(RuntimeError::UnknownField(name.clone(), field.to_owned())),
+            }
+        }
+        // the bindings of a module can only be changed by the module itself
+        Value::Module(ref path, _) => {
+            Err(RuntimeError::ModuleMemberAssignment(path.clone(), field.to_owned()))
+        }
+        ref other => Err(RuntimeError::FieldAccessOnNonStruct(other.get_type())),
+    }
+}
+
+pub fn check_return_constraint(possible_constraint: &Option<ReturnConstraint>,
+                               possible_val: &Option<Value>)
+                               -> Result<(), RuntimeError> {
+    let constraint = match *possible_constraint {
+        None => return Ok(()),
+        Some(ref constraint) => constraint,
+    };
+    let is_satisfied = match (constraint, possible_val) {
+        (&ReturnConstraint::Void, &None) => true,
+        (&ReturnConstraint::Void, &Some(_)) |
+        (&ReturnConstraint::Type(_), &None) => false,
+        (&ReturnConstraint::Type(ref c), &Some(ref val)) => val.get_type().satisfies_constraint(c),
+    };
+    if is_satisfied {
+        Ok(())
+    } else {
+        Err(RuntimeError::ReturnTypeError(constraint.clone(),
+                                          possible_val.as_ref().map(|val| val.get_type())))
+    }
+}
+
+fn check_args_compat(num_args: usize,
                      call_sign: &CallSign,
                      expr: &ExprNode,
                      full_expr: &ExprNode)
                      -> Result<(), RuntimeErrorWithPosition> {
-    if !call_sign.variadic && call_sign.num_params != arg_vals.len() {
-        if let Expr::Identifier(ref id) = expr.data {
-            return Err((RuntimeError::ArgumentLength(Some(id.clone())), full_expr.pos));
-        }
-        return Err((RuntimeError::ArgumentLength(None), full_expr.pos));
+    if !call_sign.accepts(num_args) {
+        return Err((RuntimeError::ArgumentLength(get_fn_name(expr)), full_expr.pos));
     }
     Ok(())
 }
 
+/// The name of the function called through `expr`, if it is called by name
+pub fn get_fn_name(expr: &ExprNode) -> Option<String> {
+    match expr.data {
+        Expr::Identifier(ref id, _) => Some(id.clone()),
+        _ => None,
+    }
+}
+
 fn wrap(result: Result<Value, RuntimeErrorWithPosition>)
         -> Result<Option<Value>, RuntimeErrorWithPosition> {
     match result {
@@ -568,7 +1570,7 @@ impl Interpreter for AstWalkInterpreter {
     fn run_ast_as_statements(&mut self,
                              statements: &[StmtNode])
                              -> Result<Option<StmtResult>, RuntimeErrorWithPosition> {
-        self.eval_stmts(statements)
+        self.interpret_statements(statements)
     }
 
     fn run_ast_as_program(&mut self,
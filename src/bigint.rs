@@ -0,0 +1,387 @@
+use std::cmp::Ordering;
+use std::fmt;
+use std::str::FromStr;
+
+// Each limb holds 9 decimal digits, which keeps parsing and printing simple
+const BASE: u64 = 1_000_000_000;
+const DIGITS_PER_LIMB: usize = 9;
+// Bitwise operators work on the two's complement in base 2^32 words
+const WORD: u64 = 1 << 32;
+
+/// An arbitrary-precision integer, used for integers that do not fit in an `i64`
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct BigInt {
+    negative: bool,
+    // the limbs of the absolute value, least significant first, without
+    // trailing zero limbs, so that zero has no limbs
+    magnitude: Vec<u32>,
+}
+
+impl BigInt {
+    fn from_parts(negative: bool, magnitude: Vec<u32>) -> BigInt {
+        let mut magnitude = magnitude;
+        while magnitude.last() == Some(&0) {
+            magnitude.pop();
+        }
+        BigInt {
+            negative: negative && !magnitude.is_empty(),
+            magnitude: magnitude,
+        }
+    }
+
+    /// The exact value of a float without a fractional part
+    pub fn from_f64(f: f64) -> Option<BigInt> {
+        if !f.is_finite() || f.fract() != 0.0 {
+            return None;
+        }
+        if f.abs() < 9.2e18 {
+            return Some(BigInt::from(f as i64));
+        }
+        // large floats are a 53 bit mantissa shifted left by a positive exponent
+        let bits = f.to_bits();
+        let mut exponent = ((bits >> 52) & 0x7ff) as i64 - 1075;
+        let mantissa = (bits & ((1 << 52) - 1)) | (1 << 52);
+        let mut magnitude = BigInt::from(mantissa as i64).magnitude;
+        while exponent > 0 {
+            let shift = if exponent > 20 { 20 } else { exponent };
+            magnitude = mul_small(&magnitude, 1 << shift);
+            exponent -= shift;
+        }
+        Some(BigInt::from_parts(f < 0.0, magnitude))
+    }
+
+    pub fn to_i64(&self) -> Option<i64> {
+        let mut value: i64 = 0;
+        for &limb in self.magnitude.iter().rev() {
+            value = value.checked_mul(BASE as i64)?;
+            value = if self.negative {
+                value.checked_sub(limb as i64)?
+            } else {
+                value.checked_add(limb as i64)?
+            };
+        }
+        Some(value)
+    }
+
+    pub fn to_f64(&self) -> f64 {
+        let mut value = 0.0;
+        for &limb in self.magnitude.iter().rev() {
+            value = value * (BASE as f64) + (limb as f64);
+        }
+        if self.negative { -value } else { value }
+    }
+
+    pub fn is_zero(&self) -> bool {
+        self.magnitude.is_empty()
+    }
+
+    pub fn is_negative(&self) -> bool {
+        self.negative
+    }
+
+    pub fn abs(&self) -> BigInt {
+        BigInt::from_parts(false, self.magnitude.clone())
+    }
+
+    pub fn neg(&self) -> BigInt {
+        BigInt::from_parts(!self.negative, self.magnitude.clone())
+    }
+
+    pub fn add(&self, other: &BigInt) -> BigInt {
+        if self.negative == other.negative {
+            return BigInt::from_parts(self.negative,
+                                      add_magnitudes(&self.magnitude, &other.magnitude));
+        }
+        // the signs differ, so subtract the smaller magnitude from the larger one
+        match compare_magnitudes(&self.magnitude, &other.magnitude) {
+            Ordering::Less => {
+                BigInt::from_parts(other.negative,
+                                   sub_magnitudes(&other.magnitude, &self.magnitude))
+            }
+            _ => {
+                BigInt::from_parts(self.negative,
+                                   sub_magnitudes(&self.magnitude, &other.magnitude))
+            }
+        }
+    }
+
+    pub fn sub(&self, other: &BigInt) -> BigInt {
+        self.add(&other.neg())
+    }
+
+    pub fn mul(&self, other: &BigInt) -> BigInt {
+        let mut result = vec![0u64; self.magnitude.len() + other.magnitude.len()];
+        for (i, &a) in self.magnitude.iter().enumerate() {
+            let mut carry = 0;
+            for (j, &b) in other.magnitude.iter().enumerate() {
+                let current = result[i + j] + (a as u64) * (b as u64) + carry;
+                result[i + j] = current % BASE;
+                carry = current / BASE;
+            }
+            result[i + other.magnitude.len()] += carry;
+        }
+        let magnitude = result.into_iter().map(|limb| limb as u32).collect();
+        BigInt::from_parts(self.negative != other.negative, magnitude)
+    }
+
+    /// The quotient rounded towards zero and the remainder, which has the sign
+    /// of `self`, like the operators on `i64`. Returns `None` when dividing by zero.
+    pub fn div_rem(&self, other: &BigInt) -> Option<(BigInt, BigInt)> {
+        if other.is_zero() {
+            return None;
+        }
+        let mut quotient = vec![0u32; self.magnitude.len()];
+        let mut remainder: Vec<u32> = Vec::new();
+        for i in (0..self.magnitude.len()).rev() {
+            // remainder = remainder * BASE + limb
+            remainder.insert(0, self.magnitude[i]);
+            while remainder.last() == Some(&0) {
+                remainder.pop();
+            }
+            // find the largest digit such that other * digit <= remainder
+            let (mut low, mut high) = (0u64, BASE - 1);
+            while low < high {
+                let mid = (low + high).div_ceil(2);
+                let product = mul_small(&other.magnitude, mid);
+                if compare_magnitudes(&product, &remainder) == Ordering::Greater {
+                    high = mid - 1;
+                } else {
+                    low = mid;
+                }
+            }
+            quotient[i] = low as u32;
+            remainder = sub_magnitudes(&remainder, &mul_small(&other.magnitude, low));
+        }
+        Some((BigInt::from_parts(self.negative != other.negative, quotient),
+              BigInt::from_parts(self.negative, remainder)))
+    }
+
+    pub fn pow(&self, exponent: u32) -> BigInt {
+        let mut result = BigInt::from(1);
+        let mut base = self.clone();
+        let mut exponent = exponent;
+        while exponent > 0 {
+            if exponent % 2 == 1 {
+                result = result.mul(&base);
+            }
+            exponent /= 2;
+            if exponent > 0 {
+                base = base.mul(&base);
+            }
+        }
+        result
+    }
+
+    /// Divides by `2^n`, rounding towards negative infinity like `>>` on `i64`
+    pub fn shr(&self, n: u64) -> BigInt {
+        // every limb is less than 2^30, so larger shifts only leave the sign
+        if n >= 30 * self.magnitude.len() as u64 {
+            return BigInt::from(if self.negative { -1 } else { 0 });
+        }
+        let (quotient, remainder) = self.div_rem(&BigInt::from(2).pow(n as u32)).unwrap();
+        if remainder.is_negative() {
+            quotient.sub(&BigInt::from(1))
+        } else {
+            quotient
+        }
+    }
+
+    /// Applies a bitwise operator to the words of the two's complements of the
+    /// numbers, like `&` does on `i64`
+    pub fn bitwise(&self, other: &BigInt, op: fn(u32, u32) -> u32) -> BigInt {
+        // a word holds more than a limb, and the extra word holds the sign
+        let len = self.magnitude.len().max(other.magnitude.len()) + 1;
+        let words: Vec<u32> = self.to_twos_complement(len)
+            .into_iter()
+            .zip(other.to_twos_complement(len))
+            .map(|(a, b)| op(a, b))
+            .collect();
+        BigInt::from_twos_complement(&words)
+    }
+
+    fn to_twos_complement(&self, len: usize) -> Vec<u32> {
+        // -x is stored as the complement of x - 1
+        let mut magnitude = if self.negative {
+            self.abs().sub(&BigInt::from(1)).magnitude
+        } else {
+            self.magnitude.clone()
+        };
+        let mut words = Vec::new();
+        while words.len() < len {
+            let (quotient, remainder) = div_small(&magnitude, WORD);
+            let word = remainder as u32;
+            words.push(if self.negative { !word } else { word });
+            magnitude = quotient;
+        }
+        words
+    }
+
+    fn from_twos_complement(words: &[u32]) -> BigInt {
+        let negative = words.last().cloned().unwrap_or(0) >> 31 == 1;
+        let mut magnitude = Vec::new();
+        for &word in words.iter().rev() {
+            let word = if negative { !word } else { word };
+            magnitude = add_magnitudes(&mul_small(&magnitude, WORD),
+                                       &BigInt::from(word as i64).magnitude);
+        }
+        let result = BigInt::from_parts(false, magnitude);
+        if negative {
+            result.add(&BigInt::from(1)).neg()
+        } else {
+            result
+        }
+    }
+}
+
+fn compare_magnitudes(a: &[u32], b: &[u32]) -> Ordering {
+    if a.len() != b.len() {
+        return a.len().cmp(&b.len());
+    }
+    for (x, y) in a.iter().rev().zip(b.iter().rev()) {
+        if x != y {
+            return x.cmp(y);
+        }
+    }
+    Ordering::Equal
+}
+
+fn add_magnitudes(a: &[u32], b: &[u32]) -> Vec<u32> {
+    let mut result = Vec::new();
+    let mut carry = 0;
+    for i in 0..a.len().max(b.len()) {
+        let sum = *a.get(i).unwrap_or(&0) as u64 + *b.get(i).unwrap_or(&0) as u64 + carry;
+        result.push((sum % BASE) as u32);
+        carry = sum / BASE;
+    }
+    if carry > 0 {
+        result.push(carry as u32);
+    }
+    result
+}
+
+// `a` must not be smaller than `b`
+fn sub_magnitudes(a: &[u32], b: &[u32]) -> Vec<u32> {
+    let mut result = Vec::new();
+    let mut borrow = 0;
+    for (i, &limb) in a.iter().enumerate() {
+        let subtrahend = *b.get(i).unwrap_or(&0) as i64 + borrow;
+        let mut difference = limb as i64 - subtrahend;
+        if difference < 0 {
+            difference += BASE as i64;
+            borrow = 1;
+        } else {
+            borrow = 0;
+        }
+        result.push(difference as u32);
+    }
+    while result.last() == Some(&0) {
+        result.pop();
+    }
+    result
+}
+
+fn mul_small(a: &[u32], n: u64) -> Vec<u32> {
+    let mut result = Vec::new();
+    let mut carry = 0;
+    for &limb in a {
+        let product = (limb as u64) * n + carry;
+        result.push((product % BASE) as u32);
+        carry = product / BASE;
+    }
+    while carry > 0 {
+        result.push((carry % BASE) as u32);
+        carry /= BASE;
+    }
+    while result.last() == Some(&0) {
+        result.pop();
+    }
+    result
+}
+
+// Returns the quotient and the remainder, for a divisor of at most 2^32
+fn div_small(a: &[u32], divisor: u64) -> (Vec<u32>, u64) {
+    let mut quotient = vec![0u32; a.len()];
+    let mut remainder = 0;
+    for (i, &limb) in a.iter().enumerate().rev() {
+        let current = remainder * BASE + limb as u64;
+        quotient[i] = (current / divisor) as u32;
+        remainder = current % divisor;
+    }
+    while quotient.last() == Some(&0) {
+        quotient.pop();
+    }
+    (quotient, remainder)
+}
+
+impl From<i64> for BigInt {
+    fn from(from: i64) -> Self {
+        let negative = from < 0;
+        // the absolute value of i64::MIN only fits in a u64
+        let mut n = from.wrapping_abs() as u64;
+        let mut magnitude = Vec::new();
+        while n > 0 {
+            magnitude.push((n % BASE) as u32);
+            n /= BASE;
+        }
+        BigInt::from_parts(negative, magnitude)
+    }
+}
+
+impl FromStr for BigInt {
+    type Err = ();
+
+    /// Parses a decimal integer with an optional sign
+    fn from_str(s: &str) -> Result<BigInt, ()> {
+        let (negative, digits) = match s.strip_prefix('-') {
+            Some(digits) => (true, digits),
+            None => (false, s.strip_prefix('+').unwrap_or(s)),
+        };
+        if digits.is_empty() || !digits.bytes().all(|b| b.is_ascii_digit()) {
+            return Err(());
+        }
+        let mut magnitude = Vec::new();
+        let mut end = digits.len();
+        while end > 0 {
+            let start = end.saturating_sub(DIGITS_PER_LIMB);
+            magnitude.push(digits[start..end].parse().unwrap());
+            end = start;
+        }
+        Ok(BigInt::from_parts(negative, magnitude))
+    }
+}
+
+impl PartialOrd for BigInt {
+    fn partial_cmp(&self, other: &BigInt) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for BigInt {
+    fn cmp(&self, other: &BigInt) -> Ordering {
+        match (self.negative, other.negative) {
+            (false, true) => Ordering::Greater,
+            (true, false) => Ordering::Less,
+            (false, false) => compare_magnitudes(&self.magnitude, &other.magnitude),
+            (true, true) => compare_magnitudes(&other.magnitude, &self.magnitude),
+        }
+    }
+}
+
+impl fmt::Display for BigInt {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let mut limbs = self.magnitude.iter().rev();
+        let first = match limbs.next() {
+            Some(first) => first,
+            None => return write!(f, "0"),
+        };
+        if self.negative {
+            write!(f, "-")?;
+        }
+        write!(f, "{}", first)?;
+        for limb in limbs {
+            write!(f, "{:09}", limb)?;
+        }
+        Ok(())
+    }
+}
+
@@ -0,0 +1,2361 @@
+use std::rc::Rc;
+use std::cell::RefCell;
+use std::fmt;
+
+use linear_map::LinearMap;
+
+use ast::*;
+use value::*;
+use operations;
+use environment::Environment;
+use function::*;
+use module::{self, ModuleLoader, ModulePath};
+use resolver::{check_global_references, declared_names, pattern_names};
+use runtime::*;
+use ast_walk_interpreter::{DEFAULT_MAX_CALL_DEPTH, add_stack_frame, call_func_with_arranged_args,
+                           caught_error_value, check_return_constraint, get_element, get_field,
+                           get_fn_name, match_pattern, set_field, to_element_index};
+
+/// An instruction of the VM. Its operands are slots of the current frame, indices
+/// into the tables of the `Prototype` it belongs to, or positions in its code.
+#[derive(Debug, Clone)]
+enum Instruction {
+    Constant(u32),
+    /// Pushes the absence of a value, like the result of a function that returns nothing
+    PushNone,
+    Pop,
+    /// Removes the entry below the top of the stack
+    Nip,
+    Dup,
+    Swap,
+    /// Fails with `NoneError` if the top of the stack is not a value, naming the
+    /// called function that was expected to return one
+    ExpectValue(Option<u32>),
+    /// Puts a new variable in the slot, which is undeclared until its declaration runs
+    NewCell(u32),
+    /// Pops into the variable in the slot, declaring it as the declaration says
+    DeclareLocal(u32, u32),
+    /// Pushes the variable in the slot, which has the given name
+    LoadLocal(u32, u32),
+    StoreLocal(u32, u32),
+    /// Pushes the given variable captured by the closure, which has the given name
+    LoadUpvalue(u32, u32),
+    StoreUpvalue(u32, u32),
+    /// Top-level variables are kept in an `Environment` and looked up by name
+    LoadGlobal(u32),
+    StoreGlobal(u32),
+    DeclareGlobal(u32),
+    Binary(BinOp),
+    Negate,
+    Not,
+    /// Replaces the top of the stack with whether it is truthy
+    Truthy,
+    Jump(u32),
+    /// Pops a condition, and jumps if it is falsy
+    JumpIfFalse(u32),
+    JumpIfTrue(u32),
+    MakeTuple(u32),
+    MakeList(u32),
+    /// Pops the values of a map literal, whose keys are the given number of names
+    /// from the first one on
+    MakeMap(u32, u32),
+    /// `object[index]`, where the span and the one after it are the positions of
+    /// the object and the index
+    Index(u32),
+    GetField(u32),
+    /// Pops an object and a value, and pushes the object with the value in the field
+    SetField(u32),
+    /// Pops an object, an index and a value, and pushes the object with the value
+    /// at the index. Lists are changed in place and not pushed, which jumps to the
+    /// given position instead.
+    SetIndex(u32, u32),
+    MakeClosure(u32),
+    Call(u32),
+    /// A call in place of the current one, for `return f(args);`
+    TailCall(u32),
+    Return,
+    /// Returns from a function whose body ended without a `return` statement
+    ReturnNone,
+    Throw,
+    /// Fails with the given error
+    Fail(u32),
+    /// Makes errors jump to the given position until the handler is popped
+    PushHandler(u32),
+    PopHandler,
+    /// Drops the values above the given depth of the frame's stack, except for the
+    /// top of the stack if told to keep it, when jumping out of a loop
+    Leave(u32, bool),
+    /// Replaces an iterable with a tuple of its items, and pushes the index of the
+    /// next item
+    IntoItems,
+    /// Leaves the callee and arguments of the given call site for the call after
+    /// it, unless the callee is `range`, whose integers a `for` loop then counts
+    /// through. A range replaces them with `None` and its state, and jumps past the
+    /// call and `IntoItems`.
+    IntoRange(u32, u32),
+    /// Pushes the next item of a `for` loop, or jumps if there are none left
+    ForNext(u32),
+    /// Pops a value and pushes the values the pattern binds in it, failing if it
+    /// does not match
+    Destructure(u32),
+    /// Pushes the values the pattern binds in the top of the stack, or jumps if it
+    /// does not match
+    MatchArm(u32, u32),
+    /// Fails with `NoMatchingPattern` for the top of the stack
+    NoMatch,
+    /// `expr?`, where the span is the position of `expr`
+    TryUnwrap(u32),
+    /// Pushes the module imported from the path with the given name
+    Import(u32),
+    /// Jumps if the call was given an argument for the parameter
+    JumpIfArg(u32, u32),
+    /// Pops the default value of the parameter into its argument
+    SetArg(u32),
+    /// Moves the argument for the parameter into the variable in the slot
+    BindParam(u32, u32),
+}
+
+/// How a closure gets one of its variables from the frame it is created in
+#[derive(Debug)]
+enum Capture {
+    Local(u32),
+    Upvalue(u32),
+}
+
+/// Where a closure looks for a variable it captured before the declaration of
+/// the variable ran, while the declaration has still not run
+#[derive(Debug, Clone, Copy)]
+enum Fallback {
+    Upvalue(u32),
+    Global,
+}
+
+#[derive(Debug)]
+struct CallSite {
+    num_positional: usize,
+    /// The names of the named arguments, whose values follow the positional ones
+    named: Vec<String>,
+    /// The name of the called function, if it is called by name
+    fn_name: Option<String>,
+    fn_pos: OffsetSpan,
+}
+
+#[derive(Debug)]
+struct Declaration {
+    name: String,
+    binding_type: BindingType,
+    constraint: Option<ConstraintType>,
+}
+
+/// The compiled code of a function, or of the top level of a program
+#[derive(Debug)]
+struct Prototype {
+    maybe_id: Option<String>,
+    is_function: bool,
+    params: Vec<FnParam>,
+    call_sign: CallSign,
+    ret_constraint: Option<ReturnConstraint>,
+    captures: Vec<Capture>,
+    /// The fallbacks of the captures that were not declared yet when captured
+    fallbacks: Vec<Option<Fallback>>,
+    num_slots: usize,
+    code: Vec<Instruction>,
+    /// The position of the code every instruction was compiled from, for errors
+    positions: Vec<OffsetSpan>,
+    constants: Vec<Value>,
+    names: Vec<String>,
+    spans: Vec<OffsetSpan>,
+    patterns: Vec<Pattern>,
+    call_sites: Vec<CallSite>,
+    declarations: Vec<Declaration>,
+    errors: Vec<RuntimeError>,
+    prototypes: Vec<Rc<Prototype>>,
+}
+
+/// A variable, shared by the frame that declares it and the closures that capture it
+#[derive(Debug)]
+struct Local {
+    /// `None` until the declaration of the variable runs
+    value: Option<Value>,
+    binding_type: BindingType,
+    constraint: Option<ConstraintType>,
+}
+
+type LocalCell = Rc<RefCell<Local>>;
+
+/// A compiled function, along with the variables it captured
+pub struct Closure {
+    proto: Rc<Prototype>,
+    upvalues: Vec<LocalCell>,
+    globals: Rc<RefCell<Environment>>,
+    // the modules loaded by the program that created the closure
+    modules: Rc<RefCell<ModuleLoader<Value>>>,
+}
+
+impl Closure {
+    pub fn get_call_sign(&self) -> CallSign {
+        self.proto.call_sign.clone()
+    }
+
+    pub fn get_param_names(&self) -> Vec<String> {
+        self.proto
+            .params
+            .iter()
+            .filter(|p| !p.is_rest)
+            .map(|p| p.name.clone())
+            .collect()
+    }
+}
+
+impl fmt::Debug for Closure {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.proto.maybe_id {
+            Some(ref id) => write!(f, "<compiled function {}>", id),
+            None => write!(f, "<compiled function>"),
+        }
+    }
+}
+
+enum Place {
+    Local(u32),
+    Upvalue(u32),
+    Global,
+}
+
+struct ScopeVariable {
+    name: String,
+    slot: u32,
+    declared: bool,
+}
+
+struct Scope {
+    variables: Vec<ScopeVariable>,
+    first_slot: u32,
+}
+
+struct LoopTarget {
+    label: Option<String>,
+    /// The depth of the stack when the loop starts
+    depth: usize,
+    /// Whether the loop is an expression, whose value is given by `break`
+    has_value: bool,
+    num_handlers: usize,
+    continue_target: usize,
+    breaks: Vec<usize>,
+}
+
+struct FunctionCompiler {
+    proto: Prototype,
+    /// The blocks being compiled, innermost last. At the top level of a program,
+    /// declarations outside of any block are global.
+    scopes: Vec<Scope>,
+    /// The names of the captured variables, or `None` for the ones that are
+    /// only captured as a fallback
+    upvalue_names: Vec<Option<String>>,
+    loops: Vec<LoopTarget>,
+    /// The number of `try` blocks being compiled
+    num_handlers: usize,
+    /// The number of entries the frame has on the stack at this point of the code
+    depth: usize,
+    next_slot: u32,
+}
+
+impl FunctionCompiler {
+    fn new(maybe_id: Option<String>,
+           params: Vec<FnParam>,
+           ret_constraint: Option<ReturnConstraint>,
+           is_function: bool)
+           -> FunctionCompiler {
+        FunctionCompiler {
+            proto: Prototype {
+                maybe_id: maybe_id,
+                is_function: is_function,
+                call_sign: CallSign::from_params(&params),
+                params: params,
+                ret_constraint: ret_constraint,
+                captures: Vec::new(),
+                fallbacks: Vec::new(),
+                num_slots: 0,
+                code: Vec::new(),
+                positions: Vec::new(),
+                constants: Vec::new(),
+                names: Vec::new(),
+                spans: Vec::new(),
+                patterns: Vec::new(),
+                call_sites: Vec::new(),
+                declarations: Vec::new(),
+                errors: Vec::new(),
+                prototypes: Vec::new(),
+            },
+            scopes: Vec::new(),
+            upvalue_names: Vec::new(),
+            loops: Vec::new(),
+            num_handlers: 0,
+            depth: 0,
+            next_slot: 0,
+        }
+    }
+
+    fn emit(&mut self, instruction: Instruction, pos: OffsetSpan) -> usize {
+        let effect = self.stack_effect(&instruction);
+        self.depth = (self.depth as isize + effect) as usize;
+        self.proto.code.push(instruction);
+        self.proto.positions.push(pos);
+        self.proto.code.len() - 1
+    }
+
+    // The change in the depth of the stack when the instruction does not jump
+    fn stack_effect(&self, instruction: &Instruction) -> isize {
+        match *instruction {
+            Instruction::Constant(_) |
+            Instruction::PushNone |
+            Instruction::Dup |
+            Instruction::LoadLocal(..) |
+            Instruction::LoadUpvalue(..) |
+            Instruction::LoadGlobal(_) |
+            Instruction::MakeClosure(_) |
+            Instruction::IntoItems |
+            Instruction::ForNext(_) |
+            Instruction::Import(_) => 1,
+            Instruction::Pop |
+            Instruction::Nip |
+            Instruction::DeclareLocal(..) |
+            Instruction::StoreLocal(..) |
+            Instruction::StoreUpvalue(..) |
+            Instruction::StoreGlobal(_) |
+            Instruction::DeclareGlobal(_) |
+            Instruction::Binary(_) |
+            Instruction::JumpIfFalse(_) |
+            Instruction::JumpIfTrue(_) |
+            Instruction::Index(_) |
+            Instruction::SetField(_) |
+            Instruction::Return |
+            Instruction::Throw |
+            Instruction::SetArg(_) => -1,
+            Instruction::SetIndex(..) => -2,
+            Instruction::MakeTuple(n) |
+            Instruction::MakeList(n) |
+            Instruction::MakeMap(_, n) => 1 - n as isize,
+            Instruction::Call(site) => {
+                let site = &self.proto.call_sites[site as usize];
+                -((site.num_positional + site.named.len()) as isize)
+            }
+            Instruction::TailCall(site) => {
+                let site = &self.proto.call_sites[site as usize];
+                -((site.num_positional + site.named.len()) as isize) - 1
+            }
+            Instruction::Destructure(pattern) => {
+                self.num_bindings(pattern) as isize - 1
+            }
+            Instruction::MatchArm(pattern, _) => self.num_bindings(pattern) as isize,
+            Instruction::Swap |
+            Instruction::ExpectValue(_) |
+            Instruction::NewCell(_) |
+            Instruction::Negate |
+            Instruction::Not |
+            Instruction::Truthy |
+            Instruction::Jump(_) |
+            Instruction::GetField(_) |
+            Instruction::ReturnNone |
+            Instruction::Fail(_) |
+            Instruction::PushHandler(_) |
+            Instruction::PopHandler |
+            Instruction::Leave(..) |
+            Instruction::NoMatch |
+            Instruction::TryUnwrap(_) |
+            Instruction::JumpIfArg(..) |
+            Instruction::IntoRange(..) |
+            Instruction::BindParam(..) => 0,
+        }
+    }
+
+    fn num_bindings(&self, pattern: u32) -> usize {
+        let mut names = Vec::new();
+        pattern_names(&self.proto.patterns[pattern as usize], &mut names);
+        names.len()
+    }
+
+    // Makes the jump at `at` go to the next instruction to be emitted
+    fn patch(&mut self, at: usize) {
+        let target = self.proto.code.len() as u32;
+        match self.proto.code[at] {
+            Instruction::Jump(ref mut t) |
+            Instruction::JumpIfFalse(ref mut t) |
+            Instruction::JumpIfTrue(ref mut t) |
+            Instruction::SetIndex(_, ref mut t) |
+            Instruction::PushHandler(ref mut t) |
+            Instruction::ForNext(ref mut t) |
+            Instruction::IntoRange(_, ref mut t) |
+            Instruction::MatchArm(_, ref mut t) |
+            Instruction::JumpIfArg(_, ref mut t) => *t = target,
+            _ => unreachable!(),
+        }
+    }
+
+    fn add_name(&mut self, name: &str) -> u32 {
+        match self.proto.names.iter().position(|n| n == name) {
+            Some(idx) => idx as u32,
+            None => {
+                self.proto.names.push(name.to_owned());
+                self.proto.names.len() as u32 - 1
+            }
+        }
+    }
+
+    // The slot of the variable `name` in the outermost `num_scopes` blocks being
+    // compiled, with whether it is declared at this point and the index of its
+    // block. Variables declared further on in their block are only seen by
+    // closures.
+    fn find_variable(&self,
+                     name: &str,
+                     include_undeclared: bool,
+                     num_scopes: usize)
+                     -> Option<(u32, bool, usize)> {
+        for (idx, scope) in self.scopes[..num_scopes].iter().enumerate().rev() {
+            if let Some(variable) = scope.variables.iter().find(|v| v.name == name) {
+                if variable.declared || include_undeclared {
+                    return Some((variable.slot, variable.declared, idx));
+                }
+            }
+        }
+        None
+    }
+
+    fn finish(self) -> Prototype {
+        self.proto
+    }
+}
+
+struct Compiler {
+    /// The functions being compiled, innermost last
+    functions: Vec<FunctionCompiler>,
+    /// The names declared as globals
+    globals: Vec<String>,
+    /// The names that are looked up as globals, where they are referred to
+    global_references: Vec<(String, OffsetSpan)>,
+}
+
+// Compiles the statements of a program, or of the REPL when there is no `env`.
+// A program fails with a `ReferenceError` for a name that refers to a global
+// which neither the program nor `env` declares, before any of it runs.
+fn compile_program(statements: &[StmtNode],
+                   possible_env: Option<&Environment>)
+                   -> Result<Prototype, RuntimeErrorWithPosition> {
+    let mut compiler = Compiler {
+        functions: vec![FunctionCompiler::new(None, Vec::new(), None, false)],
+        globals: Vec::new(),
+        global_references: Vec::new(),
+    };
+    compiler.compile_stmts(statements, true, (0, 0));
+    compiler.emit(Instruction::Return, (0, 0));
+    if let Some(env) = possible_env {
+        check_global_references(&compiler.global_references, &compiler.globals, env)?;
+    }
+    Ok(compiler.functions.pop().unwrap().finish())
+}
+
+impl Compiler {
+    fn current(&mut self) -> &mut FunctionCompiler {
+        self.functions.last_mut().unwrap()
+    }
+
+    fn emit(&mut self, instruction: Instruction, pos: OffsetSpan) -> usize {
+        self.current().emit(instruction, pos)
+    }
+
+    fn patch(&mut self, at: usize) {
+        self.current().patch(at)
+    }
+
+    fn code_len(&mut self) -> usize {
+        self.current().proto.code.len()
+    }
+
+    fn name(&mut self, name: &str) -> u32 {
+        self.current().add_name(name)
+    }
+
+    fn add_span(&mut self, pos: OffsetSpan) -> u32 {
+        let spans = &mut self.current().proto.spans;
+        spans.push(pos);
+        spans.len() as u32 - 1
+    }
+
+    fn add_pattern(&mut self, pattern: &Pattern) -> u32 {
+        let patterns = &mut self.current().proto.patterns;
+        patterns.push(pattern.clone());
+        patterns.len() as u32 - 1
+    }
+
+    fn fail(&mut self, err: RuntimeError, pos: OffsetSpan) {
+        let errors_len = {
+            let errors = &mut self.current().proto.errors;
+            errors.push(err);
+            errors.len()
+        };
+        self.emit(Instruction::Fail(errors_len as u32 - 1), pos);
+    }
+
+    fn is_global_scope(&mut self) -> bool {
+        self.current().scopes.is_empty()
+    }
+
+    fn begin_scope(&mut self, statements: &[StmtNode], pos: OffsetSpan) {
+        {
+            let function = self.current();
+            let first_slot = function.next_slot;
+            function
+                .scopes
+                .push(Scope {
+                          variables: Vec::new(),
+                          first_slot: first_slot,
+                      });
+        }
+        // the variables declared in a block are created when entering it, so that
+        // closures can refer to the variables declared after them
+        let mut names = Vec::new();
+        for statement in statements {
+            declared_names(statement, &mut names);
+        }
+        for name in names {
+            let is_new = self.current()
+                .scopes
+                .last()
+                .unwrap()
+                .variables
+                .iter()
+                .all(|v| v.name != name);
+            if is_new {
+                self.add_variable(&name, false, pos);
+            }
+        }
+    }
+
+    fn end_scope(&mut self) {
+        let function = self.current();
+        let scope = function.scopes.pop().unwrap();
+        function.next_slot = scope.first_slot;
+    }
+
+    fn add_variable(&mut self, name: &str, declared: bool, pos: OffsetSpan) -> u32 {
+        let slot = {
+            let function = self.current();
+            let slot = function.next_slot;
+            function.next_slot += 1;
+            if function.next_slot as usize > function.proto.num_slots {
+                function.proto.num_slots = function.next_slot as usize;
+            }
+            function
+                .scopes
+                .last_mut()
+                .unwrap()
+                .variables
+                .push(ScopeVariable {
+                          name: name.to_owned(),
+                          slot: slot,
+                          declared: declared,
+                      });
+            slot
+        };
+        self.emit(Instruction::NewCell(slot), pos);
+        slot
+    }
+
+    // The slot that a declaration of `name` in the innermost block stores into.
+    // Declaring a name again in the same block replaces the variable's value.
+    fn slot_for_declaration(&mut self, name: &str, pos: OffsetSpan) -> u32 {
+        let existing = self.current()
+            .scopes
+            .last_mut()
+            .unwrap()
+            .variables
+            .iter_mut()
+            .find(|v| v.name == name)
+            .map(|variable| {
+                     variable.declared = true;
+                     variable.slot
+                 });
+        match existing {
+            Some(slot) => slot,
+            None => self.add_variable(name, true, pos),
+        }
+    }
+
+    fn add_declaration(&mut self,
+                       name: &str,
+                       binding_type: BindingType,
+                       constraint: Option<ConstraintType>)
+                       -> u32 {
+        let declarations = &mut self.current().proto.declarations;
+        declarations.push(Declaration {
+                              name: name.to_owned(),
+                              binding_type: binding_type,
+                              constraint: constraint,
+                          });
+        declarations.len() as u32 - 1
+    }
+
+    // Declares `name` with the value on top of the stack
+    fn declare(&mut self,
+               name: &str,
+               binding_type: BindingType,
+               constraint: Option<ConstraintType>,
+               pos: OffsetSpan) {
+        let declaration = self.add_declaration(name, binding_type, constraint);
+        if self.is_global_scope() {
+            self.globals.push(name.to_owned());
+            self.emit(Instruction::DeclareGlobal(declaration), pos);
+        } else {
+            let slot = self.slot_for_declaration(name, pos);
+            self.emit(Instruction::DeclareLocal(slot, declaration), pos);
+        }
+    }
+
+    // Declares the names bound by `pattern`, whose values are on the stack in
+    // the order of the names
+    fn declare_bindings(&mut self, pattern: &Pattern, binding_type: &BindingType, pos: OffsetSpan) {
+        let mut names = Vec::new();
+        pattern_names(pattern, &mut names);
+        for (i, name) in names.iter().enumerate().rev() {
+            // the last value bound to a name is the one it keeps
+            if names[i + 1..].contains(name) {
+                self.emit(Instruction::Pop, pos);
+            } else {
+                self.declare(name, binding_type.clone(), None, pos);
+            }
+        }
+    }
+
+    fn resolve(&mut self, name: &str) -> Place {
+        let innermost = self.functions.len() - 1;
+        self.resolve_in(innermost, name, false)
+    }
+
+    // Finds the variable `name` refers to in the function at `idx`, capturing it
+    // from the enclosing functions if needed
+    fn resolve_in(&mut self, idx: usize, name: &str, include_undeclared: bool) -> Place {
+        let num_scopes = self.functions[idx].scopes.len();
+        if let Some((slot, ..)) = self.functions[idx]
+               .find_variable(name, include_undeclared, num_scopes) {
+            return Place::Local(slot);
+        }
+        self.resolve_outside_blocks(idx, name)
+    }
+
+    // Finds the variable `name` refers to outside of the blocks of the function
+    // at `idx`
+    fn resolve_outside_blocks(&mut self, idx: usize, name: &str) -> Place {
+        if let Some(upvalue) = self.functions[idx]
+               .upvalue_names
+               .iter()
+               .position(|n| n.as_ref().is_some_and(|n| n == name)) {
+            return Place::Upvalue(upvalue as u32);
+        }
+        if idx == 0 {
+            return Place::Global;
+        }
+        let num_scopes = self.functions[idx - 1].scopes.len();
+        match self.capture(idx, name, num_scopes) {
+            Some((capture, fallback)) => {
+                Place::Upvalue(self.add_upvalue(idx, Some(name), capture, fallback))
+            }
+            None => Place::Global,
+        }
+    }
+
+    // How the function at `idx` captures the variable `name` of the outermost
+    // `num_scopes` blocks of the function around it, or `None` for a global
+    fn capture(&mut self,
+               idx: usize,
+               name: &str,
+               num_scopes: usize)
+               -> Option<(Capture, Option<Fallback>)> {
+        let outer = idx - 1;
+        match self.functions[outer].find_variable(name, true, num_scopes) {
+            Some((slot, true, _)) => Some((Capture::Local(slot), None)),
+            // until its declaration runs, the name refers to the variable outside
+            // of its block
+            Some((slot, false, scope)) => {
+                let fallback = self.fallback(idx, name, scope);
+                Some((Capture::Local(slot), Some(fallback)))
+            }
+            None => {
+                match self.resolve_outside_blocks(outer, name) {
+                    Place::Upvalue(upvalue) => {
+                        let fallback = self.inherited_fallback(idx, upvalue);
+                        Some((Capture::Upvalue(upvalue), fallback))
+                    }
+                    Place::Global => None,
+                    Place::Local(_) => unreachable!(),
+                }
+            }
+        }
+    }
+
+    // The fallback of the function at `idx` for the variable `name` of the
+    // outermost `num_scopes` blocks of the function around it
+    fn fallback(&mut self, idx: usize, name: &str, num_scopes: usize) -> Fallback {
+        match self.capture(idx, name, num_scopes) {
+            Some((capture, fallback)) => {
+                Fallback::Upvalue(self.add_upvalue(idx, None, capture, fallback))
+            }
+            None => Fallback::Global,
+        }
+    }
+
+    // The fallback of the function at `idx` for the upvalue of the function
+    // around it, which it captures
+    fn inherited_fallback(&mut self, idx: usize, outer_upvalue: u32) -> Option<Fallback> {
+        match self.functions[idx - 1].proto.fallbacks[outer_upvalue as usize] {
+            None => None,
+            Some(Fallback::Global) => Some(Fallback::Global),
+            Some(Fallback::Upvalue(outer_fallback)) => {
+                let fallback = self.inherited_fallback(idx, outer_fallback);
+                let upvalue =
+                    self.add_upvalue(idx, None, Capture::Upvalue(outer_fallback), fallback);
+                Some(Fallback::Upvalue(upvalue))
+            }
+        }
+    }
+
+    fn add_upvalue(&mut self,
+                   idx: usize,
+                   possible_name: Option<&str>,
+                   capture: Capture,
+                   fallback: Option<Fallback>)
+                   -> u32 {
+        let function = &mut self.functions[idx];
+        function.upvalue_names.push(possible_name.map(str::to_owned));
+        function.proto.captures.push(capture);
+        function.proto.fallbacks.push(fallback);
+        function.upvalue_names.len() as u32 - 1
+    }
+
+    fn load(&mut self, name: &str, pos: OffsetSpan) {
+        let name_idx = self.name(name);
+        let instruction = match self.resolve(name) {
+            Place::Local(slot) => Instruction::LoadLocal(slot, name_idx),
+            Place::Upvalue(upvalue) => Instruction::LoadUpvalue(upvalue, name_idx),
+            Place::Global => {
+                self.global_references.push((name.to_owned(), pos));
+                Instruction::LoadGlobal(name_idx)
+            }
+        };
+        self.emit(instruction, pos);
+    }
+
+    fn store(&mut self, name: &str, pos: OffsetSpan) {
+        let name_idx = self.name(name);
+        let instruction = match self.resolve(name) {
+            Place::Local(slot) => Instruction::StoreLocal(slot, name_idx),
+            Place::Upvalue(upvalue) => Instruction::StoreUpvalue(upvalue, name_idx),
+            Place::Global => {
+                self.global_references.push((name.to_owned(), pos));
+                Instruction::StoreGlobal(name_idx)
+            }
+        };
+        self.emit(instruction, pos);
+    }
+
+    fn set_depth(&mut self, depth: usize) {
+        self.current().depth = depth;
+    }
+
+    fn depth(&mut self) -> usize {
+        self.current().depth
+    }
+
+    // Compiles statements, leaving the value of the last one on the stack if
+    // `want_value` is set
+    fn compile_stmts(&mut self, statements: &[StmtNode], want_value: bool, pos: OffsetSpan) {
+        for (i, statement) in statements.iter().enumerate() {
+            self.compile_stmt(statement, want_value && i == statements.len() - 1);
+        }
+        if want_value && statements.is_empty() {
+            self.emit(Instruction::PushNone, pos);
+        }
+    }
+
+    fn compile_block(&mut self, statements: &[StmtNode], want_value: bool, pos: OffsetSpan) {
+        self.begin_scope(statements, pos);
+        self.compile_stmts(statements, want_value, pos);
+        self.end_scope();
+    }
+
+    fn compile_stmt(&mut self, s: &StmtNode, want_value: bool) {
+        let depth = self.depth();
+        match s.data {
+            Stmt::Expr(ref expr) => {
+                self.compile_expr(expr);
+                if !want_value {
+                    self.emit(Instruction::Pop, s.pos);
+                }
+                return;
+            }
+            Stmt::Block(ref statements, _) => {
+                return self.compile_block(statements, want_value, s.pos)
+            }
+            Stmt::IfThen(ref if_then_stmt) => {
+                return self.compile_if_then(if_then_stmt, want_value, s.pos);
+            }
+            Stmt::TryCatch(ref try_block, ref error_name, ref catch_block) => {
+                return self.compile_try_catch(try_block,
+                                              error_name,
+                                              catch_block,
+                                              want_value,
+                                              s.pos);
+            }
+            Stmt::VarDecl(ref variable, ref expr) => self.compile_var_decl(variable, expr),
+            Stmt::StructDecl(ref struct_decl) => {
+                let &StructDecl {
+                         ref name,
+                         ref fields,
+                         ..
+                     } = struct_decl;
+                if let Some(field) = struct_decl.find_duplicate_field() {
+                    self.fail(RuntimeError::DuplicateField(name.clone(), field.clone()), s.pos);
+                }
+                let constructor = Function::StructConstructor {
+                    call_sign: CallSign {
+                        min_params: fields.len(),
+                        max_params: Some(fields.len()),
+                    },
+                    name: name.clone(),
+                    fields: fields.clone(),
+                };
+                self.compile_constant(Value::Function(Box::new(constructor)), s.pos);
+                self.declare(name, BindingType::Mutable, None, s.pos);
+            }
+            Stmt::Assign(ref lhs_expr, ref expr) => {
+                self.compile_expr_value(expr);
+                self.compile_assignment(lhs_expr, lhs_expr.pos);
+            }
+            Stmt::AssignOp(ref lhs_expr, ref op, ref expr) => {
+                self.compile_expr_value(expr);
+                self.compile_lhs_value(lhs_expr);
+                self.emit(Instruction::Swap, s.pos);
+                self.emit(Instruction::Binary(op.clone()), s.pos);
+                self.compile_assignment(lhs_expr, s.pos);
+            }
+            Stmt::Loop(ref label, ref block) => self.compile_loop(label, block, false, s.pos),
+            Stmt::While(ref label, ref cond, ref block) => {
+                self.compile_while(label, cond, block, s.pos)
+            }
+            Stmt::ForIn(ref label, ref var_name, ref iterable_expr, ref block) => {
+                self.compile_for_in(label, var_name, iterable_expr, block, s.pos)
+            }
+            Stmt::Return(ref possible_expr) => self.compile_return(possible_expr, s),
+            Stmt::Throw(ref expr) => {
+                self.compile_expr_value(expr);
+                self.emit(Instruction::Throw, s.pos);
+            }
+            Stmt::Import(ref path, ref name) => {
+                let path = self.name(path);
+                self.emit(Instruction::Import(path), s.pos);
+                self.declare(name, BindingType::Immutable, None, s.pos);
+            }
+            Stmt::Break(ref label, ref possible_expr) => {
+                self.compile_break(label, possible_expr, s.pos)
+            }
+            Stmt::Continue(ref label) => self.compile_continue(label, s.pos),
+            Stmt::Empty => {}
+        }
+        // the code after a `return`, `break` or `continue` is never run, and is
+        // compiled as if the statement left the stack as it was
+        self.set_depth(depth);
+        if want_value {
+            self.emit(Instruction::PushNone, s.pos);
+        }
+    }
+
+    fn compile_var_decl(&mut self, variable: &Variable, expr: &ExprNode) {
+        self.compile_expr_value(expr);
+        match *variable {
+            Variable::Identifier(ref binding_type, ref name, ref possible_constraint, _) => {
+                self.declare(name,
+                             binding_type.clone(),
+                             possible_constraint.clone(),
+                             expr.pos);
+            }
+            Variable::Pattern(ref binding_type, ref pattern) => {
+                if let Some(name) = pattern.data.find_duplicate_binding() {
+                    self.fail(RuntimeError::DuplicateBinding(name.clone()), pattern.pos);
+                }
+                let pattern_idx = self.add_pattern(&pattern.data);
+                self.emit(Instruction::Destructure(pattern_idx), expr.pos);
+                self.declare_bindings(&pattern.data, binding_type, expr.pos);
+            }
+        }
+    }
+
+    // Stores the value on top of the stack in the place `lhs_expr` refers to
+    fn compile_assignment(&mut self, lhs_expr: &LhsExprNode, identifier_pos: OffsetSpan) {
+        let mut list_jumps = Vec::new();
+        self.compile_store(lhs_expr, identifier_pos, &mut list_jumps);
+        for jump in list_jumps {
+            self.patch(jump);
+        }
+    }
+
+    // Since values other than lists are not shared, assigning to a part of them
+    // rebuilds the object and assigns it back
+    fn compile_store(&mut self,
+                     lhs_expr: &LhsExprNode,
+                     identifier_pos: OffsetSpan,
+                     list_jumps: &mut Vec<usize>) {
+        match lhs_expr.data {
+            LhsExpr::Identifier(ref id, _) => self.store(id, identifier_pos),
+            LhsExpr::Field(ref object_lhs_expr, ref field) => {
+                self.compile_lhs_value(object_lhs_expr);
+                let field = self.name(field);
+                self.emit(Instruction::SetField(field), lhs_expr.pos);
+                self.compile_store(object_lhs_expr, object_lhs_expr.pos, list_jumps);
+            }
+            LhsExpr::Index(ref object_lhs_expr, ref index_expr) => {
+                self.compile_lhs_value(object_lhs_expr);
+                self.compile_expr_value(index_expr);
+                let span = self.add_span(object_lhs_expr.pos);
+                self.add_span(index_expr.pos);
+                list_jumps.push(self.emit(Instruction::SetIndex(span, 0), lhs_expr.pos));
+                self.compile_store(object_lhs_expr, object_lhs_expr.pos, list_jumps);
+            }
+        }
+    }
+
+    // Pushes the current value of the place that `lhs_expr` refers to
+    fn compile_lhs_value(&mut self, lhs_expr: &LhsExprNode) {
+        match lhs_expr.data {
+            LhsExpr::Identifier(ref id, _) => self.load(id, lhs_expr.pos),
+            LhsExpr::Field(ref object_lhs_expr, ref field) => {
+                self.compile_lhs_value(object_lhs_expr);
+                let field = self.name(field);
+                self.emit(Instruction::GetField(field), lhs_expr.pos);
+            }
+            LhsExpr::Index(ref object_lhs_expr, ref index_expr) => {
+                self.compile_lhs_value(object_lhs_expr);
+                self.compile_expr_value(index_expr);
+                let span = self.add_span(object_lhs_expr.pos);
+                self.add_span(index_expr.pos);
+                self.emit(Instruction::Index(span), lhs_expr.pos);
+            }
+        }
+    }
+
+    fn compile_if_then(&mut self, if_then_stmt: &IfThenStmt, want_value: bool, pos: OffsetSpan) {
+        let &IfThenStmt {
+                 ref cond,
+                 ref then_block,
+                 ref maybe_else_block,
+             } = if_then_stmt;
+        self.compile_expr_value(cond);
+        let else_jump = self.emit(Instruction::JumpIfFalse(0), cond.pos);
+        let depth = self.depth();
+        self.compile_stmt(then_block, want_value);
+        let end_jump = self.emit(Instruction::Jump(0), pos);
+        self.patch(else_jump);
+        self.set_depth(depth);
+        match *maybe_else_block {
+            Some(ref else_block) => self.compile_stmt(else_block, want_value),
+            None => {
+                if want_value {
+                    self.emit(Instruction::PushNone, pos);
+                }
+            }
+        }
+        self.patch(end_jump);
+        self.set_depth(depth + want_value as usize);
+    }
+
+    fn begin_loop(&mut self, label: &Option<String>, has_value: bool, continue_target: usize) {
+        let function = self.current();
+        let target = LoopTarget {
+            label: label.clone(),
+            depth: function.depth,
+            has_value: has_value,
+            num_handlers: function.num_handlers,
+            continue_target: continue_target,
+            breaks: Vec::new(),
+        };
+        function.loops.push(target);
+    }
+
+    // Makes the `break`s of the innermost loop jump to the next instruction
+    fn end_loop(&mut self) {
+        let target = self.current().loops.pop().unwrap();
+        for jump in target.breaks {
+            self.patch(jump);
+        }
+        self.set_depth(target.depth + target.has_value as usize);
+    }
+
+    fn compile_loop(&mut self,
+                    label: &Option<String>,
+                    block: &StmtNode,
+                    has_value: bool,
+                    pos: OffsetSpan) {
+        let start = self.code_len();
+        self.begin_loop(label, has_value, start);
+        self.compile_stmt(block, false);
+        self.emit(Instruction::Jump(start as u32), pos);
+        self.end_loop();
+    }
+
+    fn compile_while(&mut self,
+                     label: &Option<String>,
+                     cond: &ExprNode,
+                     block: &StmtNode,
+                     pos: OffsetSpan) {
+        let start = self.code_len();
+        self.compile_expr_value(cond);
+        let exit_jump = self.emit(Instruction::JumpIfFalse(0), cond.pos);
+        self.begin_loop(label, false, start);
+        self.compile_stmt(block, false);
+        self.emit(Instruction::Jump(start as u32), pos);
+        self.patch(exit_jump);
+        self.end_loop();
+    }
+
+    fn compile_for_in(&mut self,
+                      label: &Option<String>,
+                      var_name: &str,
+                      iterable_expr: &ExprNode,
+                      block: &StmtNode,
+                      pos: OffsetSpan) {
+        let possible_range_jump = match iterable_expr.data {
+            Expr::FnCall(ref f_expr, ref args, ref named_args) => {
+                let site = self.compile_callee_and_args(f_expr, args, named_args);
+                let range_jump = self.emit(Instruction::IntoRange(site, 0), iterable_expr.pos);
+                self.emit(Instruction::Call(site), iterable_expr.pos);
+                let name_idx = get_fn_name(f_expr).map(|name| self.name(&name));
+                self.emit(Instruction::ExpectValue(name_idx), iterable_expr.pos);
+                Some(range_jump)
+            }
+            _ => {
+                self.compile_expr_value(iterable_expr);
+                None
+            }
+        };
+        self.emit(Instruction::IntoItems, iterable_expr.pos);
+        if let Some(range_jump) = possible_range_jump {
+            self.patch(range_jump);
+        }
+        let start = self.code_len();
+        self.begin_loop(label, false, start);
+        let exit_jump = self.emit(Instruction::ForNext(0), pos);
+        // each iteration gets its own variable, so closures capture the current item
+        self.begin_scope(&[], pos);
+        self.declare(var_name, BindingType::Mutable, None, pos);
+        self.compile_stmt(block, false);
+        self.end_scope();
+        self.emit(Instruction::Jump(start as u32), pos);
+        self.patch(exit_jump);
+        self.end_loop();
+        self.emit(Instruction::Pop, pos);
+        self.emit(Instruction::Pop, pos);
+    }
+
+    // The index of the loop that a `break` or `continue` with `label` leaves, or
+    // `None` after compiling the error it fails with
+    fn find_loop(&mut self,
+                 label: &Option<String>,
+                 outside_loop_error: RuntimeError,
+                 pos: OffsetSpan)
+                 -> Option<usize> {
+        let possible_idx = {
+            let loops = &self.current().loops;
+            if loops.is_empty() {
+                None
+            } else {
+                match *label {
+                    Some(_) => loops.iter().rposition(|target| target.label == *label),
+                    None => Some(loops.len() - 1),
+                }
+            }
+        };
+        if possible_idx.is_none() {
+            let err = match *label {
+                Some(ref label) if !self.current().loops.is_empty() => {
+                    RuntimeError::UnknownLabel(label.clone())
+                }
+                _ => outside_loop_error,
+            };
+            self.fail(err, pos);
+        }
+        possible_idx
+    }
+
+    // Leaves the `try` blocks and drops the stack entries inside the loop at `idx`
+    fn leave_loop(&mut self, idx: usize, keep_top: bool, pos: OffsetSpan) {
+        let (num_handlers, target_depth) = {
+            let function = self.current();
+            let target = &function.loops[idx];
+            (function.num_handlers - target.num_handlers, target.depth)
+        };
+        for _ in 0..num_handlers {
+            self.emit(Instruction::PopHandler, pos);
+        }
+        if self.depth() != target_depth + keep_top as usize {
+            self.emit(Instruction::Leave(target_depth as u32, keep_top), pos);
+        }
+    }
+
+    fn compile_break(&mut self,
+                     label: &Option<String>,
+                     possible_expr: &Option<ExprNode>,
+                     pos: OffsetSpan) {
+        let idx = match self.find_loop(label, RuntimeError::BreakOutsideLoop, pos) {
+            Some(idx) => idx,
+            None => return,
+        };
+        let has_value = self.current().loops[idx].has_value;
+        match *possible_expr {
+            Some(ref expr) => {
+                self.compile_expr_value(expr);
+                // the value a loop statement is broken with goes unused
+                if !has_value {
+                    self.emit(Instruction::Pop, pos);
+                }
+            }
+            None => {
+                if has_value {
+                    self.emit(Instruction::PushNone, pos);
+                }
+            }
+        }
+        self.leave_loop(idx, has_value, pos);
+        let jump = self.emit(Instruction::Jump(0), pos);
+        self.current().loops[idx].breaks.push(jump);
+    }
+
+    fn compile_continue(&mut self, label: &Option<String>, pos: OffsetSpan) {
+        let idx = match self.find_loop(label, RuntimeError::ContinueOutsideLoop, pos) {
+            Some(idx) => idx,
+            None => return,
+        };
+        self.leave_loop(idx, false, pos);
+        let target = self.current().loops[idx].continue_target;
+        self.emit(Instruction::Jump(target as u32), pos);
+    }
+
+    fn compile_return(&mut self, possible_expr: &Option<ExprNode>, return_stmt: &StmtNode) {
+        if !self.current().proto.is_function {
+            return self.fail(RuntimeError::ReturnOutsideFunction, return_stmt.pos);
+        }
+        match *possible_expr {
+            Some(ref expr) => {
+                // calls in a `try` block are never tail calls, since the `catch`
+                // block has to see their errors
+                if let Expr::FnCall(ref f_expr, ref args, ref named_args) = expr.data {
+                    if self.current().num_handlers == 0 {
+                        return self.compile_call(f_expr, args, named_args, expr, true);
+                    }
+                }
+                self.compile_expr_value(expr);
+            }
+            None => {
+                self.emit(Instruction::PushNone, return_stmt.pos);
+            }
+        }
+        self.emit(Instruction::Return, return_stmt.pos);
+    }
+
+    fn compile_try_catch(&mut self,
+                         try_block: &StmtNode,
+                         error_name: &str,
+                         catch_block: &StmtNode,
+                         want_value: bool,
+                         pos: OffsetSpan) {
+        let depth = self.depth();
+        let handler = self.emit(Instruction::PushHandler(0), pos);
+        self.current().num_handlers += 1;
+        self.compile_stmt(try_block, want_value);
+        self.current().num_handlers -= 1;
+        self.emit(Instruction::PopHandler, pos);
+        let end_jump = self.emit(Instruction::Jump(0), pos);
+        // the handler jumps here with the caught error on the stack
+        self.patch(handler);
+        self.set_depth(depth + 1);
+        self.begin_scope(&[], pos);
+        self.declare(error_name, BindingType::Mutable, None, pos);
+        self.compile_stmt(catch_block, want_value);
+        self.end_scope();
+        self.patch(end_jump);
+        self.set_depth(depth + want_value as usize);
+    }
+
+    fn compile_constant(&mut self, val: Value, pos: OffsetSpan) {
+        let constant = {
+            let constants = &mut self.current().proto.constants;
+            constants.push(val);
+            constants.len() as u32 - 1
+        };
+        self.emit(Instruction::Constant(constant), pos);
+    }
+
+    // Pushes the value of an expression, failing if it has none
+    fn compile_expr_value(&mut self, expr: &ExprNode) {
+        self.compile_expr(expr);
+        let possible_fn_name = match expr.data {
+            Expr::FnCall(ref f_expr, ..) => Some(get_fn_name(f_expr)),
+            Expr::Loop(..) |
+            Expr::IfThen(_) |
+            Expr::Block(..) |
+            Expr::Match(..) => Some(None),
+            _ => None,
+        };
+        // only calls, and loops, `if`s, blocks and `match`es can have no value
+        if let Some(fn_name) = possible_fn_name {
+            let name_idx = fn_name.map(|name| self.name(&name));
+            self.emit(Instruction::ExpectValue(name_idx), expr.pos);
+        }
+    }
+
+    // Pushes the value of an expression, or its absence
+    fn compile_expr(&mut self, e: &ExprNode) {
+        match e.data {
+            Expr::Literal(ref x) => self.compile_constant(Value::from(x.data.clone()), e.pos),
+            Expr::Identifier(ref id, _) => self.load(id, e.pos),
+            Expr::Tuple(ref elems) => {
+                for elem in elems {
+                    self.compile_expr_value(elem);
+                }
+                self.emit(Instruction::MakeTuple(elems.len() as u32), e.pos);
+            }
+            Expr::List(ref elems) => {
+                for elem in elems {
+                    self.compile_expr_value(elem);
+                }
+                self.emit(Instruction::MakeList(elems.len() as u32), e.pos);
+            }
+            Expr::Map(ref entries) => {
+                for &(_, ref value_expr) in entries {
+                    self.compile_expr_value(value_expr);
+                }
+                let first_key = {
+                    let names = &mut self.current().proto.names;
+                    let first_key = names.len();
+                    names.extend(entries.iter().map(|&(ref key, _)| key.clone()));
+                    first_key
+                };
+                self.emit(Instruction::MakeMap(first_key as u32, entries.len() as u32),
+                          e.pos);
+            }
+            Expr::Unary(ref op, ref expr) => {
+                self.compile_expr_value(expr);
+                match *op {
+                    UnOp::Neg => self.emit(Instruction::Negate, e.pos),
+                };
+            }
+            Expr::UnaryLogical(ref op, ref expr) => {
+                self.compile_expr_value(expr);
+                match *op {
+                    LogicalUnOp::Not => self.emit(Instruction::Not, e.pos),
+                };
+            }
+            Expr::Binary(ref expr1, ref op, ref expr2) => {
+                self.compile_expr_value(expr1);
+                self.compile_expr_value(expr2);
+                self.emit(Instruction::Binary(op.clone()), e.pos);
+            }
+            Expr::BinaryLogical(ref expr1, ref op, ref expr2) => {
+                self.compile_expr_value(expr1);
+                self.emit(Instruction::Truthy, e.pos);
+                self.emit(Instruction::Dup, e.pos);
+                let short_circuit = match *op {
+                    LogicalBinOp::And => Instruction::JumpIfFalse(0),
+                    LogicalBinOp::Or => Instruction::JumpIfTrue(0),
+                };
+                let jump = self.emit(short_circuit, e.pos);
+                self.emit(Instruction::Pop, e.pos);
+                self.compile_expr_value(expr2);
+                self.emit(Instruction::Truthy, e.pos);
+                self.patch(jump);
+            }
+            Expr::MemberByIdx(ref object_expr, ref index_expr) => {
+                self.compile_expr_value(object_expr);
+                self.compile_expr_value(index_expr);
+                let span = self.add_span(object_expr.pos);
+                self.add_span(index_expr.pos);
+                self.emit(Instruction::Index(span), e.pos);
+            }
+            Expr::Loop(ref label, ref block) => self.compile_loop(label, block, true, e.pos),
+            Expr::IfThen(ref if_then_stmt) => self.compile_if_then(if_then_stmt, true, e.pos),
+            Expr::Block(ref statements, _) => self.compile_block(statements, true, e.pos),
+            Expr::Match(ref scrutinee, ref arms) => self.compile_match(scrutinee, arms),
+            Expr::Try(ref result_expr) => {
+                self.compile_expr_value(result_expr);
+                let span = self.add_span(result_expr.pos);
+                self.emit(Instruction::TryUnwrap(span), e.pos);
+            }
+            Expr::FieldAccess(ref object_expr, ref field) => {
+                self.compile_expr_value(object_expr);
+                let field = self.name(field);
+                self.emit(Instruction::GetField(field), e.pos);
+            }
+            Expr::FnDef(ref fn_def_expr) => self.compile_fn_def(fn_def_expr, e.pos),
+            Expr::FnCall(ref f_expr, ref args, ref named_args) => {
+                self.compile_call(f_expr, args, named_args, e, false)
+            }
+        }
+    }
+
+    fn compile_match(&mut self, scrutinee: &ExprNode, arms: &[MatchArmNode]) {
+        for arm in arms {
+            let pattern = &arm.data.pattern;
+            if let Some(name) = pattern.data.find_duplicate_binding() {
+                self.fail(RuntimeError::DuplicateBinding(name.clone()), pattern.pos);
+            }
+        }
+        self.compile_expr_value(scrutinee);
+        let depth = self.depth();
+        let mut end_jumps = Vec::new();
+        for arm in arms {
+            let pattern = self.add_pattern(&arm.data.pattern.data);
+            self.begin_scope(&[], arm.pos);
+            let next_arm_jump = self.emit(Instruction::MatchArm(pattern, 0), arm.pos);
+            self.declare_bindings(&arm.data.pattern.data, &BindingType::Mutable, arm.pos);
+            let possible_guard_jump = match arm.data.guard {
+                Some(ref guard) => {
+                    self.compile_expr_value(guard);
+                    Some(self.emit(Instruction::JumpIfFalse(0), guard.pos))
+                }
+                None => None,
+            };
+            self.compile_expr(&arm.data.body);
+            self.end_scope();
+            // the value of the arm replaces the scrutinee
+            self.emit(Instruction::Nip, arm.pos);
+            end_jumps.push(self.emit(Instruction::Jump(0), arm.pos));
+            self.patch(next_arm_jump);
+            if let Some(guard_jump) = possible_guard_jump {
+                self.patch(guard_jump);
+            }
+            self.set_depth(depth);
+        }
+        self.emit(Instruction::NoMatch, scrutinee.pos);
+        for jump in end_jumps {
+            self.patch(jump);
+        }
+    }
+
+    fn compile_fn_def(&mut self, fn_def_expr: &FnDefExpr, pos: OffsetSpan) {
+        let &FnDefExpr {
+                 ref maybe_id,
+                 ref params,
+                 ref ret_constraint,
+                 ref body,
+                 ..
+             } = fn_def_expr;
+        if let Some(param) = fn_def_expr.find_required_param_after_default() {
+            self.fail(RuntimeError::RequiredParameterAfterDefault(param.clone()), pos);
+        }
+        // a named function is declared before its body is compiled, so that the
+        // body can refer to it
+        let possible_slot = match *maybe_id {
+            Some(ref id) if !self.is_global_scope() => Some(self.slot_for_declaration(id, pos)),
+            _ => None,
+        };
+        self.functions
+            .push(FunctionCompiler::new(maybe_id.clone(),
+                                        params.clone(),
+                                        ret_constraint.clone(),
+                                        true));
+        self.begin_scope(&[], pos);
+        for (i, param) in params.iter().enumerate() {
+            // defaults are evaluated on every call, and can refer to the
+            // parameters before them
+            if let Some(ref default) = param.default {
+                let jump = self.emit(Instruction::JumpIfArg(i as u32, 0), default.pos);
+                self.compile_expr_value(default);
+                self.emit(Instruction::SetArg(i as u32), default.pos);
+                self.patch(jump);
+            }
+            let slot = self.slot_for_declaration(&param.name, pos);
+            self.emit(Instruction::BindParam(i as u32, slot), pos);
+        }
+        self.compile_stmt(body, false);
+        self.emit(Instruction::ReturnNone, body.pos);
+        let proto = self.functions.pop().unwrap().finish();
+        let proto_idx = {
+            let prototypes = &mut self.current().proto.prototypes;
+            prototypes.push(Rc::new(proto));
+            prototypes.len() as u32 - 1
+        };
+        self.emit(Instruction::MakeClosure(proto_idx), pos);
+        if let Some(ref id) = *maybe_id {
+            self.emit(Instruction::Dup, pos);
+            let declaration = self.add_declaration(id, BindingType::Mutable, None);
+            match possible_slot {
+                Some(slot) => self.emit(Instruction::DeclareLocal(slot, declaration), pos),
+                None => {
+                    self.globals.push(id.clone());
+                    self.emit(Instruction::DeclareGlobal(declaration), pos)
+                }
+            };
+        }
+    }
+
+    fn compile_call(&mut self,
+                    f_expr: &ExprNode,
+                    args: &[ExprNode],
+                    named_args: &[(String, ExprNode)],
+                    fn_call_expr: &ExprNode,
+                    is_tail_call: bool) {
+        let site_idx = self.compile_callee_and_args(f_expr, args, named_args);
+        let instruction = if is_tail_call {
+            Instruction::TailCall(site_idx)
+        } else {
+            Instruction::Call(site_idx)
+        };
+        self.emit(instruction, fn_call_expr.pos);
+    }
+
+    // Pushes the function and the arguments of a call, returning its call site
+    fn compile_callee_and_args(&mut self,
+                               f_expr: &ExprNode,
+                               args: &[ExprNode],
+                               named_args: &[(String, ExprNode)])
+                               -> u32 {
+        self.compile_expr_value(f_expr);
+        for arg in args {
+            self.compile_expr_value(arg);
+        }
+        for &(_, ref arg) in named_args {
+            self.compile_expr_value(arg);
+        }
+        let site = CallSite {
+            num_positional: args.len(),
+            named: named_args.iter().map(|&(ref name, _)| name.clone()).collect(),
+            fn_name: get_fn_name(f_expr),
+            fn_pos: f_expr.pos,
+        };
+        let call_sites = &mut self.current().proto.call_sites;
+        call_sites.push(site);
+        call_sites.len() as u32 - 1
+    }
+}
+
+/// Where the `catch` block of a `try` block being run starts, and the size of the
+/// stack when the `try` block started
+struct Handler {
+    ip: usize,
+    stack_len: usize,
+}
+
+struct Frame {
+    closure: Rc<Closure>,
+    ip: usize,
+    /// Where the entries of the frame start on the stack
+    base: usize,
+    slot_base: usize,
+    /// The arguments of the call in the order of the parameters, until they are bound
+    args: Vec<Option<Value>>,
+    call_pos: OffsetSpan,
+    call_depth: usize,
+    handlers: Vec<Handler>,
+    /// The return annotations of the functions that were replaced by a tail call,
+    /// which the value returned in the end has to satisfy
+    pending_ret_constraints: Vec<ReturnConstraint>,
+}
+
+// What the VM does after running the code of a frame
+enum Flow {
+    /// Goes on with the frame on top, which a call or tail call has changed
+    Switch,
+    Return(Option<Value>),
+    /// Fails the call of the frame, rather than the code inside it
+    CallError(RuntimeError),
+}
+
+struct Vm {
+    file: Option<String>,
+    modules: Rc<RefCell<ModuleLoader<Value>>>,
+    max_call_depth: usize,
+    stack: Vec<Option<Value>>,
+    slots: Vec<Option<LocalCell>>,
+    frames: Vec<Frame>,
+}
+
+impl Vm {
+    fn new(file: Option<String>,
+           modules: Rc<RefCell<ModuleLoader<Value>>>,
+           max_call_depth: usize)
+           -> Vm {
+        Vm {
+            file: file,
+            modules: modules,
+            max_call_depth: max_call_depth,
+            stack: Vec::new(),
+            slots: Vec::new(),
+            frames: Vec::new(),
+        }
+    }
+
+    fn push_frame(&mut self,
+                  closure: Rc<Closure>,
+                  args: Vec<Option<Value>>,
+                  call_pos: OffsetSpan,
+                  call_depth: usize) {
+        let slot_base = self.slots.len();
+        self.slots.resize(slot_base + closure.proto.num_slots, None);
+        self.frames
+            .push(Frame {
+                      closure: closure,
+                      ip: 0,
+                      base: self.stack.len(),
+                      slot_base: slot_base,
+                      args: args,
+                      call_pos: call_pos,
+                      call_depth: call_depth,
+                      handlers: Vec::new(),
+                      pending_ret_constraints: Vec::new(),
+                  });
+    }
+
+    fn pop_frame(&mut self) -> Frame {
+        let frame = self.frames.pop().unwrap();
+        self.stack.truncate(frame.base);
+        self.slots.truncate(frame.slot_base);
+        frame
+    }
+
+    // Runs until the frame that was pushed first returns
+    fn run(&mut self) -> Result<Option<Value>, RuntimeErrorWithPosition> {
+        loop {
+            let flow = match self.run_frame() {
+                Ok(flow) => flow,
+                Err((err, pos)) => {
+                    self.unwind(err, pos)?;
+                    continue;
+                }
+            };
+            match flow {
+                Flow::Switch => {}
+                Flow::Return(possible_val) => {
+                    self.pop_frame();
+                    if self.frames.is_empty() {
+                        return Ok(possible_val);
+                    }
+                    self.stack.push(possible_val);
+                }
+                Flow::CallError(err) => {
+                    let frame = self.pop_frame();
+                    if self.frames.is_empty() {
+                        return Err((err, frame.call_pos));
+                    }
+                    self.unwind(err, frame.call_pos)?;
+                }
+            }
+        }
+    }
+
+    // Passes an error at `pos` in the frame on top to the innermost `catch` block,
+    // leaving the frames of the calls it is outside of
+    fn unwind(&mut self,
+              err: RuntimeError,
+              pos: OffsetSpan)
+              -> Result<(), RuntimeErrorWithPosition> {
+        let (mut err, mut pos) = (err, pos);
+        loop {
+            let possible_handler = if err.is_catchable() {
+                self.frames.last_mut().unwrap().handlers.pop()
+            } else {
+                None
+            };
+            if let Some(handler) = possible_handler {
+                self.stack.truncate(handler.stack_len);
+                self.stack.push(Some(caught_error_value(err)));
+                self.frames.last_mut().unwrap().ip = handler.ip;
+                return Ok(());
+            }
+            let frame = self.pop_frame();
+            if frame.closure.proto.is_function {
+                err = add_stack_frame(&frame.closure.proto.maybe_id, (err, pos));
+                pos = frame.call_pos;
+            }
+            if self.frames.is_empty() {
+                return Err((err, pos));
+            }
+        }
+    }
+
+    fn pop_value(&mut self) -> Value {
+        match self.stack.pop() {
+            Some(Some(val)) => val,
+            _ => panic!("expected a value on top of the stack"),
+        }
+    }
+
+    fn pop_values(&mut self, n: usize) -> Vec<Value> {
+        let len = self.stack.len();
+        self.stack
+            .drain(len - n..)
+            .map(|possible_val| possible_val.expect("expected values on top of the stack"))
+            .collect()
+    }
+
+    // Runs the code of the frame on top until it calls, returns or fails
+    fn run_frame(&mut self) -> Result<Flow, RuntimeErrorWithPosition> {
+        let (closure, mut ip, base, slot_base) = {
+            let frame = self.frames.last().unwrap();
+            (frame.closure.clone(), frame.ip, frame.base, frame.slot_base)
+        };
+        let proto = &*closure.proto;
+        loop {
+            let pos = proto.positions[ip];
+            let instruction = &proto.code[ip];
+            ip += 1;
+            match *instruction {
+                Instruction::Constant(idx) => {
+                    self.stack.push(Some(proto.constants[idx as usize].clone()))
+                }
+                Instruction::PushNone => self.stack.push(None),
+                Instruction::Pop => {
+                    self.stack.pop();
+                }
+                Instruction::Nip => {
+                    let len = self.stack.len();
+                    self.stack.swap_remove(len - 2);
+                }
+                Instruction::Dup => {
+                    let top = self.stack.last().unwrap().clone();
+                    self.stack.push(top);
+                }
+                Instruction::Swap => {
+                    let len = self.stack.len();
+                    self.stack.swap(len - 1, len - 2);
+                }
+                Instruction::ExpectValue(possible_name) => {
+                    if let Some(&None) = self.stack.last() {
+                        let fn_name = possible_name.map(|name| proto.names[name as usize].clone());
+                        return Err((RuntimeError::NoneError(fn_name), pos));
+                    }
+                }
+                Instruction::NewCell(slot) => {
+                    let local = Local {
+                        value: None,
+                        binding_type: BindingType::Mutable,
+                        constraint: None,
+                    };
+                    self.slots[slot_base + slot as usize] = Some(Rc::new(RefCell::new(local)));
+                }
+                Instruction::DeclareLocal(slot, declaration) => {
+                    let val = self.pop_value();
+                    let declaration = &proto.declarations[declaration as usize];
+                    check_declaration(declaration, &val)
+                        .map_err(|err| (err, pos))?;
+                    let cell = self.slots[slot_base + slot as usize].as_ref().unwrap();
+                    *cell.borrow_mut() = Local {
+                        value: Some(val),
+                        binding_type: declaration.binding_type.clone(),
+                        constraint: declaration.constraint.clone(),
+                    };
+                }
+                Instruction::LoadLocal(slot, name) => {
+                    let val = {
+                        let cell = self.slots[slot_base + slot as usize].as_ref().unwrap();
+                        load_variable(cell, &proto.names[name as usize])
+                            .map_err(|err| (err, pos))?
+                    };
+                    self.stack.push(Some(val));
+                }
+                Instruction::StoreLocal(slot, name) => {
+                    let val = self.pop_value();
+                    let cell = self.slots[slot_base + slot as usize].as_ref().unwrap();
+                    store_variable(cell, &proto.names[name as usize], val)
+                        .map_err(|err| (err, pos))?;
+                }
+                Instruction::LoadUpvalue(upvalue, name) => {
+                    let val = load_upvalue(&closure, upvalue, &proto.names[name as usize])
+                        .map_err(|err| (err, pos))?;
+                    self.stack.push(Some(val));
+                }
+                Instruction::StoreUpvalue(upvalue, name) => {
+                    let val = self.pop_value();
+                    store_upvalue(&closure, upvalue, &proto.names[name as usize], val)
+                        .map_err(|err| (err, pos))?;
+                }
+                Instruction::LoadGlobal(name) => {
+                    let name = &proto.names[name as usize];
+                    let possible_val = closure.globals.borrow_mut().get_value(name);
+                    match possible_val {
+                        Some(val) => self.stack.push(Some(val)),
+                        None => return Err((RuntimeError::ReferenceError(name.clone()), pos)),
+                    }
+                }
+                Instruction::StoreGlobal(name) => {
+                    let val = self.pop_value();
+                    closure
+                        .globals
+                        .borrow_mut()
+                        .set(&proto.names[name as usize], val)
+                        .map_err(|err| (err, pos))?;
+                }
+                Instruction::DeclareGlobal(declaration) => {
+                    let val = self.pop_value();
+                    let declaration = &proto.declarations[declaration as usize];
+                    check_declaration(declaration, &val)
+                        .map_err(|err| (err, pos))?;
+                    closure
+                        .globals
+                        .borrow_mut()
+                        .declare_binding(&declaration.name,
+                                         &val,
+                                         declaration.binding_type.clone(),
+                                         declaration.constraint.clone());
+                }
+                Instruction::Binary(ref op) => {
+                    let val2 = self.pop_value();
+                    let val1 = self.pop_value();
+                    let val = operations::binary(op, val1, val2)
+                        .map_err(|err| (err, pos))?;
+                    self.stack.push(Some(val));
+                }
+                Instruction::Negate => {
+                    let val = self.pop_value();
+                    let val = operations::unary_minus(val).map_err(|err| (err, pos))?;
+                    self.stack.push(Some(val));
+                }
+                Instruction::Not => {
+                    let val = self.pop_value();
+                    self.stack.push(Some(Value::Bool(!val.is_truthy())));
+                }
+                Instruction::Truthy => {
+                    let val = self.pop_value();
+                    self.stack.push(Some(Value::Bool(val.is_truthy())));
+                }
+                Instruction::Jump(target) => ip = target as usize,
+                Instruction::JumpIfFalse(target) => {
+                    if !self.pop_value().is_truthy() {
+                        ip = target as usize;
+                    }
+                }
+                Instruction::JumpIfTrue(target) => {
+                    if self.pop_value().is_truthy() {
+                        ip = target as usize;
+                    }
+                }
+                Instruction::MakeTuple(n) => {
+                    let values = self.pop_values(n as usize);
+                    self.stack.push(Some(Value::Tuple(values)));
+                }
+                Instruction::MakeList(n) => {
+                    let values = self.pop_values(n as usize);
+                    self.stack
+                        .push(Some(Value::List(Rc::new(RefCell::new(values)))));
+                }
+                Instruction::MakeMap(first_key, n) => {
+                    let values = self.pop_values(n as usize);
+                    let keys = &proto.names[first_key as usize..(first_key + n) as usize];
+                    let mut map = LinearMap::new();
+                    for (key, val) in keys.iter().zip(values) {
+                        map.insert(key.clone(), val);
+                    }
+                    self.stack.push(Some(Value::Map(map)));
+                }
+                Instruction::Index(span) => {
+                    let index = self.pop_value();
+                    let object = self.pop_value();
+                    let val = get_element(&object,
+                                          &index,
+                                          proto.spans[span as usize],
+                                          proto.spans[span as usize + 1],
+                                          pos)?;
+                    self.stack.push(Some(val));
+                }
+                Instruction::GetField(field) => {
+                    let object = self.pop_value();
+                    let val = get_field(&object, &proto.names[field as usize])
+                        .map_err(|err| (err, pos))?;
+                    self.stack.push(Some(val));
+                }
+                Instruction::SetField(field) => {
+                    let mut object = self.pop_value();
+                    let val = self.pop_value();
+                    set_field(&mut object, &proto.names[field as usize], val)
+                        .map_err(|err| (err, pos))?;
+                    self.stack.push(Some(object));
+                }
+                Instruction::SetIndex(span, target) => {
+                    let index = self.pop_value();
+                    let mut object = self.pop_value();
+                    let val = self.pop_value();
+                    let object_pos = proto.spans[span as usize];
+                    let index_pos = proto.spans[span as usize + 1];
+                    match object {
+                        Value::List(ref l) => {
+                            let idx = to_element_index(&index, l.borrow().len(), index_pos, pos)?;
+                            l.borrow_mut()[idx] = val;
+                            // the list is shared, so there is nothing to assign back
+                            ip = target as usize;
+                            continue;
+                        }
+                        Value::Map(ref mut m) => {
+                            match index {
+                                Value::String(key) => {
+                                    m.insert(key, val);
+                                }
+                                non_string_index => {
+                                    return Err((RuntimeError::NonStringMapKey(non_string_index
+                                                                                  .get_type()),
+                                                index_pos));
+                                }
+                            }
+                        }
+                        ref other => {
+                            return Err((RuntimeError::IndexAssignmentOnImmutable(other.get_type()),
+                                        object_pos));
+                        }
+                    }
+                    self.stack.push(Some(object));
+                }
+                Instruction::MakeClosure(proto_idx) => {
+                    let fn_proto = proto.prototypes[proto_idx as usize].clone();
+                    let upvalues = fn_proto
+                        .captures
+                        .iter()
+                        .map(|capture| match *capture {
+                                 Capture::Local(slot) => {
+                                     self.slots[slot_base + slot as usize].clone().unwrap()
+                                 }
+                                 Capture::Upvalue(upvalue) => {
+                                     closure.upvalues[upvalue as usize].clone()
+                                 }
+                             })
+                        .collect();
+                    let new_closure = Closure {
+                        proto: fn_proto,
+                        upvalues: upvalues,
+                        globals: closure.globals.clone(),
+                        modules: closure.modules.clone(),
+                    };
+                    let func = Function::Compiled(Rc::new(new_closure));
+                    self.stack.push(Some(Value::Function(Box::new(func))));
+                }
+                Instruction::Call(site) => {
+                    if let Some(flow) = self.call(proto, site, pos, ip, false)? {
+                        return Ok(flow);
+                    }
+                }
+                Instruction::TailCall(site) => {
+                    if let Some(flow) = self.call(proto, site, pos, ip, true)? {
+                        return Ok(flow);
+                    }
+                }
+                Instruction::Return => {
+                    let possible_val = self.stack.pop().unwrap();
+                    return self.return_from_frame(proto, possible_val, pos);
+                }
+                Instruction::ReturnNone => {
+                    // the function body ended without a return statement
+                    if let Err(err) = check_return_constraint(&proto.ret_constraint, &None) {
+                        return Ok(Flow::CallError(err));
+                    }
+                    return Ok(self.return_flow(None));
+                }
+                Instruction::Throw => {
+                    let val = self.pop_value();
+                    return Err((RuntimeError::Thrown(val), pos));
+                }
+                Instruction::Fail(err) => {
+                    return Err((proto.errors[err as usize].clone(), pos));
+                }
+                Instruction::PushHandler(target) => {
+                    let handler = Handler {
+                        ip: target as usize,
+                        stack_len: self.stack.len(),
+                    };
+                    self.frames.last_mut().unwrap().handlers.push(handler);
+                }
+                Instruction::PopHandler => {
+                    self.frames.last_mut().unwrap().handlers.pop();
+                }
+                Instruction::Leave(depth, keep_top) => {
+                    let possible_top = if keep_top { self.stack.pop() } else { None };
+                    self.stack.truncate(base + depth as usize);
+                    if let Some(top) = possible_top {
+                        self.stack.push(top);
+                    }
+                }
+                Instruction::IntoItems => {
+                    let items = match self.pop_value() {
+                        Value::Tuple(v) => v,
+                        // iterate over the elements the list had when the loop started
+                        Value::List(l) => l.borrow().clone(),
+                        Value::String(s) => {
+                            s.chars()
+                                .map(|c| Value::String(c.to_string()))
+                                .collect()
+                        }
+                        other => {
+                            return Err((RuntimeError::NonIterable(other.get_type()), pos));
+                        }
+                    };
+                    self.stack.push(Some(Value::Tuple(items)));
+                    self.stack.push(Some(Value::Number(Number::Integer(0))));
+                }
+                Instruction::IntoRange(site, target) => {
+                    let site = &proto.call_sites[site as usize];
+                    let fn_idx = self.stack.len() - site.num_positional - site.named.len() - 1;
+                    let is_range = match self.stack[fn_idx] {
+                        Some(Value::Function(ref func)) => is_native_range(func),
+                        _ => false,
+                    };
+                    // other calls of `range` fail in the call itself
+                    let num_args = site.num_positional;
+                    if is_range && site.named.is_empty() && (num_args == 2 || num_args == 3) {
+                        let args: Vec<Value> =
+                            self.stack.drain(fn_idx + 1..).map(Option::unwrap).collect();
+                        self.stack.pop();
+                        let range = Range::from_args(&args).map_err(|err| (err, pos))?;
+                        self.stack.push(None);
+                        self.stack.push(Some(range.to_tuple()));
+                        ip = target as usize;
+                    }
+                }
+                Instruction::ForNext(target) => {
+                    let len = self.stack.len();
+                    let possible_item = match self.stack[len - 2] {
+                        Some(Value::Tuple(ref items)) => {
+                            let idx = match self.stack[len - 1] {
+                                Some(Value::Number(Number::Integer(idx))) => idx,
+                                _ => unreachable!(),
+                            };
+                            let possible_item = items.get(idx as usize).cloned();
+                            if possible_item.is_some() {
+                                self.stack[len - 1] =
+                                    Some(Value::Number(Number::Integer(idx + 1)));
+                            }
+                            possible_item
+                        }
+                        // a range that `IntoRange` counts through
+                        None => {
+                            let mut range = match self.stack[len - 1] {
+                                Some(ref state) => Range::from_tuple(state),
+                                None => unreachable!(),
+                            };
+                            let possible_i = range.next();
+                            self.stack[len - 1] = Some(range.to_tuple());
+                            possible_i.map(|i| Value::Number(Number::Integer(i)))
+                        }
+                        _ => unreachable!(),
+                    };
+                    match possible_item {
+                        Some(item) => self.stack.push(Some(item)),
+                        None => ip = target as usize,
+                    }
+                }
+                Instruction::Destructure(pattern) => {
+                    let val = self.pop_value();
+                    let mut bindings = Vec::new();
+                    if !match_pattern(&proto.patterns[pattern as usize], &val, &mut bindings) {
+                        return Err((RuntimeError::NoMatchingPattern(val.get_type()), pos));
+                    }
+                    self.stack
+                        .extend(bindings.into_iter().map(|(_, _, val)| Some(val)));
+                }
+                Instruction::MatchArm(pattern, target) => {
+                    let mut bindings = Vec::new();
+                    let is_match = match *self.stack.last().unwrap() {
+                        Some(ref val) => {
+                            match_pattern(&proto.patterns[pattern as usize], val, &mut bindings)
+                        }
+                        None => unreachable!(),
+                    };
+                    if is_match {
+                        self.stack
+                            .extend(bindings.into_iter().map(|(_, _, val)| Some(val)));
+                    } else {
+                        ip = target as usize;
+                    }
+                }
+                Instruction::NoMatch => {
+                    let val = self.pop_value();
+                    return Err((RuntimeError::NoMatchingPattern(val.get_type()), pos));
+                }
+                Instruction::TryUnwrap(span) => {
+                    match self.pop_value() {
+                        Value::Result(Ok(ok_val)) => self.stack.push(Some(*ok_val)),
+                        val @ Value::Result(Err(_)) => {
+                            if !proto.is_function {
+                                return Err((RuntimeError::ReturnOutsideFunction, pos));
+                            }
+                            // the `Err` is returned from the function
+                            return self.return_from_frame(proto, Some(val), pos);
+                        }
+                        other => {
+                            return Err((RuntimeError::TryOnNonResult(other.get_type()),
+                                        proto.spans[span as usize]));
+                        }
+                    }
+                }
+                Instruction::Import(path) => {
+                    let module = self.import(&proto.names[path as usize])
+                        .map_err(|err| (err, pos))?;
+                    self.stack.push(Some(module));
+                }
+                Instruction::JumpIfArg(param, target) => {
+                    if let Some(&Some(_)) = self.frames
+                           .last()
+                           .unwrap()
+                           .args
+                           .get(param as usize) {
+                        ip = target as usize;
+                    }
+                }
+                Instruction::SetArg(param) => {
+                    let val = self.pop_value();
+                    let args = &mut self.frames.last_mut().unwrap().args;
+                    if param as usize >= args.len() {
+                        args.resize(param as usize + 1, None);
+                    }
+                    args[param as usize] = Some(val);
+                }
+                Instruction::BindParam(param, slot) => {
+                    let param_idx = param as usize;
+                    let param = &proto.params[param_idx];
+                    let possible_arg = {
+                        let args = &mut self.frames.last_mut().unwrap().args;
+                        if param.is_rest {
+                            let rest = if param_idx < args.len() {
+                                args.drain(param_idx..).flatten().collect()
+                            } else {
+                                Vec::new()
+                            };
+                            Some(Value::Tuple(rest))
+                        } else {
+                            args.get_mut(param_idx).and_then(|arg| arg.take())
+                        }
+                    };
+                    let arg = match possible_arg {
+                        Some(arg) => arg,
+                        None => {
+                            let err = RuntimeError::MissingArgument(param.name.clone());
+                            return Ok(Flow::CallError(err));
+                        }
+                    };
+                    if let Some(ref constraint) = param.constraint {
+                        if !arg.get_type().satisfies_constraint(constraint) {
+                            let err = RuntimeError::BindingTypeError(param.name.clone(),
+                                                                     constraint.clone(),
+                                                                     arg.get_type());
+                            return Ok(Flow::CallError(err));
+                        }
+                    }
+                    let cell = self.slots[slot_base + slot as usize].as_ref().unwrap();
+                    *cell.borrow_mut() = Local {
+                        value: Some(arg),
+                        binding_type: BindingType::Mutable,
+                        constraint: param.constraint.clone(),
+                    };
+                }
+            }
+        }
+    }
+
+    // Calls the function below the arguments of the call site on the stack. Calls
+    // of compiled functions push a frame, while other functions are called here.
+    fn call(&mut self,
+            proto: &Prototype,
+            site: u32,
+            pos: OffsetSpan,
+            ip: usize,
+            is_tail_call: bool)
+            -> Result<Option<Flow>, RuntimeErrorWithPosition> {
+        let site = &proto.call_sites[site as usize];
+        let num_args = site.num_positional + site.named.len();
+        let fn_idx = self.stack.len() - num_args - 1;
+        let func = match self.stack[fn_idx].take() {
+            Some(Value::Function(func)) => func,
+            Some(val) => {
+                return Err((RuntimeError::CallToNonFunction(site.fn_name.clone(), val.get_type()),
+                            site.fn_pos));
+            }
+            None => unreachable!(),
+        };
+        if !func.get_call_sign().accepts(num_args) {
+            return Err((RuntimeError::ArgumentLength(site.fn_name.clone()), pos));
+        }
+        let mut args: Vec<Option<Value>> = self.stack.drain(fn_idx + 1..).collect();
+        self.stack.pop();
+        if !site.named.is_empty() {
+            args = arrange_named_args(&func, args, site).map_err(|err| (err, pos))?;
+        }
+        let call_depth = self.frames.last().unwrap().call_depth;
+        if !is_tail_call && call_depth >= self.max_call_depth {
+            return Err((RuntimeError::StackOverflow(self.max_call_depth), pos));
+        }
+        if let Function::Compiled(ref callee) = *func {
+            if is_tail_call {
+                // the call is made in place of the current one, so that tail
+                // recursion does not grow the stack
+                let frame = self.pop_frame();
+                let mut pending_ret_constraints = frame.pending_ret_constraints;
+                if let Some(ref constraint) = proto.ret_constraint {
+                    if pending_ret_constraints.last() != Some(constraint) {
+                        pending_ret_constraints.push(constraint.clone());
+                    }
+                }
+                self.push_frame(callee.clone(), args, frame.call_pos, frame.call_depth);
+                self.frames.last_mut().unwrap().pending_ret_constraints = pending_ret_constraints;
+            } else {
+                self.frames.last_mut().unwrap().ip = ip;
+                self.push_frame(callee.clone(), args, pos, call_depth + 1);
+            }
+            return Ok(Some(Flow::Switch));
+        }
+        let possible_val =
+            call_func_with_arranged_args(&func, args, call_depth, self.max_call_depth)
+                .map_err(|err| (err, pos))?;
+        if is_tail_call {
+            return self.return_from_frame(proto, possible_val, pos).map(Some);
+        }
+        self.stack.push(possible_val);
+        Ok(None)
+    }
+
+    fn return_from_frame(&self,
+                         proto: &Prototype,
+                         possible_val: Option<Value>,
+                         pos: OffsetSpan)
+                         -> Result<Flow, RuntimeErrorWithPosition> {
+        check_return_constraint(&proto.ret_constraint, &possible_val)
+            .map_err(|err| (err, pos))?;
+        Ok(self.return_flow(possible_val))
+    }
+
+    // Returns from the frame on top, unless the value does not satisfy the return
+    // annotations of the functions the frame was a tail call of
+    fn return_flow(&self, possible_val: Option<Value>) -> Flow {
+        let frame = self.frames.last().unwrap();
+        for constraint in &frame.pending_ret_constraints {
+            if let Err(err) = check_return_constraint(&Some(constraint.clone()), &possible_val) {
+                return Flow::CallError(err);
+            }
+        }
+        Flow::Return(possible_val)
+    }
+
+    fn import(&mut self, import_path: &str) -> Result<Value, RuntimeError> {
+        let module_path = module::resolve_import(&self.file, import_path)?;
+        let possible_module = self.modules.borrow().get(&module_path);
+        if let Some(module) = possible_module {
+            return Ok(module);
+        }
+        self.modules.borrow_mut().start_loading(&module_path)?;
+        let result = self.load_module(&module_path);
+        self.modules
+            .borrow_mut()
+            .finish_loading(&module_path, result.as_ref().ok().cloned());
+        result
+    }
+
+    fn load_module(&self, module_path: &ModulePath) -> Result<Value, RuntimeError> {
+        let ast = module::parse_module(module_path)?;
+        let module_env = Environment::create_child(Environment::new_root());
+        let mut machine = BytecodeInterpreter {
+            globals: module_env.clone(),
+            file: Some(module_path.display_path.clone()),
+            modules: self.modules.clone(),
+            max_call_depth: self.max_call_depth,
+        };
+        if let Err(e) = machine.run_statements(&ast, true) {
+            return Err(RuntimeError::InsideModule(module_path.display_path.clone(), Box::new(e)));
+        }
+        Ok(Value::Module(module_path.display_path.clone(), module_env))
+    }
+}
+
+fn check_declaration(declaration: &Declaration, val: &Value) -> Result<(), RuntimeError> {
+    if let Some(ref constraint) = declaration.constraint {
+        if !val.get_type().satisfies_constraint(constraint) {
+            return Err(RuntimeError::BindingTypeError(declaration.name.clone(),
+                                                      constraint.clone(),
+                                                      val.get_type()));
+        }
+    }
+    Ok(())
+}
+
+fn load_variable(cell: &LocalCell, name: &str) -> Result<Value, RuntimeError> {
+    match cell.borrow().value {
+        Some(ref val) => Ok(val.clone()),
+        None => Err(RuntimeError::ReferenceError(name.to_owned())),
+    }
+}
+
+// Loads a variable captured by the closure, or the variable its fallback refers
+// to while its declaration has not run
+fn load_upvalue(closure: &Closure, upvalue: u32, name: &str) -> Result<Value, RuntimeError> {
+    let cell = &closure.upvalues[upvalue as usize];
+    if cell.borrow().value.is_none() {
+        match closure.proto.fallbacks[upvalue as usize] {
+            Some(Fallback::Upvalue(fallback)) => return load_upvalue(closure, fallback, name),
+            Some(Fallback::Global) => {
+                return match closure.globals.borrow_mut().get_value(name) {
+                           Some(val) => Ok(val),
+                           None => Err(RuntimeError::ReferenceError(name.to_owned())),
+                       };
+            }
+            None => {}
+        }
+    }
+    load_variable(cell, name)
+}
+
+fn store_upvalue(closure: &Closure,
+                 upvalue: u32,
+                 name: &str,
+                 val: Value)
+                 -> Result<(), RuntimeError> {
+    let cell = &closure.upvalues[upvalue as usize];
+    if cell.borrow().value.is_none() {
+        match closure.proto.fallbacks[upvalue as usize] {
+            Some(Fallback::Upvalue(fallback)) => return store_upvalue(closure, fallback, name, val),
+            Some(Fallback::Global) => return closure.globals.borrow_mut().set(name, val),
+            None => {}
+        }
+    }
+    store_variable(cell, name, val)
+}
+
+fn store_variable(cell: &LocalCell, name: &str, val: Value) -> Result<(), RuntimeError> {
+    let mut local = cell.borrow_mut();
+    if local.value.is_none() {
+        return Err(RuntimeError::UndeclaredAssignment(name.to_owned()));
+    }
+    if let BindingType::Immutable = local.binding_type {
+        return Err(RuntimeError::ImmutableAssignment(name.to_owned()));
+    }
+    if let Some(ref constraint) = local.constraint {
+        if !val.get_type().satisfies_constraint(constraint) {
+            return Err(RuntimeError::BindingTypeError(name.to_owned(),
+                                                      constraint.clone(),
+                                                      val.get_type()));
+        }
+    }
+    local.value = Some(val);
+    Ok(())
+}
+
+// Puts every named argument, which follow the positional arguments, at the
+// position of the parameter with its name
+fn arrange_named_args(func: &Function,
+                      mut args: Vec<Option<Value>>,
+                      site: &CallSite)
+                      -> Result<Vec<Option<Value>>, RuntimeError> {
+    let named_arg_vals = args.split_off(site.num_positional);
+    let param_names = func.get_param_names();
+    for (name, val) in site.named.iter().zip(named_arg_vals) {
+        let idx = match param_names.iter().position(|param| param == name) {
+            Some(idx) => idx,
+            None => {
+                return Err(RuntimeError::UnknownParameter(site.fn_name.clone(), name.clone()));
+            }
+        };
+        if idx >= args.len() {
+            args.resize(idx + 1, None);
+        }
+        if args[idx].is_some() {
+            return Err(RuntimeError::DuplicateArgument(name.clone()));
+        }
+        args[idx] = val;
+    }
+    Ok(args)
+}
+
+/// Calls a compiled function from outside of the VM, like from a native function,
+/// with an argument for every parameter in order, or `None` for the parameters
+/// that get their default value. `call_depth` is the depth of the call itself,
+/// counting the calls it is nested in
+pub fn call_closure(closure: &Rc<Closure>,
+                    args: Vec<Option<Value>>,
+                    call_depth: usize,
+                    max_call_depth: usize)
+                    -> Result<Option<Value>, RuntimeError> {
+    // imports only appear at the top level, so function bodies never load modules
+    // and the file they are relative to is not needed
+    let mut vm = Vm::new(None, closure.modules.clone(), max_call_depth);
+    vm.push_frame(closure.clone(), args, (0, 0), call_depth);
+    vm.run().map_err(|(err, _)| err)
+}
+
+/// Runs programs by compiling them to bytecode for a stack-based virtual machine,
+/// where the variables of functions and blocks are kept in slots of their frame
+pub struct BytecodeInterpreter {
+    /// The top-level variables of the program, which are looked up by name
+    globals: Rc<RefCell<Environment>>,
+    // the file being run, which imports are relative to
+    file: Option<String>,
+    modules: Rc<RefCell<ModuleLoader<Value>>>,
+    max_call_depth: usize,
+}
+
+impl BytecodeInterpreter {
+    pub fn new() -> BytecodeInterpreter {
+        BytecodeInterpreter {
+            globals: Environment::new_root(),
+            file: None,
+            modules: Rc::new(RefCell::new(ModuleLoader::new(&None))),
+            max_call_depth: DEFAULT_MAX_CALL_DEPTH,
+        }
+    }
+
+    pub fn for_file(file_name: &str) -> BytecodeInterpreter {
+        let file = Some(file_name.to_owned());
+        BytecodeInterpreter {
+            globals: Environment::new_root(),
+            modules: Rc::new(RefCell::new(ModuleLoader::new(&file))),
+            file: file,
+            max_call_depth: DEFAULT_MAX_CALL_DEPTH,
+        }
+    }
+
+    /// Sets the number of nested function calls after which a call fails with
+    /// `RuntimeError::StackOverflow`
+    pub fn set_max_call_depth(&mut self, max_call_depth: usize) {
+        self.max_call_depth = max_call_depth;
+    }
+
+    // Runs a program, or statements of the REPL, which can refer to globals
+    // that are declared by later input
+    fn run_statements(&mut self,
+                      statements: &[StmtNode],
+                      is_program: bool)
+                      -> Result<Option<StmtResult>, RuntimeErrorWithPosition> {
+        if statements.is_empty() {
+            return Ok(None);
+        }
+        let proto = if is_program {
+            compile_program(statements, Some(&self.globals.borrow()))?
+        } else {
+            compile_program(statements, None)?
+        };
+        let closure = Closure {
+            proto: Rc::new(proto),
+            upvalues: Vec::new(),
+            globals: self.globals.clone(),
+            modules: self.modules.clone(),
+        };
+        let mut vm = Vm::new(self.file.clone(), self.modules.clone(), self.max_call_depth);
+        vm.push_frame(Rc::new(closure), Vec::new(), (0, 0), 0);
+        match vm.run()? {
+            Some(val) => Ok(Some(StmtResult::Value(val))),
+            None => Ok(Some(StmtResult::None)),
+        }
+    }
+}
+
+impl Interpreter for BytecodeInterpreter {
+    fn run_ast_as_statements(&mut self,
+                             statements: &[StmtNode])
+                             -> Result<Option<StmtResult>, RuntimeErrorWithPosition> {
+        self.run_statements(statements, false)
+    }
+
+    fn run_ast_as_program(&mut self,
+                          program: &[StmtNode])
+                          -> Result<Option<StmtResult>, RuntimeErrorWithPosition> {
+        self.run_statements(program, true)
+    }
+}
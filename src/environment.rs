@@ -1,15 +1,44 @@
 use std::rc::Rc;
 use std::cell::RefCell;
+use std::collections::BTreeMap;
+use std::f64;
 
 use fnv::FnvHashMap;
 
+use ast::{BindingType, ConstraintType};
 use value::*;
 use function::*;
+use runtime::RuntimeError;
+use typechecker::Type;
+
+#[derive(Debug)]
+struct Binding {
+    value: Value,
+    binding_type: BindingType,
+    constraint: Option<ConstraintType>,
+}
+
+impl Binding {
+    fn set(&mut self, identifier: &str, value: Value) -> Result<(), RuntimeError> {
+        if let BindingType::Immutable = self.binding_type {
+            return Err(RuntimeError::ImmutableAssignment(identifier.to_owned()));
+        }
+        if let Some(ref constraint) = self.constraint {
+            if !value.get_type().satisfies_constraint(constraint) {
+                return Err(RuntimeError::BindingTypeError(identifier.to_owned(),
+                                                          constraint.clone(),
+                                                          value.get_type()));
+            }
+        }
+        self.value = value;
+        Ok(())
+    }
+}
 
 #[derive(Debug)]
 pub struct Environment {
     parent: Option<Rc<RefCell<Environment>>>,
-    symbol_table: FnvHashMap<String, Value>,
+    symbol_table: FnvHashMap<String, Binding>,
 }
 
 // Testing to see if linked list Env system works
@@ -25,39 +54,302 @@ impl Environment {
         let builtin_functions = &[
             ("println",
              Function::NativeVoid(CallSign {
-                                      num_params: 0,
-                                      variadic: true,
+                                      min_params: 0,
+                                      max_params: None,
                                   },
                                   native_println)),
             ("assert",
              Function::NativeVoid(CallSign {
-                                      num_params: 1,
-                                      variadic: false,
+                                      min_params: 1,
+                                      max_params: Some(1),
                                   },
                                   native_assert)),
             ("assert_eq",
              Function::NativeVoid(CallSign {
-                                      num_params: 2,
-                                      variadic: false,
+                                      min_params: 2,
+                                      max_params: Some(2),
                                   },
                                   native_assert_eq)),
             ("run_http_server",
              Function::NativeVoid(CallSign {
-                                      num_params: 1,
-                                      variadic: false,
+                                      min_params: 1,
+                                      max_params: Some(1),
                                   },
                                   native_run_http_server)),
             ("len",
              Function::NativeReturning(CallSign {
-                                           num_params: 1,
-                                           variadic: false,
+                                           min_params: 1,
+                                           max_params: Some(1),
                                        },
                                        native_len)),
+            ("push",
+             Function::NativeVoid(CallSign {
+                                      min_params: 2,
+                                      max_params: Some(2),
+                                  },
+                                  native_push)),
+            ("pop",
+             Function::NativeReturning(CallSign {
+                                           min_params: 1,
+                                           max_params: Some(1),
+                                       },
+                                       native_pop)),
+            ("insert",
+             Function::NativeVoid(CallSign {
+                                      min_params: 3,
+                                      max_params: Some(3),
+                                  },
+                                  native_insert)),
+            ("remove",
+             Function::NativeReturning(CallSign {
+                                           min_params: 2,
+                                           max_params: Some(2),
+                                       },
+                                       native_remove)),
+            ("range",
+             Function::NativeReturning(CallSign {
+                                           min_params: 2,
+                                           max_params: Some(3),
+                                       },
+                                       native_range)),
+            ("split",
+             Function::NativeReturning(CallSign {
+                                           min_params: 2,
+                                           max_params: Some(2),
+                                       },
+                                       native_split)),
+            ("join",
+             Function::NativeReturning(CallSign {
+                                           min_params: 2,
+                                           max_params: Some(2),
+                                       },
+                                       native_join)),
+            ("trim",
+             Function::NativeReturning(CallSign {
+                                           min_params: 1,
+                                           max_params: Some(1),
+                                       },
+                                       native_trim)),
+            ("replace",
+             Function::NativeReturning(CallSign {
+                                           min_params: 3,
+                                           max_params: Some(3),
+                                       },
+                                       native_replace)),
+            ("find",
+             Function::NativeReturning(CallSign {
+                                           min_params: 2,
+                                           max_params: Some(2),
+                                       },
+                                       native_find)),
+            ("starts_with",
+             Function::NativeReturning(CallSign {
+                                           min_params: 2,
+                                           max_params: Some(2),
+                                       },
+                                       native_starts_with)),
+            ("ends_with",
+             Function::NativeReturning(CallSign {
+                                           min_params: 2,
+                                           max_params: Some(2),
+                                       },
+                                       native_ends_with)),
+            ("to_upper",
+             Function::NativeReturning(CallSign {
+                                           min_params: 1,
+                                           max_params: Some(1),
+                                       },
+                                       native_to_upper)),
+            ("to_lower",
+             Function::NativeReturning(CallSign {
+                                           min_params: 1,
+                                           max_params: Some(1),
+                                       },
+                                       native_to_lower)),
+            ("substring",
+             Function::NativeReturning(CallSign {
+                                           min_params: 3,
+                                           max_params: Some(3),
+                                       },
+                                       native_substring)),
+            ("parse_int",
+             Function::NativeReturning(CallSign {
+                                           min_params: 1,
+                                           max_params: Some(1),
+                                       },
+                                       native_parse_int)),
+            ("parse_float",
+             Function::NativeReturning(CallSign {
+                                           min_params: 1,
+                                           max_params: Some(1),
+                                       },
+                                       native_parse_float)),
+            ("abs",
+             Function::NativeReturning(CallSign {
+                                           min_params: 1,
+                                           max_params: Some(1),
+                                       },
+                                       native_abs)),
+            ("floor",
+             Function::NativeReturning(CallSign {
+                                           min_params: 1,
+                                           max_params: Some(1),
+                                       },
+                                       native_floor)),
+            ("ceil",
+             Function::NativeReturning(CallSign {
+                                           min_params: 1,
+                                           max_params: Some(1),
+                                       },
+                                       native_ceil)),
+            ("round",
+             Function::NativeReturning(CallSign {
+                                           min_params: 1,
+                                           max_params: Some(1),
+                                       },
+                                       native_round)),
+            ("sqrt",
+             Function::NativeReturning(CallSign {
+                                           min_params: 1,
+                                           max_params: Some(1),
+                                       },
+                                       native_sqrt)),
+            ("pow",
+             Function::NativeReturning(CallSign {
+                                           min_params: 2,
+                                           max_params: Some(2),
+                                       },
+                                       native_pow)),
+            ("min",
+             Function::NativeReturning(CallSign {
+                                           min_params: 2,
+                                           max_params: None,
+                                       },
+                                       native_min)),
+            ("max",
+             Function::NativeReturning(CallSign {
+                                           min_params: 2,
+                                           max_params: None,
+                                       },
+                                       native_max)),
+            ("sin",
+             Function::NativeReturning(CallSign {
+                                           min_params: 1,
+                                           max_params: Some(1),
+                                       },
+                                       native_sin)),
+            ("cos",
+             Function::NativeReturning(CallSign {
+                                           min_params: 1,
+                                           max_params: Some(1),
+                                       },
+                                       native_cos)),
+            ("tan",
+             Function::NativeReturning(CallSign {
+                                           min_params: 1,
+                                           max_params: Some(1),
+                                       },
+                                       native_tan)),
+            ("asin",
+             Function::NativeReturning(CallSign {
+                                           min_params: 1,
+                                           max_params: Some(1),
+                                       },
+                                       native_asin)),
+            ("acos",
+             Function::NativeReturning(CallSign {
+                                           min_params: 1,
+                                           max_params: Some(1),
+                                       },
+                                       native_acos)),
+            ("atan",
+             Function::NativeReturning(CallSign {
+                                           min_params: 1,
+                                           max_params: Some(1),
+                                       },
+                                       native_atan)),
+            ("atan2",
+             Function::NativeReturning(CallSign {
+                                           min_params: 2,
+                                           max_params: Some(2),
+                                       },
+                                       native_atan2)),
+            ("int",
+             Function::NativeReturning(CallSign {
+                                           min_params: 1,
+                                           max_params: Some(1),
+                                       },
+                                       native_int)),
+            ("float",
+             Function::NativeReturning(CallSign {
+                                           min_params: 1,
+                                           max_params: Some(1),
+                                       },
+                                       native_float)),
+            ("is_integer",
+             Function::NativeReturning(CallSign {
+                                           min_params: 1,
+                                           max_params: Some(1),
+                                       },
+                                       native_is_integer)),
+            ("Ok",
+             Function::NativeReturning(CallSign {
+                                           min_params: 1,
+                                           max_params: Some(1),
+                                       },
+                                       native_ok)),
+            ("Err",
+             Function::NativeReturning(CallSign {
+                                           min_params: 1,
+                                           max_params: Some(1),
+                                       },
+                                       native_err)),
+            ("is_ok",
+             Function::NativeReturning(CallSign {
+                                           min_params: 1,
+                                           max_params: Some(1),
+                                       },
+                                       native_is_ok)),
+            ("is_err",
+             Function::NativeReturning(CallSign {
+                                           min_params: 1,
+                                           max_params: Some(1),
+                                       },
+                                       native_is_err)),
+            ("unwrap",
+             Function::NativeReturning(CallSign {
+                                           min_params: 1,
+                                           max_params: Some(1),
+                                       },
+                                       native_unwrap)),
+            ("unwrap_err",
+             Function::NativeReturning(CallSign {
+                                           min_params: 1,
+                                           max_params: Some(1),
+                                       },
+                                       native_unwrap_err)),
         ];
         for item in builtin_functions.iter() {
             let (name, ref func) = *item;
             env.declare(&name.to_string(), &Value::Function(Box::new(func.clone())));
         }
+        // errors caught by `catch` are instances of this struct
+        let error_constructor = Function::StructConstructor {
+            call_sign: CallSign {
+                min_params: 2,
+                max_params: Some(2),
+            },
+            name: "Error".to_owned(),
+            fields: vec!["kind".to_owned(), "message".to_owned()],
+        };
+        env.declare("Error", &Value::Function(Box::new(error_constructor)));
+        let constants = &[("PI", f64::consts::PI), ("E", f64::consts::E)];
+        for &(name, value) in constants.iter() {
+            env.declare_binding(name,
+                                &Value::Number(Number::Float(value)),
+                                BindingType::Immutable,
+                                None);
+        }
         Rc::new(RefCell::new(env))
     }
 
@@ -77,27 +369,62 @@ impl Environment {
     }
 
     pub fn declare(&mut self, identifier: &str, value: &Value) {
+        self.declare_binding(identifier, value, BindingType::Mutable, None);
+    }
+
+    pub fn declare_binding(&mut self,
+                           identifier: &str,
+                           value: &Value,
+                           binding_type: BindingType,
+                           constraint: Option<ConstraintType>) {
+        let binding = Binding {
+            value: value.clone(),
+            binding_type: binding_type,
+            constraint: constraint,
+        };
+        self.symbol_table.insert(identifier.to_owned(), binding);
+    }
+
+    pub fn set(&mut self, identifier: &str, value: Value) -> Result<(), RuntimeError> {
+        if let Some(binding) = self.symbol_table.get_mut(identifier) {
+            return binding.set(identifier, value);
+        }
+        match self.parent {
+            Some(ref parent) => parent.borrow_mut().set(identifier, value),
+            None => Err(RuntimeError::UndeclaredAssignment(identifier.to_owned())),
+        }
+    }
+
+    // TODO: Why &mut?
+    /// Looks up a binding in this environment only, ignoring its parents
+    pub fn get_own_value(&self, identifier: &str) -> Option<Value> {
         self.symbol_table
-            .insert(identifier.to_owned(), value.clone());
+            .get(identifier)
+            .map(|binding| binding.value.clone())
     }
 
-    pub fn set(&mut self, identifier: &str, value: Value) -> bool {
-        // TODO: Entry API
+    /// Whether this environment or one of its parents has a binding of `identifier`
+    pub fn contains(&self, identifier: &str) -> bool {
         if self.symbol_table.contains_key(identifier) {
-            self.symbol_table.insert(identifier.to_owned(), value);
-            true
-        } else {
-            match self.parent {
-                Some(ref parent) => parent.borrow_mut().set(identifier, value),
-                None => false,
-            }
+            return true;
+        }
+        match self.parent {
+            Some(ref parent) => parent.borrow().contains(identifier),
+            None => false,
         }
     }
 
-    // TODO: Why &mut?
+    /// The types of the bindings in this environment, ignoring its parents
+    pub fn get_own_types(&self) -> BTreeMap<String, Type> {
+        self.symbol_table
+            .iter()
+            .map(|(id, binding)| (id.clone(), binding.value.get_type()))
+            .collect()
+    }
+
     pub fn get_value(&mut self, identifier: &str) -> Option<Value> {
-        if let Some(val) = self.symbol_table.get(identifier) {
-            return Some(val.clone());
+        if let Some(binding) = self.symbol_table.get(identifier) {
+            return Some(binding.value.clone());
         } else {
             match self.parent {
                 Some(ref parent) => parent.borrow_mut().get_value(identifier),
@@ -106,3 +433,98 @@ impl Environment {
         }
     }
 }
+
+/// The variables of a block or function call, kept in the slots that the
+/// resolver gave them. Top-level variables are kept in an `Environment` instead.
+#[derive(Debug)]
+pub struct Frame {
+    parent: Option<Rc<RefCell<Frame>>>,
+    // the names and bindings of the variables, or `None` for the variables
+    // whose declaration has not run yet
+    slots: Vec<Option<(String, Binding)>>,
+}
+
+impl Frame {
+    pub fn create_child(parent: &Option<Rc<RefCell<Frame>>>,
+                        num_slots: usize)
+                        -> Rc<RefCell<Frame>> {
+        let frame = Frame {
+            parent: parent.clone(),
+            slots: (0..num_slots).map(|_| None).collect(),
+        };
+        Rc::new(RefCell::new(frame))
+    }
+
+    pub fn declare_binding(&mut self,
+                           slot: usize,
+                           identifier: &str,
+                           value: &Value,
+                           binding_type: BindingType,
+                           constraint: Option<ConstraintType>) {
+        let binding = Binding {
+            value: value.clone(),
+            binding_type: binding_type,
+            constraint: constraint,
+        };
+        self.slots[slot] = Some((identifier.to_owned(), binding));
+    }
+
+    /// Finds the innermost declared variable named `identifier`, as the number
+    /// of frames out from this one and its slot in that frame
+    pub fn find(&self, identifier: &str) -> Option<(usize, usize)> {
+        let possible_slot = self.slots
+            .iter()
+            .rposition(|slot| match *slot {
+                           Some((ref name, _)) => name == identifier,
+                           None => false,
+                       });
+        if let Some(slot) = possible_slot {
+            return Some((0, slot));
+        }
+        match self.parent {
+            Some(ref parent) => {
+                parent
+                    .borrow()
+                    .find(identifier)
+                    .map(|(depth, slot)| (depth + 1, slot))
+            }
+            None => None,
+        }
+    }
+
+    /// Assigns to the variable in `slot` of the frame `depth` frames out from
+    /// this one, which is named `identifier`
+    pub fn set(&mut self,
+               depth: usize,
+               slot: usize,
+               identifier: &str,
+               value: Value)
+               -> Result<(), RuntimeError> {
+        if depth > 0 {
+            return self.parent
+                       .as_ref()
+                       .unwrap()
+                       .borrow_mut()
+                       .set(depth - 1, slot, identifier, value);
+        }
+        match self.slots[slot] {
+            Some((_, ref mut binding)) => binding.set(identifier, value),
+            None => Err(RuntimeError::UndeclaredAssignment(identifier.to_owned())),
+        }
+    }
+
+    /// Looks up the variable in `slot` of the frame `depth` frames out from this
+    /// one, if it has been declared
+    pub fn get_value(&self, depth: usize, slot: usize) -> Option<Value> {
+        if depth > 0 {
+            return self.parent
+                       .as_ref()
+                       .unwrap()
+                       .borrow()
+                       .get_value(depth - 1, slot);
+        }
+        self.slots[slot]
+            .as_ref()
+            .map(|&(_, ref binding)| binding.value.clone())
+    }
+}
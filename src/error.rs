@@ -96,7 +96,7 @@ fn offset_to_line_and_col(input: &str, pos: usize) -> (usize, usize) {
     (line_num, remaining + 1)
 }
 
-use runtime::RuntimeError;
+use runtime::{RuntimeError, StackFrame};
 use typechecker::TypeCheckerIssue;
 
 fn adjust_source_span(span: &mut SourceSpan, file_content: &str) {
@@ -123,12 +123,235 @@ pub fn print_interpreter_error_for_file(err: RuntimeError,
                                      file_name);
 }
 
+/// The title and message a runtime error is reported with. Errors that wrap
+/// another error are described by the error they wrap.
+pub fn describe_runtime_error(err: &RuntimeError) -> (&'static str, String) {
+    match *err {
+        RuntimeError::ReferenceError(ref id) => {
+            ("reference error", format!("`{}` was not declared", id))
+        }
+        RuntimeError::UndeclaredAssignment(ref id) => {
+            ("reference error", format!("cannot assign to undeclared `{}`", id))
+        }
+        RuntimeError::ImmutableAssignment(ref id) => {
+            ("immutable assignment", format!("cannot assign twice to immutable `{}`", id))
+        }
+        RuntimeError::BinaryTypeError(ref binary_op, ref type1, ref type2) => {
+            ("type error",
+             format!("`{}` cannot operate on types {} and {}",
+                     binary_op,
+                     type1,
+                     type2))
+        }
+        RuntimeError::UnaryTypeError(ref unary_op, ref typ) => {
+            ("type error", format!("`{}` cannot operate on type {}", unary_op, typ))
+        }
+        RuntimeError::NoneError(ref possible_id) => {
+            match *possible_id {
+                Some(ref id) => {
+                    ("missing value error",
+                     format!("tried to use return value of non-returning function `{}`", id))
+                }
+                None => {
+                    ("missing value error",
+                     "tried to use the value of an expression that did not produce one"
+                         .to_owned())
+                }
+            }
+        }
+        RuntimeError::CallToNonFunction(ref possible_id, ref other_type) => {
+            match *possible_id {
+                Some(ref id) => {
+                    ("type error", format!("cannot call `{}` ({}) as Function", id, other_type))
+                }
+                None => ("type error", format!("cannot call {} as Function", other_type)),
+            }
+        }
+        RuntimeError::ArgumentLength(ref possible_id) => {
+            match *possible_id {
+                Some(ref id) => {
+                    ("arguments mismatch",
+                     format!("function `{}` called with incorrect number of arguments", id))
+                }
+                None => {
+                    ("arguments mismatch",
+                     "function called with incorrect number of arguments".to_owned())
+                }
+            }
+        }
+        RuntimeError::UnknownParameter(ref possible_id, ref param) => {
+            match *possible_id {
+                Some(ref id) => {
+                    ("arguments mismatch",
+                     format!("function `{}` has no parameter named `{}`", id, param))
+                }
+                None => {
+                    ("arguments mismatch", format!("function has no parameter named `{}`", param))
+                }
+            }
+        }
+        RuntimeError::DuplicateArgument(ref param) => {
+            ("arguments mismatch", format!("parameter `{}` is given more than one argument", param))
+        }
+        RuntimeError::MissingArgument(ref param) => {
+            ("arguments mismatch", format!("no argument is given for parameter `{}`", param))
+        }
+        RuntimeError::RequiredParameterAfterDefault(ref param) => {
+            ("parameter order",
+             format!("parameter `{}` needs a default value, since a parameter before it has one",
+                     param))
+        }
+        RuntimeError::ArgumentTypeError(ref possible_id, ref expected_type, ref actual_type) => {
+            match *possible_id {
+                Some(ref id) => {
+                    ("type error",
+                     format!("function `{}` expected an argument of type {} but was given {}",
+                             id,
+                             expected_type,
+                             actual_type))
+                }
+                None => {
+                    ("type error",
+                     format!("function expected an argument of type {} but was given {}",
+                             expected_type,
+                             actual_type))
+                }
+            }
+        }
+        RuntimeError::BindingTypeError(ref id, ref constraint, ref typ) => {
+            ("type error",
+             format!("`{}` is annotated as {} but was given a value of type {}",
+                     id,
+                     constraint,
+                     typ))
+        }
+        RuntimeError::ReturnTypeError(ref constraint, ref possible_type) => {
+            match *possible_type {
+                Some(ref typ) => {
+                    ("type error",
+                     format!("function is annotated to return {} but returned {}",
+                             constraint,
+                             typ))
+                }
+                None => {
+                    ("type error",
+                     format!("function is annotated to return {} but did not return a value",
+                             constraint))
+                }
+            }
+        }
+        RuntimeError::DivisionByZero => ("division by zero", "cannot divide by zero".to_owned()),
+        RuntimeError::IntegerOverflow => {
+            ("integer overflow", "the result is too large to compute".to_owned())
+        }
+        RuntimeError::NonIntegerOperand(ref binary_op) => {
+            ("type error", format!("`{}` can only operate on integers", binary_op))
+        }
+        RuntimeError::NegativeShiftAmount => {
+            ("negative shift amount", "cannot shift by a negative amount".to_owned())
+        }
+        RuntimeError::StackOverflow(max_call_depth) => {
+            ("stack overflow",
+             format!("function calls are nested more than {} deep", max_call_depth))
+        }
+        RuntimeError::AssertionFailed(ref message) => ("assertion failed", message.clone()),
+        RuntimeError::GeneralRuntimeError(ref message) => ("runtime error", message.clone()),
+        RuntimeError::InsideFunctionCall(ref err, _) => describe_runtime_error(err),
+        RuntimeError::InsideModule(_, ref error_with_position) => {
+            describe_runtime_error(&error_with_position.0)
+        }
+        RuntimeError::Thrown(ref val) => ("uncaught exception", format!("{}", val)),
+        RuntimeError::ImportError(ref path, ref reason) => {
+            ("import error", format!("could not import `{}`: {}", path, reason))
+        }
+        RuntimeError::ImportCycle(ref cycle) => {
+            ("import cycle",
+             cycle
+                 .iter()
+                 .map(|path| format!("`{}`", path))
+                 .collect::<Vec<_>>()
+                 .join(" imports "))
+        }
+        RuntimeError::UnknownModuleMember(ref path, ref member) => {
+            ("unknown module member",
+             format!("module `{}` has no top-level binding `{}`", path, member))
+        }
+        RuntimeError::ModuleMemberAssignment(ref path, ref member) => {
+            ("module member assignment",
+             format!("cannot assign to `{}` of module `{}`; the bindings of a module can only \
+                      be changed by the module itself",
+                     member,
+                     path))
+        }
+        RuntimeError::IndexOutOfBounds(index) => {
+            ("index of out bounds", format!("index `{}` is out of bounds", index))
+        }
+        RuntimeError::IndexAssignmentOnImmutable(ref typ) => {
+            ("type error", format!("cannot assign to an element of immutable type {}", typ))
+        }
+        RuntimeError::NonIterable(ref typ) => {
+            ("type error", format!("cannot iterate over type {}", typ))
+        }
+        RuntimeError::TryOnNonResult(ref typ) => {
+            ("type error", format!("cannot apply `?` to type {}, which is not a Result", typ))
+        }
+        RuntimeError::NoMatchingPattern(ref typ) => {
+            ("no matching pattern", format!("no pattern matches the value of type {}", typ))
+        }
+        RuntimeError::DuplicateBinding(ref name) => {
+            ("duplicate binding", format!("the pattern binds `{}` more than once", name))
+        }
+        RuntimeError::KeyNotFound(ref key) => {
+            ("key not found", format!("key {:?} is not present in the map", key))
+        }
+        RuntimeError::NonStringMapKey(ref typ) => {
+            ("type error", format!("cannot use non-String {} as map key", typ))
+        }
+        RuntimeError::FieldAccessOnNonStruct(ref typ) => {
+            ("type error", format!("cannot access a field of non-struct type {}", typ))
+        }
+        RuntimeError::UnknownField(ref struct_name, ref field) => {
+            ("unknown field", format!("struct `{}` has no field `{}`", struct_name, field))
+        }
+        RuntimeError::DuplicateField(ref struct_name, ref field) => {
+            ("duplicate field",
+             format!("struct `{}` declares the field `{}` more than once", struct_name, field))
+        }
+        RuntimeError::SubscriptOnNonSubscriptable(ref typ) => {
+            ("type error", format!("cannot subscript type {}", typ))
+        }
+        RuntimeError::NonIntegralSubscript(ref typ) => {
+            ("non integral subscript", format!("cannot use non-integral {:?} as subscript", typ))
+        }
+        RuntimeError::BreakOutsideLoop => {
+            ("break outside loop", "break statement appeared outside of a loop".to_owned())
+        }
+        RuntimeError::ContinueOutsideLoop => {
+            ("continue outside loop", "continue statement appeared outside of a loop".to_owned())
+        }
+        RuntimeError::ReturnOutsideFunction => {
+            ("return outside function",
+             "return statement appeared outside of a function".to_owned())
+        }
+        RuntimeError::UnknownLabel(ref label) => {
+            ("unknown label", format!("no enclosing loop is labeled `'{}`", label))
+        }
+        // always turned back into a `break`, `continue` or `return` before it
+        // leaves the interpreter
+        RuntimeError::Unwind(_) => unreachable!(),
+    }
+}
+
 pub fn print_typechecker_error_for_file(err: TypeCheckerIssue,
                                         span: SourceSpan,
                                         file_content: &str,
                                         file_name: &str) {
     let mut span = span;
     let mut error_to_print_after_this = None;
+    // the imported file that `error_to_print_after_this` is in, if it is not this one
+    let mut module_to_print_after_this = None;
+    // the function that `error_to_print_after_this` is in, if it is known
+    let mut function_to_print_after_this = None;
     adjust_source_span(&mut span, file_content);
     if span.start_line == span.end_line {
         println!("in {}, line {}, col {}:",
@@ -145,114 +368,42 @@ pub fn print_typechecker_error_for_file(err: TypeCheckerIssue,
     match err {
         TypeCheckerIssue::RuntimeError(e) => {
             match e {
-                RuntimeError::ReferenceError(id) => {
-                    println!("{}: `{}` was not declared",
-                             Red.bold().paint("reference error"),
-                             id);
-                }
-                RuntimeError::UndeclaredAssignment(id) => {
-                    println!("{}: cannot assign to undeclared `{}`",
-                             Red.bold().paint("reference error"),
-                             id);
-                }
-                RuntimeError::BinaryTypeError(binary_op, type1, type2) => {
-                    println!("{}: `{}` cannot operate on types {} and {}",
-                             Red.bold().paint("type error"),
-                             binary_op,
-                             type1,
-                             type2);
-                }
-                RuntimeError::UnaryTypeError(unary_op, typ) => {
-                    println!("{}: `{}` cannot operate on type {}",
-                             Red.bold().paint("type error"),
-                             unary_op,
-                             typ);
-                }
-                RuntimeError::NoneError(possible_id) => {
-                    match possible_id {
-                        Some(id) => {
-                            println!("{}: tried to use return value of non-returning function \
-                                      `{}`",
-                                     Red.bold().paint("missing value error"),
-                                     id);
-                        }
-                        None => {
-                            println!("{}: tried to use return value of non-returning function",
-                                     Red.bold().paint("missing value error"));
-                        }
+                RuntimeError::InsideFunctionCall(next_error, frames) => {
+                    println!("{} (most recent call last):",
+                             Red.bold().paint("error in function call"));
+                    print_stack_frames(&frames, &span, file_content);
+                    if let Some(innermost_frame) = frames.first() {
+                        error_to_print_after_this =
+                            Some((TypeCheckerIssue::RuntimeError(*next_error),
+                                  innermost_frame.pos));
+                        function_to_print_after_this = Some(innermost_frame.fn_name.clone());
                     }
                 }
-                RuntimeError::CallToNonFunction(possible_id, other_type) => {
-                    match possible_id {
-                        Some(id) => {
-                            println!("{}: cannot call `{}` ({}) as Function",
-                                     Red.bold().paint("type error"),
-                                     id,
-                                     other_type);
-                        }
-                        None => {
-                            println!("{}: cannot call {} as Function",
-                                     Red.bold().paint("type error"),
-                                     other_type);
-                        }
-                    }
-                }
-                RuntimeError::ArgumentLength(possible_id) => {
-                    match possible_id {
-                        Some(id) => {
-                            println!("{}: function `{}` called with incorrect number of arguments",
-                                     Red.bold().paint("arguments mismatch"),
-                                     id);
-                        }
-                        None => {
-                            println!("{}: function called with incorrect number of arguments",
-                                     Red.bold().paint("arguments mismatch"));
-                        }
-                    }
-                }
-                RuntimeError::GeneralRuntimeError(message) => {
-                    println!("{}: {}", Red.bold().paint("runtime error"), message);
-                }
-                RuntimeError::InsideFunctionCall(error_with_position) => {
-                    println!("{}:", Red.bold().paint("error in function call"));
-                    let unboxed_error_with_position = *error_with_position;
-                    let (next_error, next_pos) = unboxed_error_with_position;
+                RuntimeError::InsideModule(path, error_with_position) => {
+                    println!("{}:", Red.bold().paint("error in imported module"));
+                    let (next_error, next_pos) = *error_with_position;
                     error_to_print_after_this = Some((TypeCheckerIssue::RuntimeError(next_error),
                                                       next_pos));
+                    module_to_print_after_this = Some(path);
                 }
-                RuntimeError::IndexOutOfBounds(index) => {
-                    println!("{}: index `{}` is out of bounds of the tuple",
-                             Red.bold().paint("index of out bounds"),
-                             index);
-                }
-                RuntimeError::SubscriptOnNonSubscriptable(typ) => {
-                    println!("{}: cannot subscript type {}",
-                             Red.bold().paint("type error"),
-                             typ);
+                e => {
+                    let (title, message) = describe_runtime_error(&e);
+                    println!("{}: {}", Red.bold().paint(title), message);
                 }
-                RuntimeError::NonIntegralSubscript(typ) => {
-                    println!("{}: cannot use non-integral {:?} as subscript",
-                             Red.bold().paint("non integral subscript"),
-                             typ);
-                }
-                RuntimeError::BreakOutsideLoop => {
-                    println!("{}: break statement appeared outside of a loop",
-                             Red.bold().paint("break outside loop"));
-                }
-                RuntimeError::ContinueOutsideLoop => {
-                    println!("{}: continue statement appeared outside of a loop",
-                             Red.bold().paint("continue outside loop"));
+            }
+        }
+        TypeCheckerIssue::MultipleTypesFromBranchWarning(possible_id) => {
+            match possible_id {
+                Some(id) => {
+                    println!("{}: `{}` gets different types in branches",
+                             Style::new().bold().paint("multiple types from branch"),
+                             id);
                 }
-                RuntimeError::ReturnOutsideFunction => {
-                    println!("{}: return statement appeared outside of a function",
-                             Red.bold().paint("return outside function"));
+                None => {
+                    println!("{}: the branches give values of different types",
+                             Style::new().bold().paint("multiple types from branch"));
                 }
             }
-        }
-        TypeCheckerIssue::MultipleTypesFromBranchWarning(id) => {
-            println!("{}: `{}` gets different types in branches",
-                     Style::new().bold().paint("multiple types from branch"),
-                     id);
             is_error = false;
         }
         TypeCheckerIssue::InsideFunctionCall(issue_with_position) => {
@@ -271,6 +422,32 @@ pub fn print_typechecker_error_for_file(err: TypeCheckerIssue,
                      Style::new().bold().paint("unreachable code"));
             is_error = false;
         }
+        TypeCheckerIssue::InsideModule(path, issue_with_position) => {
+            println!("{}:", Red.bold().paint("issue in imported module"));
+            error_to_print_after_this = Some(*issue_with_position);
+            module_to_print_after_this = Some(path);
+        }
+        TypeCheckerIssue::UnreachableMatchArm => {
+            println!("{}: this arm of the match can never be reached",
+                     Style::new().bold().paint("unreachable match arm"));
+            is_error = false;
+        }
+        TypeCheckerIssue::NonExhaustiveMatch => {
+            println!("{}: the match does not handle both `true` and `false`",
+                     Style::new().bold().paint("non-exhaustive match"));
+            is_error = false;
+        }
+        TypeCheckerIssue::BreakWithoutValue => {
+            println!("{}: the loop's value is used, but this `break` gives it no value",
+                     Style::new().bold().paint("break without value"));
+            is_error = false;
+        }
+        TypeCheckerIssue::UnknownField(struct_name, field) => {
+            println!("{}: struct `{}` has no field `{}`",
+                     Red.bold().paint("unknown field"),
+                     struct_name,
+                     field);
+        }
         TypeCheckerIssue::PossibleNoneError(possible_id) => {
             match possible_id {
                 Some(id) => {
@@ -375,10 +552,60 @@ pub fn print_typechecker_error_for_file(err: TypeCheckerIssue,
         println!("{line_num:width$} |", line_num = "", width = max_idx_width);
     }
     if let Some(next_err) = error_to_print_after_this {
+        if let Some(module_file) = module_to_print_after_this {
+            let mut module_content = String::new();
+            File::open(&module_file)
+                .and_then(|mut file| file.read_to_string(&mut module_content))
+                .unwrap();
+            let span = offset_span_to_source_span(next_err.1, &module_content);
+            print_typechecker_error_for_file(next_err.0, span, &module_content, &module_file);
+            return;
+        }
         let span = offset_span_to_source_span(next_err.1, file_content);
-        print_typechecker_error_for_file(next_err.0,
-                                         span,
-                                         file_content,
-                                         &("function in ".to_owned() + file_name));
+        let location = match function_to_print_after_this {
+            Some(fn_name) => format!("function `{}` in {}", fn_name, file_name),
+            None => "function in ".to_owned() + file_name,
+        };
+        print_typechecker_error_for_file(next_err.0, span, file_content, &location);
+    }
+}
+
+// Prints where each call of a stack trace was made, starting from the outermost
+// call, which is made at `span`. Calls repeated one after another, as in a
+// recursion, are printed once.
+fn print_stack_frames(frames: &[StackFrame], span: &SourceSpan, file_content: &str) {
+    let mut calls = Vec::new();
+    let mut possible_caller: Option<&StackFrame> = None;
+    for frame in frames.iter().rev() {
+        let call = match possible_caller {
+            Some(caller) => {
+                let call_span = offset_span_to_source_span(caller.pos, file_content);
+                format!("`{}` called from `{}` on line {}, col {}",
+                        frame.fn_name,
+                        caller.fn_name,
+                        call_span.start_line,
+                        call_span.start_col)
+            }
+            None => {
+                format!("`{}` called on line {}, col {}",
+                        frame.fn_name,
+                        span.start_line,
+                        span.start_col)
+            }
+        };
+        calls.push(call);
+        possible_caller = Some(frame);
+    }
+    let mut idx = 0;
+    while idx < calls.len() {
+        let num_repeats = calls[idx..]
+            .iter()
+            .take_while(|call| **call == calls[idx])
+            .count();
+        println!("  {}", calls[idx]);
+        if num_repeats > 1 {
+            println!("  ... repeated {} more times", num_repeats - 1);
+        }
+        idx += num_repeats;
     }
 }
@@ -1,15 +1,49 @@
 use std::rc::Rc;
 use std::cell::RefCell;
+use std::ptr;
 
 use value::*;
 use ast;
-use environment::Environment;
+use bigint::BigInt;
+use environment::{Environment, Frame};
+use bytecode::Closure;
 use runtime::RuntimeError;
+use typechecker::Type;
 
 #[derive(Clone, Debug)]
 pub struct CallSign {
-    pub num_params: usize,
-    pub variadic: bool,
+    pub min_params: usize,
+    /// `None` for variadic functions, which take any number of extra arguments
+    pub max_params: Option<usize>,
+}
+
+impl CallSign {
+    /// The call sign of a function with the given parameters, where parameters with
+    /// a default value are optional and a rest parameter makes the function variadic
+    pub fn from_params(params: &[ast::FnParam]) -> CallSign {
+        let num_fixed = params.iter().filter(|p| !p.is_rest).count();
+        CallSign {
+            min_params: params.iter().filter(|p| !p.is_rest && p.default.is_none()).count(),
+            max_params: if num_fixed < params.len() {
+                None
+            } else {
+                Some(num_fixed)
+            },
+        }
+    }
+
+    pub fn accepts(&self, num_args: usize) -> bool {
+        match self.max_params {
+            Some(max_params) => num_args >= self.min_params && num_args <= max_params,
+            None => num_args >= self.min_params,
+        }
+    }
+
+    /// Whether there is a number of arguments that both call signs accept
+    pub fn overlaps(&self, other: &CallSign) -> bool {
+        let min = ::std::cmp::max(self.min_params, other.min_params);
+        self.accepts(min) && other.accepts(min)
+    }
 }
 
 #[derive(Clone, Debug)]
@@ -18,10 +52,26 @@ pub enum Function {
     NativeReturning(CallSign, fn(Vec<Value>) -> Result<Value, RuntimeError>),
     User {
         call_sign: CallSign,
-        param_names: Vec<String>,
+        /// The name given in the definition, which stack traces show
+        maybe_id: Option<String>,
+        params: Vec<ast::FnParam>,
+        ret_constraint: Option<ast::ReturnConstraint>,
         body: Box<ast::StmtNode>,
+        /// The number of variables of the frame that the parameters are declared in
+        num_slots: usize,
         env: Rc<RefCell<Environment>>,
+        /// The frame of the block the function is defined in, if it is not
+        /// defined at the top level
+        frame: Option<Rc<RefCell<Frame>>>,
+    },
+    /// The constructor declared by `struct name { fields }`
+    StructConstructor {
+        call_sign: CallSign,
+        name: String,
+        fields: Vec<String>,
     },
+    /// A function compiled by the bytecode VM, with the variables it captured
+    Compiled(Rc<Closure>),
 }
 
 impl Function {
@@ -29,7 +79,26 @@ impl Function {
         match *self {
             Function::NativeVoid(ref call_sign, _) |
             Function::NativeReturning(ref call_sign, _) |
-            Function::User { ref call_sign, .. } => call_sign.clone(),
+            Function::User { ref call_sign, .. } |
+            Function::StructConstructor { ref call_sign, .. } => call_sign.clone(),
+            Function::Compiled(ref closure) => closure.get_call_sign(),
+        }
+    }
+
+    /// The names that named arguments can refer to, in the order of the parameters
+    pub fn get_param_names(&self) -> Vec<String> {
+        match *self {
+            Function::NativeVoid(..) |
+            Function::NativeReturning(..) => Vec::new(),
+            Function::User { ref params, .. } => {
+                params
+                    .iter()
+                    .filter(|p| !p.is_rest)
+                    .map(|p| p.name.clone())
+                    .collect()
+            }
+            Function::StructConstructor { ref fields, .. } => fields.clone(),
+            Function::Compiled(ref closure) => closure.get_param_names(),
         }
     }
 }
@@ -55,8 +124,7 @@ pub fn native_println(args: Vec<Value>) -> Result<(), RuntimeError> {
 pub fn native_assert(args: Vec<Value>) -> Result<(), RuntimeError> {
     let val = &args[0];
     if !val.is_truthy() {
-        Err(RuntimeError::GeneralRuntimeError(format!("assert: assertion failed for value {}",
-                                                      val)))
+        Err(RuntimeError::AssertionFailed(format!("assert: assertion failed for value {}", val)))
     } else {
         Ok(())
     }
@@ -67,7 +135,7 @@ pub fn native_assert_eq(args: Vec<Value>) -> Result<(), RuntimeError> {
     let (val1, val2) = (&args[0], &args[1]);
 
     if val1 != val2 {
-        Err(RuntimeError::GeneralRuntimeError(format!("assert_eq: {} != {}", val1, val2)))
+        Err(RuntimeError::AssertionFailed(format!("assert_eq: {} != {}", val1, val2)))
     } else {
         Ok(())
     }
@@ -78,6 +146,9 @@ pub fn native_len(args: Vec<Value>) -> Result<Value, RuntimeError> {
     let val = &args[0];
     match *val {
         Value::Tuple(ref v) => Ok(Value::Number(Number::Integer(v.len() as i64))),
+        Value::List(ref l) => Ok(Value::Number(Number::Integer(l.borrow().len() as i64))),
+        Value::Map(ref m) => Ok(Value::Number(Number::Integer(m.len() as i64))),
+        Value::String(ref s) => Ok(Value::Number(Number::Integer(s.chars().count() as i64))),
         ref non_tuple_val => {
             Err(RuntimeError::GeneralRuntimeError(format!("cannot get len of {:?}",
                                                           non_tuple_val.get_type())))
@@ -85,6 +156,546 @@ pub fn native_len(args: Vec<Value>) -> Result<Value, RuntimeError> {
     }
 }
 
+fn get_list_arg(fn_name: &str, val: &Value) -> Result<Rc<RefCell<Vec<Value>>>, RuntimeError> {
+    match *val {
+        Value::List(ref l) => Ok(l.clone()),
+        ref other => {
+            Err(RuntimeError::ArgumentTypeError(Some(fn_name.to_owned()),
+                                                Type::List,
+                                                other.get_type()))
+        }
+    }
+}
+
+fn get_integer_arg(fn_name: &str, val: &Value) -> Result<i64, RuntimeError> {
+    match val.as_index() {
+        Some(i) => Ok(i),
+        None => {
+            Err(RuntimeError::ArgumentTypeError(Some(fn_name.to_owned()),
+                                                Type::Number,
+                                                val.get_type()))
+        }
+    }
+}
+
+#[cfg_attr(feature = "cargo-clippy", allow(needless_pass_by_value))]
+pub fn native_push(args: Vec<Value>) -> Result<(), RuntimeError> {
+    let list = get_list_arg("push", &args[0])?;
+    list.borrow_mut().push(args[1].clone());
+    Ok(())
+}
+
+#[cfg_attr(feature = "cargo-clippy", allow(needless_pass_by_value))]
+pub fn native_pop(args: Vec<Value>) -> Result<Value, RuntimeError> {
+    let list = get_list_arg("pop", &args[0])?;
+    let popped = list.borrow_mut().pop();
+    match popped {
+        Some(val) => Ok(val),
+        None => Err(RuntimeError::GeneralRuntimeError("pop: list is empty".to_owned())),
+    }
+}
+
+#[cfg_attr(feature = "cargo-clippy", allow(needless_pass_by_value))]
+pub fn native_insert(args: Vec<Value>) -> Result<(), RuntimeError> {
+    let list = get_list_arg("insert", &args[0])?;
+    let idx = get_integer_arg("insert", &args[1])?;
+    let len = list.borrow().len();
+    if idx < 0 || idx as usize > len {
+        return Err(RuntimeError::IndexOutOfBounds(idx));
+    }
+    list.borrow_mut().insert(idx as usize, args[2].clone());
+    Ok(())
+}
+
+#[cfg_attr(feature = "cargo-clippy", allow(needless_pass_by_value))]
+pub fn native_remove(args: Vec<Value>) -> Result<Value, RuntimeError> {
+    let list = get_list_arg("remove", &args[0])?;
+    let idx = get_integer_arg("remove", &args[1])?;
+    let len = list.borrow().len();
+    if idx < 0 || idx as usize >= len {
+        return Err(RuntimeError::IndexOutOfBounds(idx));
+    }
+    let removed = list.borrow_mut().remove(idx as usize);
+    Ok(removed)
+}
+
+/// `range(start, end)` or `range(start, end, step)`, as a tuple of integers
+/// from `start` up to but not including `end`
+#[cfg_attr(feature = "cargo-clippy", allow(needless_pass_by_value))]
+pub fn native_range(args: Vec<Value>) -> Result<Value, RuntimeError> {
+    let range = Range::from_args(&args)?;
+    Ok(Value::Tuple(range.map(|i| Value::Number(Number::Integer(i))).collect()))
+}
+
+/// Whether `func` is the builtin `range`, which `for ... in` counts through
+/// instead of building the tuple
+pub fn is_native_range(func: &Function) -> bool {
+    if let Function::NativeReturning(_, f) = *func {
+        let range: fn(Vec<Value>) -> Result<Value, RuntimeError> = native_range;
+        return ptr::fn_addr_eq(f, range);
+    }
+    false
+}
+
+/// The integers that `range` gives, produced one at a time
+#[derive(Clone, Debug)]
+pub struct Range {
+    next: i64,
+    end: i64,
+    step: i64,
+}
+
+impl Range {
+    /// Checks the arguments of a call to `range`
+    pub fn from_args(args: &[Value]) -> Result<Range, RuntimeError> {
+        if args.len() < 2 || args.len() > 3 {
+            return Err(RuntimeError::ArgumentLength(Some("range".to_owned())));
+        }
+        let start = get_integer_arg("range", &args[0])?;
+        let end = get_integer_arg("range", &args[1])?;
+        let step = match args.get(2) {
+            Some(val) => get_integer_arg("range", val)?,
+            None => 1,
+        };
+        if step == 0 {
+            return Err(RuntimeError::GeneralRuntimeError("range: step cannot be 0".to_owned()));
+        }
+        Ok(Range {
+               next: start,
+               end: end,
+               step: step,
+           })
+    }
+
+    /// The range as a tuple of its next integer, its end and its step, which the
+    /// VM keeps on its stack while a loop counts through it
+    pub fn to_tuple(&self) -> Value {
+        Value::Tuple(vec![Value::Number(Number::Integer(self.next)),
+                          Value::Number(Number::Integer(self.end)),
+                          Value::Number(Number::Integer(self.step))])
+    }
+
+    /// The range kept in a tuple made by `to_tuple`
+    pub fn from_tuple(val: &Value) -> Range {
+        match *val {
+            Value::Tuple(ref v) if v.len() == 3 => {
+                let get = |idx: usize| v[idx].as_index().unwrap();
+                Range {
+                    next: get(0),
+                    end: get(1),
+                    step: get(2),
+                }
+            }
+            _ => unreachable!(),
+        }
+    }
+}
+
+impl Iterator for Range {
+    type Item = i64;
+
+    fn next(&mut self) -> Option<i64> {
+        let i = self.next;
+        if (self.step > 0 && i >= self.end) || (self.step < 0 && i <= self.end) {
+            return None;
+        }
+        // stepping past the largest or smallest integer ends the range
+        self.next = i.checked_add(self.step).unwrap_or(self.end);
+        Some(i)
+    }
+}
+
+fn get_string_arg<'a>(fn_name: &str, val: &'a Value) -> Result<&'a str, RuntimeError> {
+    match *val {
+        Value::String(ref s) => Ok(s),
+        ref other => {
+            Err(RuntimeError::ArgumentTypeError(Some(fn_name.to_owned()),
+                                                Type::String,
+                                                other.get_type()))
+        }
+    }
+}
+
+// Strings are indexed by chars rather than bytes
+fn char_index_to_byte_index(s: &str, idx: i64) -> Result<usize, RuntimeError> {
+    if idx < 0 {
+        return Err(RuntimeError::IndexOutOfBounds(idx));
+    }
+    match s.char_indices().nth(idx as usize) {
+        Some((byte_idx, _)) => Ok(byte_idx),
+        None if idx as usize == s.chars().count() => Ok(s.len()),
+        None => Err(RuntimeError::IndexOutOfBounds(idx)),
+    }
+}
+
+#[cfg_attr(feature = "cargo-clippy", allow(needless_pass_by_value))]
+pub fn native_split(args: Vec<Value>) -> Result<Value, RuntimeError> {
+    let s = get_string_arg("split", &args[0])?;
+    let separator = get_string_arg("split", &args[1])?;
+    let parts: Vec<Value> = if separator.is_empty() {
+        s.chars().map(|c| Value::String(c.to_string())).collect()
+    } else {
+        s.split(separator)
+            .map(|part| Value::String(part.to_owned()))
+            .collect()
+    };
+    Ok(Value::List(Rc::new(RefCell::new(parts))))
+}
+
+#[cfg_attr(feature = "cargo-clippy", allow(needless_pass_by_value))]
+pub fn native_join(args: Vec<Value>) -> Result<Value, RuntimeError> {
+    let separator = get_string_arg("join", &args[1])?;
+    let elems = match args[0] {
+        Value::Tuple(ref t) => t.clone(),
+        Value::List(ref l) => l.borrow().clone(),
+        ref other => {
+            return Err(RuntimeError::ArgumentTypeError(Some("join".to_owned()),
+                                                       Type::List,
+                                                       other.get_type()));
+        }
+    };
+    let strings: Vec<String> = elems.iter().map(|elem| elem.to_string()).collect();
+    Ok(Value::String(strings.join(separator)))
+}
+
+#[cfg_attr(feature = "cargo-clippy", allow(needless_pass_by_value))]
+pub fn native_trim(args: Vec<Value>) -> Result<Value, RuntimeError> {
+    let s = get_string_arg("trim", &args[0])?;
+    Ok(Value::String(s.trim().to_owned()))
+}
+
+#[cfg_attr(feature = "cargo-clippy", allow(needless_pass_by_value))]
+pub fn native_replace(args: Vec<Value>) -> Result<Value, RuntimeError> {
+    let s = get_string_arg("replace", &args[0])?;
+    let from = get_string_arg("replace", &args[1])?;
+    let to = get_string_arg("replace", &args[2])?;
+    Ok(Value::String(s.replace(from, to)))
+}
+
+/// The index of the first occurrence of the substring, or -1 if there is none
+#[cfg_attr(feature = "cargo-clippy", allow(needless_pass_by_value))]
+pub fn native_find(args: Vec<Value>) -> Result<Value, RuntimeError> {
+    let s = get_string_arg("find", &args[0])?;
+    let substring = get_string_arg("find", &args[1])?;
+    let idx = match s.find(substring) {
+        Some(byte_idx) => s[..byte_idx].chars().count() as i64,
+        None => -1,
+    };
+    Ok(Value::Number(Number::Integer(idx)))
+}
+
+#[cfg_attr(feature = "cargo-clippy", allow(needless_pass_by_value))]
+pub fn native_starts_with(args: Vec<Value>) -> Result<Value, RuntimeError> {
+    let s = get_string_arg("starts_with", &args[0])?;
+    let prefix = get_string_arg("starts_with", &args[1])?;
+    Ok(Value::Bool(s.starts_with(prefix)))
+}
+
+#[cfg_attr(feature = "cargo-clippy", allow(needless_pass_by_value))]
+pub fn native_ends_with(args: Vec<Value>) -> Result<Value, RuntimeError> {
+    let s = get_string_arg("ends_with", &args[0])?;
+    let suffix = get_string_arg("ends_with", &args[1])?;
+    Ok(Value::Bool(s.ends_with(suffix)))
+}
+
+#[cfg_attr(feature = "cargo-clippy", allow(needless_pass_by_value))]
+pub fn native_to_upper(args: Vec<Value>) -> Result<Value, RuntimeError> {
+    let s = get_string_arg("to_upper", &args[0])?;
+    Ok(Value::String(s.to_uppercase()))
+}
+
+#[cfg_attr(feature = "cargo-clippy", allow(needless_pass_by_value))]
+pub fn native_to_lower(args: Vec<Value>) -> Result<Value, RuntimeError> {
+    let s = get_string_arg("to_lower", &args[0])?;
+    Ok(Value::String(s.to_lowercase()))
+}
+
+/// `substring(s, start, end)`, the chars of `s` from `start` up to but not
+/// including `end`
+#[cfg_attr(feature = "cargo-clippy", allow(needless_pass_by_value))]
+pub fn native_substring(args: Vec<Value>) -> Result<Value, RuntimeError> {
+    let s = get_string_arg("substring", &args[0])?;
+    let start = get_integer_arg("substring", &args[1])?;
+    let end = get_integer_arg("substring", &args[2])?;
+    let start_byte = char_index_to_byte_index(s, start)?;
+    let end_byte = char_index_to_byte_index(s, end)?;
+    if end_byte < start_byte {
+        return Err(RuntimeError::IndexOutOfBounds(end));
+    }
+    Ok(Value::String(s[start_byte..end_byte].to_owned()))
+}
+
+#[cfg_attr(feature = "cargo-clippy", allow(needless_pass_by_value))]
+pub fn native_parse_int(args: Vec<Value>) -> Result<Value, RuntimeError> {
+    let s = get_string_arg("parse_int", &args[0])?;
+    match s.trim().parse::<BigInt>() {
+        Ok(i) => Ok(Value::Number(Number::from(i))),
+        Err(_) => {
+            Err(RuntimeError::GeneralRuntimeError(format!("parse_int: invalid integer {:?}", s)))
+        }
+    }
+}
+
+#[cfg_attr(feature = "cargo-clippy", allow(needless_pass_by_value))]
+pub fn native_parse_float(args: Vec<Value>) -> Result<Value, RuntimeError> {
+    let s = get_string_arg("parse_float", &args[0])?;
+    match s.trim().parse() {
+        Ok(f) => Ok(Value::Number(Number::Float(f))),
+        Err(_) => {
+            Err(RuntimeError::GeneralRuntimeError(format!("parse_float: invalid number {:?}", s)))
+        }
+    }
+}
+
+fn get_number_arg(fn_name: &str, val: &Value) -> Result<Number, RuntimeError> {
+    match *val {
+        Value::Number(ref n) => Ok(n.clone()),
+        ref other => {
+            Err(RuntimeError::ArgumentTypeError(Some(fn_name.to_owned()),
+                                                Type::Number,
+                                                other.get_type()))
+        }
+    }
+}
+
+fn get_float_arg(fn_name: &str, val: &Value) -> Result<f64, RuntimeError> {
+    Ok(get_number_arg(fn_name, val)?.as_float())
+}
+
+// Rounded floats become integers, so that they can be used as indices.
+// Infinity and NaN stay floats.
+fn float_to_integral_number(f: f64) -> Number {
+    match BigInt::from_f64(f) {
+        Some(i) => Number::from(i),
+        None => Number::Float(f),
+    }
+}
+
+fn round_with(fn_name: &str, args: &[Value], round: fn(f64) -> f64) -> Result<Value, RuntimeError> {
+    match get_number_arg(fn_name, &args[0])? {
+        Number::Float(f) => Ok(Value::Number(float_to_integral_number(round(f)))),
+        integer => Ok(Value::Number(integer)),
+    }
+}
+
+fn apply_float_fn(fn_name: &str, args: &[Value], f: fn(f64) -> f64) -> Result<Value, RuntimeError> {
+    let x = get_float_arg(fn_name, &args[0])?;
+    Ok(Value::Number(Number::Float(f(x))))
+}
+
+#[cfg_attr(feature = "cargo-clippy", allow(needless_pass_by_value))]
+pub fn native_abs(args: Vec<Value>) -> Result<Value, RuntimeError> {
+    match get_number_arg("abs", &args[0])? {
+        Number::Integer(i) => {
+            match i.checked_abs() {
+                Some(abs) => Ok(Value::Number(Number::Integer(abs))),
+                None => Ok(Value::Number(Number::from(BigInt::from(i).abs()))),
+            }
+        }
+        Number::Float(f) => Ok(Value::Number(Number::Float(f.abs()))),
+        Number::BigInt(i) => Ok(Value::Number(Number::BigInt(i.abs()))),
+    }
+}
+
+#[cfg_attr(feature = "cargo-clippy", allow(needless_pass_by_value))]
+pub fn native_floor(args: Vec<Value>) -> Result<Value, RuntimeError> {
+    round_with("floor", &args, f64::floor)
+}
+
+#[cfg_attr(feature = "cargo-clippy", allow(needless_pass_by_value))]
+pub fn native_ceil(args: Vec<Value>) -> Result<Value, RuntimeError> {
+    round_with("ceil", &args, f64::ceil)
+}
+
+#[cfg_attr(feature = "cargo-clippy", allow(needless_pass_by_value))]
+pub fn native_round(args: Vec<Value>) -> Result<Value, RuntimeError> {
+    round_with("round", &args, f64::round)
+}
+
+#[cfg_attr(feature = "cargo-clippy", allow(needless_pass_by_value))]
+pub fn native_sqrt(args: Vec<Value>) -> Result<Value, RuntimeError> {
+    apply_float_fn("sqrt", &args, f64::sqrt)
+}
+
+/// `pow(base, exponent)`, which is an integer when both are integers and the
+/// exponent is not negative
+#[cfg_attr(feature = "cargo-clippy", allow(needless_pass_by_value))]
+pub fn native_pow(args: Vec<Value>) -> Result<Value, RuntimeError> {
+    let base = get_number_arg("pow", &args[0])?;
+    let exponent = get_number_arg("pow", &args[1])?;
+    Ok(Value::Number(base.pow(exponent)?))
+}
+
+fn fold_numbers(fn_name: &str,
+                args: &[Value],
+                pick_second: fn(&Number, &Number) -> bool)
+                -> Result<Value, RuntimeError> {
+    let mut result = get_number_arg(fn_name, &args[0])?;
+    for arg in &args[1..] {
+        let n = get_number_arg(fn_name, arg)?;
+        if pick_second(&result, &n) {
+            result = n;
+        }
+    }
+    Ok(Value::Number(result))
+}
+
+#[cfg_attr(feature = "cargo-clippy", allow(needless_pass_by_value))]
+pub fn native_min(args: Vec<Value>) -> Result<Value, RuntimeError> {
+    fold_numbers("min", &args, |a, b| b < a)
+}
+
+#[cfg_attr(feature = "cargo-clippy", allow(needless_pass_by_value))]
+pub fn native_max(args: Vec<Value>) -> Result<Value, RuntimeError> {
+    fold_numbers("max", &args, |a, b| b > a)
+}
+
+#[cfg_attr(feature = "cargo-clippy", allow(needless_pass_by_value))]
+pub fn native_sin(args: Vec<Value>) -> Result<Value, RuntimeError> {
+    apply_float_fn("sin", &args, f64::sin)
+}
+
+#[cfg_attr(feature = "cargo-clippy", allow(needless_pass_by_value))]
+pub fn native_cos(args: Vec<Value>) -> Result<Value, RuntimeError> {
+    apply_float_fn("cos", &args, f64::cos)
+}
+
+#[cfg_attr(feature = "cargo-clippy", allow(needless_pass_by_value))]
+pub fn native_tan(args: Vec<Value>) -> Result<Value, RuntimeError> {
+    apply_float_fn("tan", &args, f64::tan)
+}
+
+#[cfg_attr(feature = "cargo-clippy", allow(needless_pass_by_value))]
+pub fn native_asin(args: Vec<Value>) -> Result<Value, RuntimeError> {
+    apply_float_fn("asin", &args, f64::asin)
+}
+
+#[cfg_attr(feature = "cargo-clippy", allow(needless_pass_by_value))]
+pub fn native_acos(args: Vec<Value>) -> Result<Value, RuntimeError> {
+    apply_float_fn("acos", &args, f64::acos)
+}
+
+#[cfg_attr(feature = "cargo-clippy", allow(needless_pass_by_value))]
+pub fn native_atan(args: Vec<Value>) -> Result<Value, RuntimeError> {
+    apply_float_fn("atan", &args, f64::atan)
+}
+
+/// `atan2(y, x)`, the angle of the point `(x, y)`
+#[cfg_attr(feature = "cargo-clippy", allow(needless_pass_by_value))]
+pub fn native_atan2(args: Vec<Value>) -> Result<Value, RuntimeError> {
+    let y = get_float_arg("atan2", &args[0])?;
+    let x = get_float_arg("atan2", &args[1])?;
+    Ok(Value::Number(Number::Float(y.atan2(x))))
+}
+
+// Numeric strings are parsed, for the conversion functions
+fn get_number_or_numeric_string_arg(fn_name: &str, val: &Value) -> Result<Number, RuntimeError> {
+    match *val {
+        Value::String(ref s) => {
+            let s = s.trim();
+            if let Ok(i) = s.parse::<BigInt>() {
+                return Ok(Number::from(i));
+            }
+            match s.parse() {
+                Ok(f) => Ok(Number::Float(f)),
+                Err(_) => {
+                    Err(RuntimeError::GeneralRuntimeError(format!("{}: invalid number {:?}",
+                                                                  fn_name,
+                                                                  s)))
+                }
+            }
+        }
+        ref other => get_number_arg(fn_name, other),
+    }
+}
+
+/// Converts a number or numeric string to an integer, truncating towards zero
+#[cfg_attr(feature = "cargo-clippy", allow(needless_pass_by_value))]
+pub fn native_int(args: Vec<Value>) -> Result<Value, RuntimeError> {
+    match get_number_or_numeric_string_arg("int", &args[0])? {
+        Number::Float(f) => {
+            match float_to_integral_number(f.trunc()) {
+                Number::Float(_) => {
+                    Err(RuntimeError::GeneralRuntimeError(format!("int: {} is out of range", f)))
+                }
+                integer => Ok(Value::Number(integer)),
+            }
+        }
+        integer => Ok(Value::Number(integer)),
+    }
+}
+
+/// Converts a number or numeric string to a float
+#[cfg_attr(feature = "cargo-clippy", allow(needless_pass_by_value))]
+pub fn native_float(args: Vec<Value>) -> Result<Value, RuntimeError> {
+    let n = get_number_or_numeric_string_arg("float", &args[0])?;
+    Ok(Value::Number(Number::Float(n.as_float())))
+}
+
+/// Whether the number has no fractional part, like `2` or `2.0`
+#[cfg_attr(feature = "cargo-clippy", allow(needless_pass_by_value))]
+pub fn native_is_integer(args: Vec<Value>) -> Result<Value, RuntimeError> {
+    match get_number_arg("is_integer", &args[0])? {
+        Number::Integer(_) |
+        Number::BigInt(_) => Ok(Value::Bool(true)),
+        Number::Float(f) => Ok(Value::Bool(f.is_finite() && f.fract() == 0.0)),
+    }
+}
+
+#[cfg_attr(feature = "cargo-clippy", allow(needless_pass_by_value))]
+pub fn native_ok(args: Vec<Value>) -> Result<Value, RuntimeError> {
+    Ok(Value::Result(Ok(Box::new(args[0].clone()))))
+}
+
+#[cfg_attr(feature = "cargo-clippy", allow(needless_pass_by_value))]
+pub fn native_err(args: Vec<Value>) -> Result<Value, RuntimeError> {
+    Ok(Value::Result(Err(Box::new(args[0].clone()))))
+}
+
+fn get_result_arg(fn_name: &str, val: &Value) -> Result<Result<Value, Value>, RuntimeError> {
+    match *val {
+        Value::Result(Ok(ref v)) => Ok(Ok(*v.clone())),
+        Value::Result(Err(ref e)) => Ok(Err(*e.clone())),
+        ref other => {
+            Err(RuntimeError::ArgumentTypeError(Some(fn_name.to_owned()),
+                                                Type::Result,
+                                                other.get_type()))
+        }
+    }
+}
+
+#[cfg_attr(feature = "cargo-clippy", allow(needless_pass_by_value))]
+pub fn native_is_ok(args: Vec<Value>) -> Result<Value, RuntimeError> {
+    let result = get_result_arg("is_ok", &args[0])?;
+    Ok(Value::Bool(result.is_ok()))
+}
+
+#[cfg_attr(feature = "cargo-clippy", allow(needless_pass_by_value))]
+pub fn native_is_err(args: Vec<Value>) -> Result<Value, RuntimeError> {
+    let result = get_result_arg("is_err", &args[0])?;
+    Ok(Value::Bool(result.is_err()))
+}
+
+#[cfg_attr(feature = "cargo-clippy", allow(needless_pass_by_value))]
+pub fn native_unwrap(args: Vec<Value>) -> Result<Value, RuntimeError> {
+    match get_result_arg("unwrap", &args[0])? {
+        Ok(val) => Ok(val),
+        Err(err) => {
+            Err(RuntimeError::GeneralRuntimeError(format!("unwrap: called on Err({:?})", err)))
+        }
+    }
+}
+
+#[cfg_attr(feature = "cargo-clippy", allow(needless_pass_by_value))]
+pub fn native_unwrap_err(args: Vec<Value>) -> Result<Value, RuntimeError> {
+    match get_result_arg("unwrap_err", &args[0])? {
+        Err(err) => Ok(err),
+        Ok(val) => {
+            Err(RuntimeError::GeneralRuntimeError(format!("unwrap_err: called on Ok({:?})", val)))
+        }
+    }
+}
+
 #[cfg_attr(feature = "cargo-clippy", allow(needless_pass_by_value))]
 pub fn native_run_http_server(args: Vec<Value>) -> Result<(), RuntimeError> {
     use hyper::server::{Server, Request, Response};
@@ -1,7 +1,9 @@
 use parser;
 use runtime::Interpreter;
 use runtime::StmtResult;
-use ast_walk_interpreter::AstWalkInterpreter;
+use runtime::RuntimeError;
+use ast_walk_interpreter::{AstWalkInterpreter, call_func_with_arranged_args};
+use bytecode::BytecodeInterpreter;
 use value::Value;
 use value::Number;
 
@@ -50,3 +52,90 @@ fn f64_support() {
     assert_eq!(run_and_get_last_value("1234567890.012345678;"),
                Value::Number(Number::Float(1234567890.012345678)));
 }
+
+#[test]
+fn bigint_support() {
+    assert_eq!(run_and_get_last_value("9223372036854775808;"),
+               Value::Number(Number::BigInt("9223372036854775808".parse().unwrap())));
+    assert_eq!(run_and_get_last_value("9223372036854775807 + 1 - 1;"),
+               Value::Number(Number::Integer(9223372036854775807)));
+}
+
+#[test]
+fn max_call_depth() {
+    let ast = parser::program("fn f(n) { return 1 + f(n + 1); } f(0);").unwrap();
+    let mut ast_walk_interpreter = AstWalkInterpreter::new();
+    ast_walk_interpreter.set_max_call_depth(20);
+    let (err, _) = ast_walk_interpreter
+        .run_ast_as_program(&ast)
+        .unwrap_err();
+    match err {
+        RuntimeError::InsideFunctionCall(err, frames) => {
+            assert_eq!(*err, RuntimeError::StackOverflow(20));
+            assert_eq!(frames.len(), 20);
+        }
+        _ => panic!("{:?}", err),
+    }
+}
+
+#[test]
+fn max_call_depth_vm() {
+    let ast = parser::program("fn f(n) { return 1 + f(n + 1); } f(0);").unwrap();
+    let mut bytecode_interpreter = BytecodeInterpreter::new();
+    bytecode_interpreter.set_max_call_depth(20);
+    let (err, _) = bytecode_interpreter
+        .run_ast_as_program(&ast)
+        .unwrap_err();
+    match err {
+        RuntimeError::InsideFunctionCall(err, frames) => {
+            assert_eq!(*err, RuntimeError::StackOverflow(20));
+            assert_eq!(frames.len(), 20);
+        }
+        _ => panic!("{:?}", err),
+    }
+}
+
+#[test]
+fn max_call_depth_compiled_function_called_from_outside_vm() {
+    let ast = parser::program("fn f(n) { return 1 + f(n + 1); } f;").unwrap();
+    let func = match BytecodeInterpreter::new().run_ast_as_program(&ast) {
+        Ok(Some(StmtResult::Value(Value::Function(func)))) => func,
+        result => panic!("{:?}", result),
+    };
+    let args = vec![Some(Value::Number(Number::Integer(0)))];
+    let err = call_func_with_arranged_args(&func, args, 15, 20).unwrap_err();
+    match err {
+        RuntimeError::InsideFunctionCall(err, frames) => {
+            assert_eq!(*err, RuntimeError::StackOverflow(20));
+            assert_eq!(frames.len(), 5);
+        }
+        _ => panic!("{:?}", err),
+    }
+}
+
+// Runs every line as the REPL does, returning the value of the last one
+fn run_lines<T: Interpreter>(machine: &mut T, lines: &[&str]) -> Option<StmtResult> {
+    let mut last_result = None;
+    for line in lines {
+        let ast = parser::program(line).unwrap();
+        last_result = machine.run_ast_as_statements(&ast).unwrap();
+    }
+    last_result
+}
+
+#[test]
+fn global_declared_in_later_line() {
+    let lines = ["fn f() { return y; }", "var y = 3;", "f();"];
+    let expected = Some(StmtResult::Value(Value::Number(Number::Integer(3))));
+    assert_eq!(run_lines(&mut AstWalkInterpreter::new(), &lines), expected);
+    assert_eq!(run_lines(&mut BytecodeInterpreter::new(), &lines), expected);
+}
+
+#[test]
+fn unicode_escape_support() {
+    assert_eq!(run_and_get_last_value(r#""\u{1F600}";"#),
+               Value::String("\u{1F600}".to_owned()));
+    // surrogates and code points past U+10FFFF are not chars
+    assert!(parser::program(r#""\u{D800}";"#).is_err());
+    assert!(parser::program(r#""\u{110000}";"#).is_err());
+}
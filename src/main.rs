@@ -1,7 +1,10 @@
+use std::cmp;
 use std::env;
 use std::io::prelude::*;
 use std::io;
 use std::fs::File;
+use std::process;
+use std::thread;
 
 extern crate ansi_term;
 
@@ -27,8 +30,10 @@ mod parser {
 }
 
 mod ast;
+mod bigint;
 mod runtime;
 mod ast_walk_interpreter;
+mod bytecode;
 #[cfg(feature = "llvm-backend")]
 mod llvm_interpreter;
 mod value;
@@ -38,6 +43,8 @@ mod repl;
 mod error;
 mod typechecker;
 mod function;
+mod module;
+mod resolver;
 
 #[cfg(test)]
 mod interpreter_test;
@@ -48,7 +55,9 @@ mod file_test {
 }
 
 use runtime::*;
-use ast_walk_interpreter::AstWalkInterpreter;
+use ast_walk_interpreter::{AstWalkInterpreter, DEFAULT_MAX_CALL_DEPTH, MAX_CALL_DEPTH_LIMIT,
+                           STACK_PER_CALL};
+use bytecode::BytecodeInterpreter;
 #[cfg(feature = "llvm-backend")]
 use llvm_interpreter::LLVMInterpreter;
 
@@ -57,11 +66,11 @@ use error::*;
 // FIXME: How do you represent the usage style in POSIX notation?
 fn print_usage() {
     if cfg!(feature = "llvm-backend") {
-        println!("usage: balloon [--repl-llvm | [MODE] FILE]
+        println!("usage: balloon [--repl-llvm | [OPTIONS] [MODE] FILE]
 
 --repl-llvm     launches the experimental REPL");
     } else {
-        println!("usage: balloon [[MODE] FILE]");
+        println!("usage: balloon [OPTIONS] [[MODE] FILE]");
     }
     println!("
 where MODE is one of:
@@ -69,24 +78,96 @@ where MODE is one of:
 --check         type check the file [FILE]
 --parse         only parse the file [FILE], don't run it
 
-Not passing any arguments to balloon will start the REPL.");
+OPTIONS can be passed first:
+--vm                runs the program on the bytecode VM instead of the
+                    AST walker
+--max-call-depth=N  allows N nested function calls (default {}, at most
+                    {}) before a stack overflow error
+
+Not passing any arguments to balloon will start the REPL.",
+             DEFAULT_MAX_CALL_DEPTH,
+             MAX_CALL_DEPTH_LIMIT);
 }
 
+// The AST walker uses the Rust stack for every nested function call, so it runs
+// on a thread with enough stack for the maximum call depth, and at least this much
+const INTERPRETER_STACK_SIZE: usize = 256 * 1024 * 1024;
+
 fn main() {
-    let args: Vec<String> = env::args().collect();
+    let mut args: Vec<String> = env::args().collect();
+    let mut max_call_depth = DEFAULT_MAX_CALL_DEPTH;
+    let mut use_vm = false;
+    while args.len() > 1 && (args[1] == "--vm" || args[1].starts_with("--max-call-depth=")) {
+        let option = args.remove(1);
+        if option == "--vm" {
+            use_vm = true;
+            continue;
+        }
+        match option.trim_start_matches("--max-call-depth=").parse() {
+            Ok(depth) if depth <= MAX_CALL_DEPTH_LIMIT => max_call_depth = depth,
+            _ => return print_usage(),
+        }
+    }
+
+    let stack_size = cmp::max(INTERPRETER_STACK_SIZE, max_call_depth * STACK_PER_CALL);
+    let spawn_result = thread::Builder::new()
+        .stack_size(stack_size)
+        .spawn(move || run_with_options(&args, max_call_depth, use_vm));
+    match spawn_result {
+        Ok(interpreter_thread) => {
+            if interpreter_thread.join().is_err() {
+                process::exit(101);
+            }
+        }
+        Err(e) => {
+            println!("Could not reserve the stack for {} nested function calls: {}",
+                     max_call_depth,
+                     e);
+            process::exit(1);
+        }
+    }
+}
+
+fn run_with_options(args: &[String], max_call_depth: usize, use_vm: bool) {
+    if use_vm {
+        run_mode(args, |possible_file_name| {
+            let mut machine = match possible_file_name {
+                Some(file_name) => BytecodeInterpreter::for_file(file_name),
+                None => BytecodeInterpreter::new(),
+            };
+            machine.set_max_call_depth(max_call_depth);
+            machine
+        })
+    } else {
+        run_mode(args, |possible_file_name| {
+            let mut machine = match possible_file_name {
+                Some(file_name) => AstWalkInterpreter::for_file(file_name),
+                None => AstWalkInterpreter::new(),
+            };
+            machine.set_max_call_depth(max_call_depth);
+            machine
+        })
+    }
+}
 
+// Runs the REPL or the mode given by the arguments after the options, on the
+// interpreters created by `new_machine` for a file or for the REPL
+fn run_mode<T, F>(args: &[String], new_machine: F)
+    where T: Interpreter,
+          F: Fn(Option<&str>) -> T
+{
     match args.len() {
-        1 => repl::run_repl(AstWalkInterpreter::new()),
+        1 => repl::run_repl(new_machine(None)),
         2 => {
             match args[1].as_str() {
                 #[cfg(feature = "llvm-backend")]
                 "--repl-llvm" => repl::run_repl(LLVMInterpreter::new()),
-                filepath => run_file(filepath, AstWalkInterpreter::new()),
+                filepath => run_file(filepath, new_machine(Some(filepath))),
             }
         }
         3 => {
             match args[1].as_str() {
-                "--run" => run_file(&args[2], AstWalkInterpreter::new()),
+                "--run" => run_file(&args[2], new_machine(Some(&args[2]))),
                 "--check" => typecheck_file(&args[2]),
                 "--parse" => {
                     if let Some(ast) = parse_file(&args[2]) {
@@ -143,7 +224,7 @@ fn run_file<T: Interpreter>(file_name: &str, mut machine: T) {
 
 fn typecheck_file(file_name: &str) {
     if let Some(ast) = parse_file(file_name) {
-        let mut checker = typechecker::TypeChecker::new();
+        let mut checker = typechecker::TypeChecker::for_file(file_name);
         checker.check_program(&ast);
         let issues = checker.get_issues();
 
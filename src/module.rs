@@ -0,0 +1,133 @@
+use std::collections::BTreeMap;
+use std::fs::File;
+use std::io::prelude::*;
+use std::path::{Component, Path, PathBuf};
+
+use ast::StmtNode;
+use parser;
+use runtime::RuntimeError;
+
+/// A file named by an `import` statement
+pub struct ModulePath {
+    /// The path relative to the working directory, used in error messages
+    pub display_path: String,
+    pub canonical_path: PathBuf,
+}
+
+/// Keeps track of the files loaded through `import` statements, so that every
+/// file is only loaded once and import cycles can be detected.
+/// `T` is what a loaded module is represented as, such as its value when
+/// running or its type when typechecking.
+pub struct ModuleLoader<T> {
+    loaded: BTreeMap<PathBuf, T>,
+    // the canonical and display paths of the files being loaded, outermost first
+    loading: Vec<(PathBuf, String)>,
+}
+
+impl<T: Clone> ModuleLoader<T> {
+    /// Creates a loader for a program in the file `main_file`, or for code that
+    /// is not in a file, like in the REPL
+    pub fn new(main_file: &Option<String>) -> ModuleLoader<T> {
+        let mut loading = Vec::new();
+        if let Some(ref file) = *main_file {
+            if let Ok(canonical_path) = Path::new(file).canonicalize() {
+                loading.push((canonical_path, file.clone()));
+            }
+        }
+        ModuleLoader {
+            loaded: BTreeMap::new(),
+            loading: loading,
+        }
+    }
+
+    pub fn get(&self, module_path: &ModulePath) -> Option<T> {
+        self.loaded.get(&module_path.canonical_path).cloned()
+    }
+
+    /// Marks the module as being loaded, unless it is already being loaded, which
+    /// means it (indirectly) imports itself
+    pub fn start_loading(&mut self, module_path: &ModulePath) -> Result<(), RuntimeError> {
+        let position = self.loading
+            .iter()
+            .position(|&(ref path, _)| *path == module_path.canonical_path);
+        if let Some(position) = position {
+            let mut cycle: Vec<String> = self.loading[position..]
+                .iter()
+                .map(|&(_, ref display_path)| display_path.clone())
+                .collect();
+            cycle.push(module_path.display_path.clone());
+            return Err(RuntimeError::ImportCycle(cycle));
+        }
+        self.loading
+            .push((module_path.canonical_path.clone(), module_path.display_path.clone()));
+        Ok(())
+    }
+
+    /// Marks the module as loaded. Modules that failed to load are not remembered.
+    pub fn finish_loading(&mut self, module_path: &ModulePath, possible_module: Option<T>) {
+        self.loading.pop();
+        if let Some(module) = possible_module {
+            self.loaded
+                .insert(module_path.canonical_path.clone(), module);
+        }
+    }
+}
+
+/// Finds the file that `import_path` refers to when imported from `importing_file`.
+/// Paths are relative to the directory of the importing file, or to the working
+/// directory for code that is not in a file.
+pub fn resolve_import(importing_file: &Option<String>,
+                      import_path: &str)
+                      -> Result<ModulePath, RuntimeError> {
+    let dir = match *importing_file {
+        Some(ref file) => {
+            Path::new(file)
+                .parent()
+                .map(|dir| dir.to_path_buf())
+                .unwrap_or_default()
+        }
+        None => PathBuf::new(),
+    };
+    let path = normalize_path(&dir.join(import_path));
+    let display_path = path.to_string_lossy().into_owned();
+    match path.canonicalize() {
+        Ok(canonical_path) => {
+            Ok(ModulePath {
+                   display_path: display_path,
+                   canonical_path: canonical_path,
+               })
+        }
+        Err(_) => Err(RuntimeError::ImportError(display_path, "file not found".to_owned())),
+    }
+}
+
+pub fn parse_module(module_path: &ModulePath) -> Result<Vec<StmtNode>, RuntimeError> {
+    let mut input = String::new();
+    File::open(&module_path.canonical_path)
+        .and_then(|mut file| file.read_to_string(&mut input))
+        .map_err(|e| RuntimeError::ImportError(module_path.display_path.clone(), e.to_string()))?;
+    parser::program(&input).map_err(|e| {
+        RuntimeError::ImportError(module_path.display_path.clone(),
+                                  format!("parse error on line {}, col {}", e.line, e.column))
+    })
+}
+
+// Removes `.` and `..` components without looking at the file system, so that
+// paths in error messages stay readable
+fn normalize_path(path: &Path) -> PathBuf {
+    let mut normalized = PathBuf::new();
+    for component in path.components() {
+        match component {
+            Component::CurDir => {}
+            Component::ParentDir => {
+                if normalized.file_name().is_some() {
+                    normalized.pop();
+                } else {
+                    normalized.push("..");
+                }
+            }
+            component => normalized.push(component.as_os_str()),
+        }
+    }
+    normalized
+}
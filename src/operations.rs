@@ -1,7 +1,34 @@
+use std::rc::Rc;
+use std::cmp::Ordering;
+use std::cell::RefCell;
+
 use value::*;
 use ast::{BinOp, UnOp};
 use runtime::RuntimeError;
 
+/// Applies the binary operator `op`, as in `a op b`
+pub fn binary(op: &BinOp, a: Value, b: Value) -> Result<Value, RuntimeError> {
+    match *op {
+        BinOp::Add => add(a, b),
+        BinOp::Sub => subtract(a, b),
+        BinOp::Mul => multiply(a, b),
+        BinOp::Div => divide(a, b),
+        BinOp::Mod => modulo(a, b),
+        BinOp::Lt => less_than(a, b),
+        BinOp::Lte => less_than_or_equal(a, b),
+        BinOp::Gt => greater_than(a, b),
+        BinOp::Gte => greater_than_or_equal(a, b),
+        BinOp::Eq => Ok(Value::Bool(a == b)),
+        BinOp::Neq => Ok(Value::Bool(a != b)),
+        BinOp::Pow => power(a, b),
+        ref op @ BinOp::BitAnd |
+        ref op @ BinOp::BitOr |
+        ref op @ BinOp::BitXor => bitwise(op.clone(), a, b),
+        BinOp::Shl => shift_left(a, b),
+        BinOp::Shr => shift_right(a, b),
+    }
+}
+
 pub fn unary_minus(a: Value) -> Result<Value, RuntimeError> {
     match a {
         Value::Number(x) => Ok(Value::Number(-x)),
@@ -16,6 +43,11 @@ pub fn add(a: Value, b: Value) -> Result<Value, RuntimeError> {
             a.append(&mut b);
             Ok(Value::Tuple(a))
         }
+        (Value::List(a), Value::List(b)) => {
+            let mut elems = a.borrow().clone();
+            elems.extend(b.borrow().iter().cloned());
+            Ok(Value::List(Rc::new(RefCell::new(elems))))
+        }
         (Value::String(sa), Value::String(sb)) => Ok(Value::String(sa + &sb)),
         (Value::String(s), other) => Ok(Value::String(s + &other.to_string())),
         (other, Value::String(s)) => Ok(Value::String(other.to_string() + &s)),
@@ -39,41 +71,92 @@ pub fn multiply(a: Value, b: Value) -> Result<Value, RuntimeError> {
 
 pub fn divide(a: Value, b: Value) -> Result<Value, RuntimeError> {
     match (a, b) {
-        (Value::Number(a), Value::Number(b)) => Ok(Value::Number(a / b)),
+        (Value::Number(a), Value::Number(b)) => Ok(Value::Number(a.checked_div(b)?)),
         (a, b) => Err(RuntimeError::BinaryTypeError(BinOp::Div, a.get_type(), b.get_type())),
     }
 }
 
 pub fn modulo(a: Value, b: Value) -> Result<Value, RuntimeError> {
     match (a, b) {
-        (Value::Number(a), Value::Number(b)) => Ok(Value::Number(a.signum() * (a % b))),
+        (Value::Number(a), Value::Number(b)) => {
+            let sign = a.signum();
+            Ok(Value::Number(sign * a.checked_rem(b)?))
+        }
         (a, b) => Err(RuntimeError::BinaryTypeError(BinOp::Mod, a.get_type(), b.get_type())),
     }
 }
 
-pub fn less_than(a: Value, b: Value) -> Result<Value, RuntimeError> {
+pub fn power(a: Value, b: Value) -> Result<Value, RuntimeError> {
     match (a, b) {
-        (Value::Number(a), Value::Number(b)) => Ok(Value::Bool(a < b)),
-        (a, b) => Err(RuntimeError::BinaryTypeError(BinOp::Lt, a.get_type(), b.get_type())),
+        (Value::Number(a), Value::Number(b)) => Ok(Value::Number(a.pow(b)?)),
+        (a, b) => Err(RuntimeError::BinaryTypeError(BinOp::Pow, a.get_type(), b.get_type())),
     }
 }
 
-pub fn less_than_or_equal(a: Value, b: Value) -> Result<Value, RuntimeError> {
+/// Applies `&`, `|` or `^`
+pub fn bitwise(op: BinOp, a: Value, b: Value) -> Result<Value, RuntimeError> {
     match (a, b) {
-        (Value::Number(a), Value::Number(b)) => Ok(Value::Bool(a <= b)),
-        (a, b) => Err(RuntimeError::BinaryTypeError(BinOp::Lte, a.get_type(), b.get_type())),
+        (Value::Number(a), Value::Number(b)) => Ok(Value::Number(a.bitwise(op, b)?)),
+        (a, b) => Err(RuntimeError::BinaryTypeError(op, a.get_type(), b.get_type())),
     }
 }
-pub fn greater_than(a: Value, b: Value) -> Result<Value, RuntimeError> {
+
+pub fn shift_left(a: Value, b: Value) -> Result<Value, RuntimeError> {
     match (a, b) {
-        (Value::Number(a), Value::Number(b)) => Ok(Value::Bool(a > b)),
-        (a, b) => Err(RuntimeError::BinaryTypeError(BinOp::Gt, a.get_type(), b.get_type())),
+        (Value::Number(a), Value::Number(b)) => Ok(Value::Number(a.shift_left(b)?)),
+        (a, b) => Err(RuntimeError::BinaryTypeError(BinOp::Shl, a.get_type(), b.get_type())),
     }
 }
 
+pub fn shift_right(a: Value, b: Value) -> Result<Value, RuntimeError> {
+    match (a, b) {
+        (Value::Number(a), Value::Number(b)) => Ok(Value::Number(a.shift_right(b)?)),
+        (a, b) => Err(RuntimeError::BinaryTypeError(BinOp::Shr, a.get_type(), b.get_type())),
+    }
+}
+
+pub fn less_than(a: Value, b: Value) -> Result<Value, RuntimeError> {
+    compare(BinOp::Lt, &a, &b, |ordering| ordering == Ordering::Less)
+}
+
+pub fn less_than_or_equal(a: Value, b: Value) -> Result<Value, RuntimeError> {
+    compare(BinOp::Lte, &a, &b, |ordering| ordering != Ordering::Greater)
+}
+
+pub fn greater_than(a: Value, b: Value) -> Result<Value, RuntimeError> {
+    compare(BinOp::Gt, &a, &b, |ordering| ordering == Ordering::Greater)
+}
+
 pub fn greater_than_or_equal(a: Value, b: Value) -> Result<Value, RuntimeError> {
+    compare(BinOp::Gte, &a, &b, |ordering| ordering != Ordering::Less)
+}
+
+// Comparisons involving NaN are false
+fn compare(op: BinOp,
+           a: &Value,
+           b: &Value,
+           accept: fn(Ordering) -> bool)
+           -> Result<Value, RuntimeError> {
+    match partial_compare(&op, a, b)? {
+        Some(ordering) => Ok(Value::Bool(accept(ordering))),
+        None => Ok(Value::Bool(false)),
+    }
+}
+
+// Numbers are ordered by value, and strings and tuples lexicographically
+fn partial_compare(op: &BinOp, a: &Value, b: &Value) -> Result<Option<Ordering>, RuntimeError> {
     match (a, b) {
-        (Value::Number(a), Value::Number(b)) => Ok(Value::Bool(a >= b)),
-        (a, b) => Err(RuntimeError::BinaryTypeError(BinOp::Gte, a.get_type(), b.get_type())),
+        (&Value::Number(ref a), &Value::Number(ref b)) => Ok(a.partial_cmp(b)),
+        (&Value::String(ref a), &Value::String(ref b)) => Ok(Some(a.cmp(b))),
+        (&Value::Tuple(ref a), &Value::Tuple(ref b)) => {
+            for (x, y) in a.iter().zip(b) {
+                match partial_compare(op, x, y)? {
+                    Some(Ordering::Equal) => {}
+                    ordering => return Ok(ordering),
+                }
+            }
+            Ok(Some(a.len().cmp(&b.len())))
+        }
+        _ => Err(RuntimeError::BinaryTypeError(op.clone(), a.get_type(), b.get_type())),
     }
 }
@@ -0,0 +1,498 @@
+use ast::*;
+use environment::Environment;
+use runtime::{RuntimeError, RuntimeErrorWithPosition};
+
+struct ScopeVariable {
+    name: String,
+    /// Whether the declaration of the variable comes before the code being resolved
+    declared: bool,
+}
+
+// A block, function or other construct that gets a frame of its own when it
+// declares any variables. The slot of a variable is its index in `variables`.
+struct Scope {
+    variables: Vec<ScopeVariable>,
+    // whether this is the scope of the parameters of a function
+    is_function: bool,
+}
+
+/// Works out where the AST walker keeps every variable of a program, so that
+/// variables of blocks and functions are found by indexing into frames rather
+/// than by looking up their names
+struct Resolver {
+    // the scopes being resolved, innermost last. Declarations outside of any
+    // scope are global.
+    scopes: Vec<Scope>,
+    // the names declared as globals
+    globals: Vec<String>,
+    // the names that are looked up as globals, where they are referred to
+    global_references: Vec<(String, OffsetSpan)>,
+}
+
+/// Annotates every variable in `program` with where the AST walker keeps it,
+/// and every block, function and match arm with the number of variables it
+/// declares. Fails with a `ReferenceError` for a name that refers to a global
+/// which neither the program nor `env` declares, before any of it runs.
+pub fn resolve_program(program: &mut [StmtNode],
+                       env: &Environment)
+                       -> Result<(), RuntimeErrorWithPosition> {
+    let resolver = resolve(program);
+    check_global_references(&resolver.global_references, &resolver.globals, env)
+}
+
+/// Annotates `statements` like `resolve_program`, for the REPL, where functions
+/// can refer to globals that are declared by later input
+pub fn resolve_statements(statements: &mut [StmtNode]) {
+    resolve(statements);
+}
+
+fn resolve(statements: &mut [StmtNode]) -> Resolver {
+    let mut resolver = Resolver {
+        scopes: Vec::new(),
+        globals: Vec::new(),
+        global_references: Vec::new(),
+    };
+    for statement in statements.iter_mut() {
+        resolver.resolve_stmt(statement);
+    }
+    resolver
+}
+
+/// Fails with a `ReferenceError` for the first of `references` to a global
+/// that is not in `declared` or in `env`, such as a builtin
+pub fn check_global_references(references: &[(String, OffsetSpan)],
+                               declared: &[String],
+                               env: &Environment)
+                               -> Result<(), RuntimeErrorWithPosition> {
+    for &(ref name, pos) in references {
+        if !declared.contains(name) && !env.contains(name) {
+            return Err((RuntimeError::ReferenceError(name.clone()), pos));
+        }
+    }
+    Ok(())
+}
+
+impl Resolver {
+    // Enters a scope where `names` are declared, which can be referred to by
+    // the functions defined in it before their declaration runs
+    fn begin_scope(&mut self, names: Vec<String>, is_function: bool) {
+        let mut variables: Vec<ScopeVariable> = Vec::new();
+        for name in names {
+            if variables.iter().all(|variable| variable.name != name) {
+                variables.push(ScopeVariable {
+                                   name: name,
+                                   declared: false,
+                               });
+            }
+        }
+        self.scopes
+            .push(Scope {
+                      variables: variables,
+                      is_function: is_function,
+                  });
+    }
+
+    // Leaves the innermost scope, returning the number of variables it declares
+    fn end_scope(&mut self) -> usize {
+        self.scopes.pop().unwrap().variables.len()
+    }
+
+    // Declares `name` in the innermost scope, or as a global at the top level
+    fn declare(&mut self, name: &str) -> VarAddress {
+        let scope = match self.scopes.last_mut() {
+            Some(scope) => scope,
+            None => {
+                self.globals.push(name.to_owned());
+                return VarAddress::Global;
+            }
+        };
+        // declaring a name again in the same scope replaces the variable's value
+        let slot = match scope
+                  .variables
+                  .iter()
+                  .rposition(|variable| variable.name == name) {
+            Some(slot) => slot,
+            None => {
+                scope
+                    .variables
+                    .push(ScopeVariable {
+                              name: name.to_owned(),
+                              declared: false,
+                          });
+                scope.variables.len() - 1
+            }
+        };
+        scope.variables[slot].declared = true;
+        VarAddress::Local(0, slot)
+    }
+
+    fn declare_pattern(&mut self, pattern: &mut PatternNode) {
+        match pattern.data {
+            Pattern::Binding(ref name, ref mut address) => *address = self.declare(name),
+            Pattern::Tuple(ref mut patterns) => {
+                for pattern in patterns {
+                    self.declare_pattern(pattern);
+                }
+            }
+            Pattern::Wildcard |
+            Pattern::Literal(_) => {}
+        }
+    }
+
+    // Finds the variable `name` refers to. Variables that are not declared yet
+    // where a function refers to them may or may not be declared when it is
+    // called, so they are left to be looked up by name, like the variables
+    // that are not declared in any scope.
+    fn resolve_name(&mut self, name: &str, pos: OffsetSpan) -> VarAddress {
+        let mut depth = 0;
+        let mut in_enclosing_function = false;
+        for scope in self.scopes.iter().rev() {
+            let possible_variable = scope
+                .variables
+                .iter()
+                .rposition(|variable| variable.name == name)
+                .map(|slot| (slot, scope.variables[slot].declared));
+            match possible_variable {
+                Some((slot, true)) => return VarAddress::Local(depth, slot),
+                Some((_, false)) if in_enclosing_function => return VarAddress::Unresolved,
+                _ => {}
+            }
+            // scopes that declare nothing get no frame
+            if !scope.variables.is_empty() {
+                depth += 1;
+            }
+            if scope.is_function {
+                in_enclosing_function = true;
+            }
+        }
+        self.global_references.push((name.to_owned(), pos));
+        VarAddress::Global
+    }
+
+    fn resolve_stmt(&mut self, s: &mut StmtNode) {
+        match s.data {
+            Stmt::VarDecl(ref mut variable, ref mut expr) => {
+                self.resolve_expr(expr);
+                match *variable {
+                    Variable::Identifier(_, ref name, _, ref mut address) => {
+                        *address = self.declare(name);
+                    }
+                    Variable::Pattern(_, ref mut pattern) => self.declare_pattern(pattern),
+                }
+            }
+            Stmt::StructDecl(ref mut struct_decl) => {
+                struct_decl.address = self.declare(&struct_decl.name);
+            }
+            Stmt::Assign(ref mut lhs_expr, ref mut expr) => {
+                self.resolve_expr(expr);
+                self.resolve_lhs_expr(lhs_expr);
+            }
+            Stmt::AssignOp(ref mut lhs_expr, _, ref mut expr) => {
+                self.resolve_expr(expr);
+                self.resolve_lhs_expr(lhs_expr);
+            }
+            Stmt::Block(ref mut statements, ref mut num_slots) => {
+                *num_slots = self.resolve_block(statements);
+            }
+            Stmt::IfThen(ref mut if_then_stmt) => self.resolve_if_then(if_then_stmt),
+            Stmt::Loop(_, ref mut block) => self.resolve_stmt(block),
+            Stmt::While(_, ref mut cond, ref mut block) => {
+                self.resolve_expr(cond);
+                self.resolve_stmt(block);
+            }
+            Stmt::ForIn(_, ref var_name, ref mut iterable_expr, ref mut block) => {
+                self.resolve_expr(iterable_expr);
+                // every iteration declares the variable in a frame of its own
+                self.begin_scope(vec![var_name.clone()], false);
+                self.declare(var_name);
+                self.resolve_stmt(block);
+                self.end_scope();
+            }
+            Stmt::TryCatch(ref mut try_block, ref error_name, ref mut catch_block) => {
+                self.resolve_stmt(try_block);
+                self.begin_scope(vec![error_name.clone()], false);
+                self.declare(error_name);
+                self.resolve_stmt(catch_block);
+                self.end_scope();
+            }
+            Stmt::Import(_, ref name) => {
+                self.declare(name);
+            }
+            Stmt::Expr(ref mut expr) |
+            Stmt::Throw(ref mut expr) |
+            Stmt::Return(Some(ref mut expr)) |
+            Stmt::Break(_, Some(ref mut expr)) => self.resolve_expr(expr),
+            Stmt::Return(None) |
+            Stmt::Break(_, None) |
+            Stmt::Continue(_) |
+            Stmt::Empty => {}
+        }
+    }
+
+    // Resolves the statements of a block, returning the number of variables
+    // they declare
+    fn resolve_block(&mut self,
+                     statements: &mut [StmtNode])
+                     -> usize {
+        let mut names = Vec::new();
+        for statement in statements.iter() {
+            declared_names(statement, &mut names);
+        }
+        self.begin_scope(names, false);
+        for statement in statements.iter_mut() {
+            self.resolve_stmt(statement);
+        }
+        self.end_scope()
+    }
+
+    fn resolve_if_then(&mut self,
+                       if_then_stmt: &mut IfThenStmt) {
+        self.resolve_expr(&mut if_then_stmt.cond);
+        self.resolve_stmt(&mut if_then_stmt.then_block);
+        if let Some(ref mut else_block) = if_then_stmt.maybe_else_block {
+            self.resolve_stmt(else_block);
+        }
+    }
+
+    fn resolve_lhs_expr(&mut self, lhs_expr: &mut LhsExprNode) {
+        match lhs_expr.data {
+            LhsExpr::Identifier(ref id, ref mut address) => {
+                *address = self.resolve_name(id, lhs_expr.pos)
+            }
+            LhsExpr::Field(ref mut object_lhs_expr, _) => self.resolve_lhs_expr(object_lhs_expr),
+            LhsExpr::Index(ref mut object_lhs_expr, ref mut index_expr) => {
+                self.resolve_lhs_expr(object_lhs_expr);
+                self.resolve_expr(index_expr);
+            }
+        }
+    }
+
+    fn resolve_expr(&mut self, e: &mut ExprNode) {
+        match e.data {
+            Expr::Identifier(ref id, ref mut address) => *address = self.resolve_name(id, e.pos),
+            Expr::Tuple(ref mut elems) |
+            Expr::List(ref mut elems) => {
+                for elem in elems {
+                    self.resolve_expr(elem);
+                }
+            }
+            Expr::Map(ref mut entries) => {
+                for &mut (_, ref mut value_expr) in entries {
+                    self.resolve_expr(value_expr);
+                }
+            }
+            Expr::Unary(_, ref mut expr) |
+            Expr::UnaryLogical(_, ref mut expr) |
+            Expr::FieldAccess(ref mut expr, _) |
+            Expr::Try(ref mut expr) => self.resolve_expr(expr),
+            Expr::Binary(ref mut expr1, _, ref mut expr2) |
+            Expr::BinaryLogical(ref mut expr1, _, ref mut expr2) |
+            Expr::MemberByIdx(ref mut expr1, ref mut expr2) => {
+                self.resolve_expr(expr1);
+                self.resolve_expr(expr2);
+            }
+            Expr::Loop(_, ref mut block) => self.resolve_stmt(block),
+            Expr::IfThen(ref mut if_then_stmt) => self.resolve_if_then(if_then_stmt),
+            Expr::Block(ref mut statements, ref mut num_slots) => {
+                *num_slots = self.resolve_block(statements);
+            }
+            Expr::Match(ref mut scrutinee, ref mut arms) => {
+                self.resolve_expr(scrutinee);
+                for arm in arms {
+                    self.resolve_match_arm(&mut arm.data);
+                }
+            }
+            Expr::FnDef(ref mut fn_def_expr) => self.resolve_fn_def(fn_def_expr),
+            Expr::FnCall(ref mut f_expr, ref mut args, ref mut named_args) => {
+                self.resolve_expr(f_expr);
+                for arg in args {
+                    self.resolve_expr(arg);
+                }
+                for &mut (_, ref mut arg) in named_args {
+                    self.resolve_expr(arg);
+                }
+            }
+            Expr::Literal(_) => {}
+        }
+    }
+
+    fn resolve_match_arm(&mut self, arm: &mut MatchArm) {
+        let mut names = Vec::new();
+        pattern_names(&arm.pattern.data, &mut names);
+        if let Some(ref guard) = arm.guard {
+            declared_names_in_expr(guard, &mut names);
+        }
+        declared_names_in_expr(&arm.body, &mut names);
+        self.begin_scope(names, false);
+        self.declare_pattern(&mut arm.pattern);
+        if let Some(ref mut guard) = arm.guard {
+            self.resolve_expr(guard);
+        }
+        self.resolve_expr(&mut arm.body);
+        arm.num_slots = self.end_scope();
+    }
+
+    fn resolve_fn_def(&mut self,
+                      fn_def_expr: &mut FnDefExpr) {
+        if let Some(ref id) = fn_def_expr.maybe_id {
+            fn_def_expr.address = self.declare(id);
+        }
+        // every parameter has the slot of its position, even if two of them
+        // have the same name
+        let mut variables: Vec<ScopeVariable> = fn_def_expr
+            .params
+            .iter()
+            .map(|param| {
+                     ScopeVariable {
+                         name: param.name.clone(),
+                         declared: false,
+                     }
+                 })
+            .collect();
+        let mut names = Vec::new();
+        for param in &fn_def_expr.params {
+            if let Some(ref default) = param.default {
+                declared_names_in_expr(default, &mut names);
+            }
+        }
+        for name in names {
+            if variables.iter().all(|variable| variable.name != name) {
+                variables.push(ScopeVariable {
+                                   name: name,
+                                   declared: false,
+                               });
+            }
+        }
+        self.scopes
+            .push(Scope {
+                      variables: variables,
+                      is_function: true,
+                  });
+        // defaults can refer to the parameters before them
+        for (slot, param) in fn_def_expr.params.iter_mut().enumerate() {
+            if let Some(ref mut default) = param.default {
+                self.resolve_expr(default);
+            }
+            self.scopes.last_mut().unwrap().variables[slot].declared = true;
+        }
+        self.resolve_stmt(&mut fn_def_expr.body);
+        fn_def_expr.num_slots = self.end_scope();
+    }
+}
+
+/// Collects the names that a statement declares in the scope it is in, which
+/// includes the named functions defined in its expressions
+pub fn declared_names(statement: &StmtNode, names: &mut Vec<String>) {
+    match statement.data {
+        Stmt::VarDecl(ref variable, ref expr) => {
+            declared_names_in_expr(expr, names);
+            match *variable {
+                Variable::Identifier(_, ref name, ..) => names.push(name.clone()),
+                Variable::Pattern(_, ref pattern) => pattern_names(&pattern.data, names),
+            }
+        }
+        Stmt::StructDecl(StructDecl { ref name, .. }) |
+        Stmt::Import(_, ref name) => names.push(name.clone()),
+        Stmt::Assign(ref lhs_expr, ref expr) |
+        Stmt::AssignOp(ref lhs_expr, _, ref expr) => {
+            declared_names_in_expr(expr, names);
+            declared_names_in_lhs_expr(lhs_expr, names);
+        }
+        Stmt::IfThen(ref if_then_stmt) => declared_names_in_if_then(if_then_stmt, names),
+        Stmt::Expr(ref expr) |
+        Stmt::While(_, ref expr, _) |
+        Stmt::ForIn(_, _, ref expr, _) |
+        Stmt::Throw(ref expr) |
+        Stmt::Return(Some(ref expr)) |
+        Stmt::Break(_, Some(ref expr)) => declared_names_in_expr(expr, names),
+        // the blocks of the other statements are scopes of their own
+        Stmt::Block(..) |
+        Stmt::Loop(..) |
+        Stmt::TryCatch(..) |
+        Stmt::Return(None) |
+        Stmt::Break(_, None) |
+        Stmt::Continue(_) |
+        Stmt::Empty => {}
+    }
+}
+
+fn declared_names_in_if_then(if_then_stmt: &IfThenStmt, names: &mut Vec<String>) {
+    declared_names_in_expr(&if_then_stmt.cond, names);
+    declared_names(&if_then_stmt.then_block, names);
+    // an `else if` is not a block, so its condition is in the same scope
+    if let Some(ref else_block) = if_then_stmt.maybe_else_block {
+        declared_names(else_block, names);
+    }
+}
+
+fn declared_names_in_lhs_expr(lhs_expr: &LhsExprNode, names: &mut Vec<String>) {
+    match lhs_expr.data {
+        LhsExpr::Identifier(..) => {}
+        LhsExpr::Field(ref object_lhs_expr, _) => {
+            declared_names_in_lhs_expr(object_lhs_expr, names)
+        }
+        LhsExpr::Index(ref object_lhs_expr, ref index_expr) => {
+            declared_names_in_lhs_expr(object_lhs_expr, names);
+            declared_names_in_expr(index_expr, names);
+        }
+    }
+}
+
+// Collects the names of the named functions defined in an expression, which are
+// declared in the scope the expression is in
+fn declared_names_in_expr(expr: &ExprNode, names: &mut Vec<String>) {
+    match expr.data {
+        Expr::FnDef(FnDefExpr { ref maybe_id, .. }) => names.extend(maybe_id.clone()),
+        Expr::Tuple(ref elems) |
+        Expr::List(ref elems) => {
+            for elem in elems {
+                declared_names_in_expr(elem, names);
+            }
+        }
+        Expr::Map(ref entries) => {
+            for &(_, ref value_expr) in entries {
+                declared_names_in_expr(value_expr, names);
+            }
+        }
+        Expr::Unary(_, ref expr) |
+        Expr::UnaryLogical(_, ref expr) |
+        Expr::FieldAccess(ref expr, _) |
+        Expr::Try(ref expr) |
+        Expr::Match(ref expr, _) => declared_names_in_expr(expr, names),
+        Expr::Binary(ref expr1, _, ref expr2) |
+        Expr::BinaryLogical(ref expr1, _, ref expr2) |
+        Expr::MemberByIdx(ref expr1, ref expr2) => {
+            declared_names_in_expr(expr1, names);
+            declared_names_in_expr(expr2, names);
+        }
+        Expr::FnCall(ref f_expr, ref args, ref named_args) => {
+            declared_names_in_expr(f_expr, names);
+            for arg in args {
+                declared_names_in_expr(arg, names);
+            }
+            for &(_, ref arg) in named_args {
+                declared_names_in_expr(arg, names);
+            }
+        }
+        Expr::IfThen(ref if_then_stmt) => declared_names_in_if_then(if_then_stmt, names),
+        // blocks, loops and match arms are scopes of their own
+        Expr::Literal(_) |
+        Expr::Identifier(..) |
+        Expr::Loop(..) |
+        Expr::Block(..) => {}
+    }
+}
+
+/// Collects the names bound by a pattern, in the order `match_pattern` binds them
+pub fn pattern_names(pattern: &Pattern, names: &mut Vec<String>) {
+    match *pattern {
+        Pattern::Binding(ref name, _) => names.push(name.clone()),
+        Pattern::Tuple(ref patterns) => {
+            for pattern in patterns {
+                pattern_names(&pattern.data, names);
+            }
+        }
+        Pattern::Wildcard |
+        Pattern::Literal(_) => {}
+    }
+}
@@ -8,6 +8,8 @@ pub enum RuntimeError {
     ReferenceError(String),
     /// When an undeclared identifier is assigned to
     UndeclaredAssignment(String),
+    /// When an identifier declared with `let` is assigned to
+    ImmutableAssignment(String),
     /// When a binary op cannot be performed on the given types
     BinaryTypeError(BinOp, Type, Type),
     /// When a unary op cannot be performed on the given type
@@ -20,27 +22,187 @@ pub enum RuntimeError {
     SubscriptOnNonSubscriptable(Type),
     NonIntegralSubscript(Type),
     IndexOutOfBounds(i64),
+    /// When an element of a value that cannot be modified, such as a tuple, is assigned to
+    IndexAssignmentOnImmutable(Type),
+    /// When a for loop is given a value that cannot be iterated over
+    NonIterable(Type),
+    /// When `?` is applied to a value that is not an `Ok` or `Err`
+    TryOnNonResult(Type),
+    /// When a value matches none of the arms of a `match`, or does not fit the
+    /// pattern of a destructuring declaration
+    NoMatchingPattern(Type),
+    /// When a pattern binds the same name more than once
+    DuplicateBinding(String),
+    /// When an imported file cannot be read or parsed
+    ImportError(String, String),
+    /// When files import each other, listing the files in the order they import
+    /// each other, ending with the file that was imported again
+    ImportCycle(Vec<String>),
+    /// When an imported module has no top-level binding with the accessed name
+    UnknownModuleMember(String, String),
+    /// When a top-level binding of an imported module is assigned to from outside
+    /// of the module
+    ModuleMemberAssignment(String, String),
+    /// When a runtime error occurs while running an imported file
+    InsideModule(String, Box<RuntimeErrorWithPosition>),
+    /// When a map is subscripted with a key it does not contain
+    KeyNotFound(String),
+    /// When a map is subscripted with a non-String key
+    NonStringMapKey(Type),
+    /// When a field access obj.field is made on a value that is not a struct
+    FieldAccessOnNonStruct(Type),
+    /// When a struct does not have the accessed field
+    UnknownField(String, String),
+    /// When a struct is declared with the same field more than once
+    DuplicateField(String, String),
     /// When the number of arguments don't match
     ArgumentLength(Option<String>),
+    /// When a named argument does not match a parameter of the called function
+    UnknownParameter(Option<String>, String),
+    /// When a parameter is given an argument both by position and by name, or
+    /// by name twice
+    DuplicateArgument(String),
+    /// When a parameter without a default value is not given an argument
+    MissingArgument(String),
+    /// When a function is defined with a parameter without a default value
+    /// after one with a default value
+    RequiredParameterAfterDefault(String),
+    /// When an argument does not have the type the called function expects
+    ArgumentTypeError(Option<String>, Type, Type),
+    /// When a value does not satisfy the type annotation of the variable or
+    /// parameter it is bound to
+    BindingTypeError(String, ConstraintType, Type),
+    /// When a function returns (or fails to return) a value in violation of its
+    /// return type annotation
+    ReturnTypeError(ReturnConstraint, Option<Type>),
+    /// When a number is divided by zero, with `/` or `%`
+    DivisionByZero,
+    /// When the result of integer arithmetic is too large to compute
+    IntegerOverflow,
+    /// When a bitwise or shift operator is applied to a number that is not an integer
+    NonIntegerOperand(BinOp),
+    /// When a number is shifted by a negative amount with `<<` or `>>`
+    NegativeShiftAmount,
+    /// When a call is nested deeper than the maximum call depth, which is given
+    StackOverflow(usize),
+    /// When `assert` or `assert_eq` fails, with what failed
+    AssertionFailed(String),
     /// When nothing else suits
     GeneralRuntimeError(String),
-    /// When a runtime error occurs inside a function call
-    /// and is getting propagated as a plain RuntimeError
-    InsideFunctionCall(Box<RuntimeErrorWithPosition>),
+    /// When a value is thrown with `throw` and not caught
+    Thrown(Value),
+    /// When a runtime error occurs inside a function call: the error, and the
+    /// calls that were being evaluated, innermost first
+    InsideFunctionCall(Box<RuntimeError>, Vec<StackFrame>),
     BreakOutsideLoop,
     ContinueOutsideLoop,
     ReturnOutsideFunction,
+    /// When a `break` or `continue` names a label no enclosing loop has
+    UnknownLabel(String),
+    /// Not an error: carries a `break`, `continue` or `return` that leaves a loop,
+    /// `if` or block expression out to the statement containing that expression,
+    /// or to the call of the function whose parameter default contains it. It
+    /// is never caught by `catch` or reported.
+    Unwind(Box<StmtResult>),
+}
+
+impl RuntimeError {
+    /// The name of the kind of error, as seen by `catch`
+    pub fn kind(&self) -> &'static str {
+        match *self {
+            RuntimeError::ReferenceError(..) => "ReferenceError",
+            RuntimeError::UndeclaredAssignment(..) => "UndeclaredAssignment",
+            RuntimeError::ImmutableAssignment(..) => "ImmutableAssignment",
+            RuntimeError::BinaryTypeError(..) => "BinaryTypeError",
+            RuntimeError::UnaryTypeError(..) => "UnaryTypeError",
+            RuntimeError::NoneError(..) => "NoneError",
+            RuntimeError::CallToNonFunction(..) => "CallToNonFunction",
+            RuntimeError::SubscriptOnNonSubscriptable(..) => "SubscriptOnNonSubscriptable",
+            RuntimeError::NonIntegralSubscript(..) => "NonIntegralSubscript",
+            RuntimeError::IndexOutOfBounds(..) => "IndexOutOfBounds",
+            RuntimeError::IndexAssignmentOnImmutable(..) => "IndexAssignmentOnImmutable",
+            RuntimeError::NonIterable(..) => "NonIterable",
+            RuntimeError::TryOnNonResult(..) => "TryOnNonResult",
+            RuntimeError::NoMatchingPattern(..) => "NoMatchingPattern",
+            RuntimeError::DuplicateBinding(..) => "DuplicateBinding",
+            RuntimeError::ImportError(..) => "ImportError",
+            RuntimeError::ImportCycle(..) => "ImportCycle",
+            RuntimeError::UnknownModuleMember(..) => "UnknownModuleMember",
+            RuntimeError::ModuleMemberAssignment(..) => "ModuleMemberAssignment",
+            RuntimeError::KeyNotFound(..) => "KeyNotFound",
+            RuntimeError::NonStringMapKey(..) => "NonStringMapKey",
+            RuntimeError::FieldAccessOnNonStruct(..) => "FieldAccessOnNonStruct",
+            RuntimeError::UnknownField(..) => "UnknownField",
+            RuntimeError::DuplicateField(..) => "DuplicateField",
+            RuntimeError::ArgumentLength(..) => "ArgumentLength",
+            RuntimeError::UnknownParameter(..) => "UnknownParameter",
+            RuntimeError::DuplicateArgument(..) => "DuplicateArgument",
+            RuntimeError::MissingArgument(..) => "MissingArgument",
+            RuntimeError::RequiredParameterAfterDefault(..) => "RequiredParameterAfterDefault",
+            RuntimeError::ArgumentTypeError(..) => "ArgumentTypeError",
+            RuntimeError::BindingTypeError(..) => "BindingTypeError",
+            RuntimeError::ReturnTypeError(..) => "ReturnTypeError",
+            RuntimeError::DivisionByZero => "DivisionByZero",
+            RuntimeError::IntegerOverflow => "IntegerOverflow",
+            RuntimeError::NonIntegerOperand(..) => "NonIntegerOperand",
+            RuntimeError::NegativeShiftAmount => "NegativeShiftAmount",
+            RuntimeError::StackOverflow(..) => "StackOverflow",
+            RuntimeError::AssertionFailed(..) => "AssertionFailed",
+            RuntimeError::GeneralRuntimeError(..) => "GeneralRuntimeError",
+            RuntimeError::Thrown(..) => "Thrown",
+            RuntimeError::InsideModule(_, ref error_with_position) => error_with_position.0.kind(),
+            RuntimeError::InsideFunctionCall(ref err, _) => err.kind(),
+            RuntimeError::BreakOutsideLoop => "BreakOutsideLoop",
+            RuntimeError::ContinueOutsideLoop => "ContinueOutsideLoop",
+            RuntimeError::ReturnOutsideFunction => "ReturnOutsideFunction",
+            RuntimeError::UnknownLabel(..) => "UnknownLabel",
+            RuntimeError::Unwind(..) => "Unwind",
+        }
+    }
+
+    /// Whether a `catch` block can handle the error. Misplaced `break`,
+    /// `continue` and `return` statements are mistakes in the program rather
+    /// than failures it can recover from, and `Unwind` is not an error at all.
+    pub fn is_catchable(&self) -> bool {
+        match *self {
+            RuntimeError::InsideFunctionCall(ref err, _) => err.is_catchable(),
+            RuntimeError::InsideModule(_, ref error_with_position) => {
+                error_with_position.0.is_catchable()
+            }
+            RuntimeError::BreakOutsideLoop |
+            RuntimeError::ContinueOutsideLoop |
+            RuntimeError::ReturnOutsideFunction |
+            RuntimeError::UnknownLabel(_) |
+            RuntimeError::Unwind(_) => false,
+            _ => true,
+        }
+    }
 }
 
 pub type RuntimeErrorWithPosition = (RuntimeError, OffsetSpan);
 
+/// A function call that was being evaluated when a runtime error occurred
+#[derive(Debug, PartialEq, Clone)]
+pub struct StackFrame {
+    /// The name the function was defined with, or `<anonymous>`
+    pub fn_name: String,
+    /// Where in the function the error occurred, which is the call of the next
+    /// frame for all but the innermost frame
+    pub pos: OffsetSpan,
+}
+
 #[derive(Debug, PartialEq, Clone)]
 pub enum StmtResult {
     None,
-    Break,
-    Continue,
+    /// The label of the loop being broken out of, and the value it is broken with
+    Break(Option<String>, Option<Value>),
+    Continue(Option<String>),
     Value(Value),
     Return(Option<Value>),
+    /// `return f(args);` for a user function `f`, with the arguments in the order
+    /// of its parameters. The call is made by the caller of the returning
+    /// function, in place of it.
+    TailCall(Value, Vec<Option<Value>>),
 }
 
 impl StmtResult {
@@ -50,9 +212,38 @@ impl StmtResult {
         match *self {
             StmtResult::None |
             StmtResult::Value(_) => false,
-            StmtResult::Break |
-            StmtResult::Continue |
-            StmtResult::Return(_) => true,
+            StmtResult::Break(..) |
+            StmtResult::Continue(_) |
+            StmtResult::Return(_) |
+            StmtResult::TailCall(..) => true,
+        }
+    }
+
+    // What a loop labeled `label` should do after its body finished with this
+    // result: `None` to run the next iteration, or the result the loop ends with.
+    pub fn after_loop_iteration(self, label: &Option<String>) -> Option<StmtResult> {
+        match self {
+            StmtResult::None |
+            StmtResult::Value(_) => None,
+            StmtResult::Break(target, val) => {
+                if target.is_none() || target == *label {
+                    Some(match val {
+                             Some(val) => StmtResult::Value(val),
+                             None => StmtResult::None,
+                         })
+                } else {
+                    Some(StmtResult::Break(target, val))
+                }
+            }
+            StmtResult::Continue(target) => {
+                if target.is_none() || target == *label {
+                    None
+                } else {
+                    Some(StmtResult::Continue(target))
+                }
+            }
+            StmtResult::Return(_) |
+            StmtResult::TailCall(..) => Some(self),
         }
     }
 }
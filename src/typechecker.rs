@@ -1,7 +1,7 @@
-use std::collections::BTreeMap;
+use std::collections::{BTreeMap, BTreeSet};
 use std::fmt;
 use std::rc::Rc;
-use std::cell::RefCell;
+use std::cell::{Cell, RefCell};
 use std::iter::Iterator;
 
 use linear_map::LinearMap;
@@ -10,28 +10,199 @@ use ast::*;
 use ast;
 use runtime::RuntimeError;
 use function::*;
+use module::{self, ModuleLoader};
 
-#[derive(Clone, Debug)]
+#[derive(Clone)]
 pub enum FunctionType {
-    NativeVoid(CallSign),
-    NativeReturning(CallSign),
+    /// A native function with the given parameter types that does not return
+    NativeVoid(CallSign, Vec<Type>),
+    /// A native function with the given parameter types and return type
+    NativeReturning(CallSign, Vec<Type>, Type),
     User {
         call_sign: CallSign,
-        param_names: Vec<String>,
+        params: Vec<FnParam>,
+        ret_constraint: Option<ReturnConstraint>,
         body: Box<ast::StmtNode>,
         env: Rc<RefCell<TypeEnvironment>>,
-        already_checked_param_types: LinearMap<Vec<Type>, ()>,
+        /// Return types inferred so far, keyed by the argument types of the call
+        already_checked_param_types: Rc<RefCell<CheckedCalls>>,
     },
 }
 
+/// The return type inferred for each list of argument types, with the number of
+/// type changes in the environment of the function when it was inferred, or
+/// `None` while the call is being checked
+pub type CheckedCalls = LinearMap<Vec<Type>, Option<(usize, Option<Type>)>>;
+
 impl FunctionType {
     pub fn get_call_sign(&self) -> CallSign {
         match *self {
-            FunctionType::NativeVoid(ref call_sign) |
-            FunctionType::NativeReturning(ref call_sign) |
+            FunctionType::NativeVoid(ref call_sign, _) |
+            FunctionType::NativeReturning(ref call_sign, _, _) |
             FunctionType::User { ref call_sign, .. } => call_sign.clone(),
         }
     }
+
+    /// The names that named arguments can refer to, in the order of the parameters
+    pub fn get_param_names(&self) -> Vec<String> {
+        match *self {
+            FunctionType::NativeReturning(_, _, Type::Struct(_, ref fields)) => {
+                fields.as_ref().clone()
+            }
+            FunctionType::NativeVoid(..) |
+            FunctionType::NativeReturning(..) => Vec::new(),
+            FunctionType::User { ref params, .. } => {
+                params
+                    .iter()
+                    .filter(|param| !param.is_rest)
+                    .map(|param| param.name.clone())
+                    .collect()
+            }
+        }
+    }
+
+    /// The types of the declared parameters, apart from a rest parameter.
+    /// Unannotated parameters of user functions are `Any`.
+    pub fn get_param_types(&self) -> Vec<Type> {
+        match *self {
+            FunctionType::NativeVoid(_, ref param_types) |
+            FunctionType::NativeReturning(_, ref param_types, _) => param_types.clone(),
+            FunctionType::User { ref params, .. } => {
+                params
+                    .iter()
+                    .filter(|param| !param.is_rest)
+                    .map(|param| match param.constraint {
+                             Some(ref constraint) => Type::from(constraint.clone()),
+                             None => Type::Any,
+                         })
+                    .collect()
+            }
+        }
+    }
+
+    /// The type of the returned value, or `None` if the function does not return
+    /// a value. User functions without a return annotation return `Any`.
+    pub fn get_return_type(&self) -> Option<Type> {
+        match *self {
+            FunctionType::NativeVoid(..) => None,
+            FunctionType::NativeReturning(_, _, ref ret_type) => Some(ret_type.clone()),
+            FunctionType::User { ref ret_constraint, .. } => {
+                match *ret_constraint {
+                    None => Some(Type::Any),
+                    Some(ReturnConstraint::Void) => None,
+                    Some(ReturnConstraint::Type(ref constraint)) => {
+                        Some(Type::from(constraint.clone()))
+                    }
+                }
+            }
+        }
+    }
+
+    fn is_compatible_with(&self, other: &FunctionType) -> bool {
+        let (sign1, sign2) = (self.get_call_sign(), other.get_call_sign());
+        if !sign1.overlaps(&sign2) {
+            return false;
+        }
+        let params_compatible = self.get_param_types()
+            .iter()
+            .zip(other.get_param_types().iter())
+            .all(|(t1, t2)| t1.is_compatible_with(t2));
+        // a function returning an unknown value may be used where no value is expected,
+        // but not the other way around
+        let returns_compatible = match (self.get_return_type(), other.get_return_type()) {
+            (None, None) |
+            (None, Some(Type::Any)) => true,
+            (Some(t1), Some(t2)) => t1.is_compatible_with(&t2),
+            _ => false,
+        };
+        params_compatible && returns_compatible
+    }
+}
+
+// User functions are equal if they come from the same definition, so that
+// closures created by separate calls of the same function share cache entries.
+impl PartialEq for FunctionType {
+    fn eq(&self, other: &FunctionType) -> bool {
+        match (self, other) {
+            (&FunctionType::User {
+                  params: ref params1,
+                  ret_constraint: ref ret_constraint1,
+                  body: ref body1,
+                  ..
+              },
+             &FunctionType::User {
+                  params: ref params2,
+                  ret_constraint: ref ret_constraint2,
+                  body: ref body2,
+                  ..
+              }) => params1 == params2 && ret_constraint1 == ret_constraint2 && body1 == body2,
+            (&FunctionType::User { .. }, _) |
+            (_, &FunctionType::User { .. }) => false,
+            _ => {
+                let (sign1, sign2) = (self.get_call_sign(), other.get_call_sign());
+                sign1.min_params == sign2.min_params && sign1.max_params == sign2.max_params &&
+                self.get_param_types() == other.get_param_types() &&
+                self.get_return_type() == other.get_return_type()
+            }
+        }
+    }
+}
+
+// The body and the environment are left out, since the environment usually
+// contains the function itself.
+impl fmt::Debug for FunctionType {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            FunctionType::NativeVoid(ref call_sign, ref param_types) => {
+                f.debug_tuple("NativeVoid")
+                    .field(call_sign)
+                    .field(param_types)
+                    .finish()
+            }
+            FunctionType::NativeReturning(ref call_sign, ref param_types, ref ret_type) => {
+                f.debug_tuple("NativeReturning")
+                    .field(call_sign)
+                    .field(param_types)
+                    .field(ret_type)
+                    .finish()
+            }
+            FunctionType::User {
+                ref call_sign,
+                ref params,
+                ref ret_constraint,
+                ..
+            } => {
+                f.debug_struct("User")
+                    .field("call_sign", call_sign)
+                    .field("params", params)
+                    .field("ret_constraint", ret_constraint)
+                    .finish()
+            }
+        }
+    }
+}
+
+impl fmt::Display for FunctionType {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let param_types = self.get_param_types();
+        write!(f, "Function(")?;
+        for (i, param_type) in param_types.iter().enumerate() {
+            if i != 0 {
+                write!(f, ", ")?;
+            }
+            write!(f, "{}", param_type)?;
+        }
+        if self.get_call_sign().max_params.is_none() {
+            if !param_types.is_empty() {
+                write!(f, ", ")?;
+            }
+            write!(f, "...")?;
+        }
+        match self.get_return_type() {
+            Some(ret_type) => write!(f, ") -> {}", ret_type),
+            None => write!(f, ") -> void"),
+        }
+    }
 }
 
 #[derive(Clone, Debug)]
@@ -41,7 +212,16 @@ pub enum Type {
     Any,
     Function(Box<Option<FunctionType>>),
     Tuple,
+    List,
+    Map,
+    /// An instance of the struct with the given name and fields
+    Struct(String, Rc<Vec<String>>),
     String,
+    /// `Ok(value)` or `Err(error)`
+    Result,
+    /// A module imported from the file at the given path, with the types of its
+    /// top-level bindings
+    Module(String, Rc<BTreeMap<String, Type>>),
 }
 
 impl PartialEq for Type {
@@ -49,25 +229,47 @@ impl PartialEq for Type {
         match (self, other) {
             (&Type::Number, &Type::Number) |
             (&Type::Bool, &Type::Bool) |
-            (&Type::Function(_), &Type::Function(_)) | // TODO
             (&Type::String, &Type::String) |
             (&Type::Tuple, &Type::Tuple) |
+            (&Type::List, &Type::List) |
+            (&Type::Map, &Type::Map) |
+            (&Type::Result, &Type::Result) |
             (&Type::Any, &Type::Any) => true,
+            (&Type::Struct(ref name1, ref fields1), &Type::Struct(ref name2, ref fields2)) => {
+                name1 == name2 && fields1 == fields2
+            }
+            (&Type::Function(ref f1), &Type::Function(ref f2)) => f1 == f2,
+            (&Type::Module(ref path1, _), &Type::Module(ref path2, _)) => path1 == path2,
             _ => false,
         }
     }
 }
 
 impl Type {
+    pub fn satisfies_constraint(&self, constraint: &ConstraintType) -> bool {
+        Type::from(constraint.clone()).is_compatible_with(self)
+    }
+
     fn is_compatible_with(&self, other: &Type) -> bool {
         match (self, other) {
             (&Type::Number, &Type::Number) |
             (&Type::Bool, &Type::Bool) |
-            (&Type::Function(_), &Type::Function(_)) | // TODO
             (&Type::String, &Type::String) |
             (&Type::Tuple, &Type::Tuple) |
+            (&Type::List, &Type::List) |
+            (&Type::Map, &Type::Map) |
+            (&Type::Result, &Type::Result) |
             (&Type::Any, _) |
             (_, &Type::Any) => true,
+            (&Type::Struct(..), &Type::Struct(..)) |
+            (&Type::Module(..), &Type::Module(..)) => self == other,
+            (&Type::Function(ref f1), &Type::Function(ref f2)) => {
+                match (&**f1, &**f2) {
+                    // a function whose signature is not known is compatible with any function
+                    (&None, _) | (_, &None) => true,
+                    (&Some(ref f1), &Some(ref f2)) => f1.is_compatible_with(f2),
+                }
+            }
             _ => false,
         }
     }
@@ -79,22 +281,49 @@ impl From<ast::Literal> for Type {
     fn from(from: ast::Literal) -> Self {
         match from {
             ast::Literal::Integer(_) |
-            ast::Literal::Float(_) => Type::Number,
+            ast::Literal::Float(_) |
+            ast::Literal::BigInt(_) => Type::Number,
             ast::Literal::Bool(_) => Type::Bool,
             ast::Literal::String(_) => Type::String,
         }
     }
 }
 
+impl From<ConstraintType> for Type {
+    fn from(from: ConstraintType) -> Self {
+        match from {
+            ConstraintType::Number => Type::Number,
+            ConstraintType::Bool => Type::Bool,
+            ConstraintType::String => Type::String,
+            ConstraintType::Function => Type::Function(Box::new(None)),
+            ConstraintType::Tuple => Type::Tuple,
+            ConstraintType::List => Type::List,
+            ConstraintType::Map => Type::Map,
+            ConstraintType::Result => Type::Result,
+            ConstraintType::Any => Type::Any,
+        }
+    }
+}
+
 impl fmt::Display for Type {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         match *self {
             Type::Number => write!(f, "Number"),
             Type::Bool => write!(f, "Bool"),
             Type::Any => write!(f, "Any"),
-            Type::Function(_) => write!(f, "Function"),
+            Type::Function(ref possible_func) => {
+                match **possible_func {
+                    Some(ref func_type) => write!(f, "{}", func_type),
+                    None => write!(f, "Function"),
+                }
+            }
             Type::String => write!(f, "String"),
             Type::Tuple => write!(f, "Tuple"),
+            Type::List => write!(f, "List"),
+            Type::Map => write!(f, "Map"),
+            Type::Result => write!(f, "Result"),
+            Type::Struct(ref name, _) => write!(f, "{}", name),
+            Type::Module(..) => write!(f, "Module"),
         }
     }
 }
@@ -102,17 +331,34 @@ impl fmt::Display for Type {
 #[derive(Clone)]
 struct Context {
     pub in_loop: bool,
+    // the labels of the loops being checked, innermost last, and whether the
+    // value of each is used
+    pub loops: Vec<(Option<String>, bool)>,
     pub in_func: bool,
     // Some(None) represents a non-returning function
     pub func_ret_type: Option<Option<Type>>,
+    pub func_ret_constraint: Option<ReturnConstraint>,
 }
 
 impl Context {
     fn root() -> Context {
         Context {
             in_loop: false,
+            loops: Vec::new(),
             in_func: false,
             func_ret_type: None,
+            func_ret_constraint: None,
+        }
+    }
+
+    // The context for the body and parameter defaults of a function
+    fn for_function(ret_constraint: &Option<ReturnConstraint>) -> Context {
+        Context {
+            in_loop: false,
+            loops: Vec::new(),
+            in_func: true,
+            func_ret_type: None,
+            func_ret_constraint: ret_constraint.clone(),
         }
     }
 }
@@ -120,11 +366,24 @@ impl Context {
 #[derive(Debug, PartialEq, Clone)]
 pub enum TypeCheckerIssue {
     RuntimeError(RuntimeError),
-    MultipleTypesFromBranchWarning(String),
+    /// When a variable, or the value of an `if` expression if there is no name,
+    /// gets different types in the branches of an `if`
+    MultipleTypesFromBranchWarning(Option<String>),
     InsideFunctionCall(Box<TypeCheckerIssueWithPosition>),
     FunctionReturnsMultipleTypes,
     PossibleNoneError(Option<String>),
+    /// When a field that the struct was not declared with is accessed
+    UnknownField(String, String),
     UnreachableCodeAfterReturn,
+    /// When a `match` arm can never be reached, because earlier arms match
+    /// everything it would, or its pattern cannot match the matched type
+    UnreachableMatchArm,
+    /// When a `match` over a Bool does not handle both `true` and `false`
+    NonExhaustiveMatch,
+    /// When a `break` without a value leaves a loop whose value is used
+    BreakWithoutValue,
+    /// When an issue is found in an imported file
+    InsideModule(String, Box<TypeCheckerIssueWithPosition>),
 }
 
 pub type TypeCheckerIssueWithPosition = (TypeCheckerIssue, OffsetSpan);
@@ -138,7 +397,12 @@ impl From<RuntimeError> for TypeCheckerIssue {
 #[derive(Clone)]
 pub struct TypeEnvironment {
     pub symbol_table: BTreeMap<String, Type>,
+    constraints: BTreeMap<String, ConstraintType>,
+    immutables: BTreeSet<String>,
     parent: Option<Rc<RefCell<TypeEnvironment>>>,
+    // the number of times a variable got a different type, in this environment
+    // or any other with the same root
+    type_changes: Rc<Cell<usize>>,
 }
 
 impl fmt::Debug for TypeEnvironment {
@@ -150,45 +414,359 @@ impl fmt::Debug for TypeEnvironment {
 impl TypeEnvironment {
     pub fn new_root() -> Rc<RefCell<TypeEnvironment>> {
         let mut env = TypeEnvironment::new();
+        // the handler is called with the request path and returns the response
+        let http_handler_type = FunctionType::NativeReturning(CallSign {
+                                                                  min_params: 1,
+                                                                  max_params: Some(1),
+                                                              },
+                                                              vec![Type::String],
+                                                              Type::Any);
         let builtin_functions = &[
             ("println",
              FunctionType::NativeVoid(CallSign {
-                                          num_params: 0,
-                                          variadic: true,
-                                      })),
+                                          min_params: 0,
+                                          max_params: None,
+                                      },
+                                      vec![])),
             ("assert",
              FunctionType::NativeVoid(CallSign {
-                                          num_params: 1,
-                                          variadic: false,
-                                      })),
+                                          min_params: 1,
+                                          max_params: Some(1),
+                                      },
+                                      vec![Type::Any])),
             ("assert_eq",
              FunctionType::NativeVoid(CallSign {
-                                          num_params: 2,
-                                          variadic: false,
-                                      })),
+                                          min_params: 2,
+                                          max_params: Some(2),
+                                      },
+                                      vec![Type::Any, Type::Any])),
             ("len",
              FunctionType::NativeReturning(CallSign {
-                                               num_params: 1,
-                                               variadic: false,
-                                           })),
+                                               min_params: 1,
+                                               max_params: Some(1),
+                                           },
+                                           vec![Type::Any],
+                                           Type::Number)),
             ("run_http_server",
              FunctionType::NativeVoid(CallSign {
-                                          num_params: 1,
-                                          variadic: false,
-                                      })),
+                                          min_params: 1,
+                                          max_params: Some(1),
+                                      },
+                                      vec![Type::Function(Box::new(Some(http_handler_type)))])),
+            ("push",
+             FunctionType::NativeVoid(CallSign {
+                                          min_params: 2,
+                                          max_params: Some(2),
+                                      },
+                                      vec![Type::List, Type::Any])),
+            ("pop",
+             FunctionType::NativeReturning(CallSign {
+                                               min_params: 1,
+                                               max_params: Some(1),
+                                           },
+                                           vec![Type::List],
+                                           Type::Any)),
+            ("insert",
+             FunctionType::NativeVoid(CallSign {
+                                          min_params: 3,
+                                          max_params: Some(3),
+                                      },
+                                      vec![Type::List, Type::Number, Type::Any])),
+            ("remove",
+             FunctionType::NativeReturning(CallSign {
+                                               min_params: 2,
+                                               max_params: Some(2),
+                                           },
+                                           vec![Type::List, Type::Number],
+                                           Type::Any)),
+            ("range",
+             FunctionType::NativeReturning(CallSign {
+                                               min_params: 2,
+                                               max_params: Some(3),
+                                           },
+                                           vec![Type::Number, Type::Number, Type::Number],
+                                           Type::Tuple)),
+            ("split",
+             FunctionType::NativeReturning(CallSign {
+                                               min_params: 2,
+                                               max_params: Some(2),
+                                           },
+                                           vec![Type::String, Type::String],
+                                           Type::List)),
+            ("join",
+             FunctionType::NativeReturning(CallSign {
+                                               min_params: 2,
+                                               max_params: Some(2),
+                                           },
+                                           vec![Type::Any, Type::String],
+                                           Type::String)),
+            ("trim",
+             FunctionType::NativeReturning(CallSign {
+                                               min_params: 1,
+                                               max_params: Some(1),
+                                           },
+                                           vec![Type::String],
+                                           Type::String)),
+            ("replace",
+             FunctionType::NativeReturning(CallSign {
+                                               min_params: 3,
+                                               max_params: Some(3),
+                                           },
+                                           vec![Type::String, Type::String, Type::String],
+                                           Type::String)),
+            ("find",
+             FunctionType::NativeReturning(CallSign {
+                                               min_params: 2,
+                                               max_params: Some(2),
+                                           },
+                                           vec![Type::String, Type::String],
+                                           Type::Number)),
+            ("starts_with",
+             FunctionType::NativeReturning(CallSign {
+                                               min_params: 2,
+                                               max_params: Some(2),
+                                           },
+                                           vec![Type::String, Type::String],
+                                           Type::Bool)),
+            ("ends_with",
+             FunctionType::NativeReturning(CallSign {
+                                               min_params: 2,
+                                               max_params: Some(2),
+                                           },
+                                           vec![Type::String, Type::String],
+                                           Type::Bool)),
+            ("to_upper",
+             FunctionType::NativeReturning(CallSign {
+                                               min_params: 1,
+                                               max_params: Some(1),
+                                           },
+                                           vec![Type::String],
+                                           Type::String)),
+            ("to_lower",
+             FunctionType::NativeReturning(CallSign {
+                                               min_params: 1,
+                                               max_params: Some(1),
+                                           },
+                                           vec![Type::String],
+                                           Type::String)),
+            ("substring",
+             FunctionType::NativeReturning(CallSign {
+                                               min_params: 3,
+                                               max_params: Some(3),
+                                           },
+                                           vec![Type::String, Type::Number, Type::Number],
+                                           Type::String)),
+            ("parse_int",
+             FunctionType::NativeReturning(CallSign {
+                                               min_params: 1,
+                                               max_params: Some(1),
+                                           },
+                                           vec![Type::String],
+                                           Type::Number)),
+            ("parse_float",
+             FunctionType::NativeReturning(CallSign {
+                                               min_params: 1,
+                                               max_params: Some(1),
+                                           },
+                                           vec![Type::String],
+                                           Type::Number)),
+            ("abs",
+             FunctionType::NativeReturning(CallSign {
+                                               min_params: 1,
+                                               max_params: Some(1),
+                                           },
+                                           vec![Type::Number],
+                                           Type::Number)),
+            ("floor",
+             FunctionType::NativeReturning(CallSign {
+                                               min_params: 1,
+                                               max_params: Some(1),
+                                           },
+                                           vec![Type::Number],
+                                           Type::Number)),
+            ("ceil",
+             FunctionType::NativeReturning(CallSign {
+                                               min_params: 1,
+                                               max_params: Some(1),
+                                           },
+                                           vec![Type::Number],
+                                           Type::Number)),
+            ("round",
+             FunctionType::NativeReturning(CallSign {
+                                               min_params: 1,
+                                               max_params: Some(1),
+                                           },
+                                           vec![Type::Number],
+                                           Type::Number)),
+            ("sqrt",
+             FunctionType::NativeReturning(CallSign {
+                                               min_params: 1,
+                                               max_params: Some(1),
+                                           },
+                                           vec![Type::Number],
+                                           Type::Number)),
+            ("pow",
+             FunctionType::NativeReturning(CallSign {
+                                               min_params: 2,
+                                               max_params: Some(2),
+                                           },
+                                           vec![Type::Number, Type::Number],
+                                           Type::Number)),
+            ("min",
+             FunctionType::NativeReturning(CallSign {
+                                               min_params: 2,
+                                               max_params: None,
+                                           },
+                                           vec![Type::Number, Type::Number],
+                                           Type::Number)),
+            ("max",
+             FunctionType::NativeReturning(CallSign {
+                                               min_params: 2,
+                                               max_params: None,
+                                           },
+                                           vec![Type::Number, Type::Number],
+                                           Type::Number)),
+            ("sin",
+             FunctionType::NativeReturning(CallSign {
+                                               min_params: 1,
+                                               max_params: Some(1),
+                                           },
+                                           vec![Type::Number],
+                                           Type::Number)),
+            ("cos",
+             FunctionType::NativeReturning(CallSign {
+                                               min_params: 1,
+                                               max_params: Some(1),
+                                           },
+                                           vec![Type::Number],
+                                           Type::Number)),
+            ("tan",
+             FunctionType::NativeReturning(CallSign {
+                                               min_params: 1,
+                                               max_params: Some(1),
+                                           },
+                                           vec![Type::Number],
+                                           Type::Number)),
+            ("asin",
+             FunctionType::NativeReturning(CallSign {
+                                               min_params: 1,
+                                               max_params: Some(1),
+                                           },
+                                           vec![Type::Number],
+                                           Type::Number)),
+            ("acos",
+             FunctionType::NativeReturning(CallSign {
+                                               min_params: 1,
+                                               max_params: Some(1),
+                                           },
+                                           vec![Type::Number],
+                                           Type::Number)),
+            ("atan",
+             FunctionType::NativeReturning(CallSign {
+                                               min_params: 1,
+                                               max_params: Some(1),
+                                           },
+                                           vec![Type::Number],
+                                           Type::Number)),
+            ("atan2",
+             FunctionType::NativeReturning(CallSign {
+                                               min_params: 2,
+                                               max_params: Some(2),
+                                           },
+                                           vec![Type::Number, Type::Number],
+                                           Type::Number)),
+            ("int",
+             FunctionType::NativeReturning(CallSign {
+                                               min_params: 1,
+                                               max_params: Some(1),
+                                           },
+                                           vec![Type::Any],
+                                           Type::Number)),
+            ("float",
+             FunctionType::NativeReturning(CallSign {
+                                               min_params: 1,
+                                               max_params: Some(1),
+                                           },
+                                           vec![Type::Any],
+                                           Type::Number)),
+            ("is_integer",
+             FunctionType::NativeReturning(CallSign {
+                                               min_params: 1,
+                                               max_params: Some(1),
+                                           },
+                                           vec![Type::Number],
+                                           Type::Bool)),
+            ("Ok",
+             FunctionType::NativeReturning(CallSign {
+                                               min_params: 1,
+                                               max_params: Some(1),
+                                           },
+                                           vec![Type::Any],
+                                           Type::Result)),
+            ("Err",
+             FunctionType::NativeReturning(CallSign {
+                                               min_params: 1,
+                                               max_params: Some(1),
+                                           },
+                                           vec![Type::Any],
+                                           Type::Result)),
+            ("is_ok",
+             FunctionType::NativeReturning(CallSign {
+                                               min_params: 1,
+                                               max_params: Some(1),
+                                           },
+                                           vec![Type::Result],
+                                           Type::Bool)),
+            ("is_err",
+             FunctionType::NativeReturning(CallSign {
+                                               min_params: 1,
+                                               max_params: Some(1),
+                                           },
+                                           vec![Type::Result],
+                                           Type::Bool)),
+            ("unwrap",
+             FunctionType::NativeReturning(CallSign {
+                                               min_params: 1,
+                                               max_params: Some(1),
+                                           },
+                                           vec![Type::Result],
+                                           Type::Any)),
+            ("unwrap_err",
+             FunctionType::NativeReturning(CallSign {
+                                               min_params: 1,
+                                               max_params: Some(1),
+                                           },
+                                           vec![Type::Result],
+                                           Type::Any)),
         ];
         for item in builtin_functions.iter() {
             let (name, ref func) = *item;
             env.declare(&name.to_string(),
                         &Type::Function(Box::new(Some(func.clone()))));
         }
+        // errors caught by `catch` are instances of this struct
+        let error_fields = vec!["kind".to_owned(), "message".to_owned()];
+        let error_constructor = FunctionType::NativeReturning(CallSign {
+                                                                  min_params: 2,
+                                                                  max_params: Some(2),
+                                                              },
+                                                              vec![Type::String, Type::String],
+                                                              Type::Struct("Error".to_owned(),
+                                                                           Rc::new(error_fields)));
+        env.declare("Error", &Type::Function(Box::new(Some(error_constructor))));
+        for name in &["PI", "E"] {
+            env.declare_binding(name, &Type::Number, BindingType::Immutable, None);
+        }
         Rc::new(RefCell::new(env))
     }
 
     pub fn new() -> TypeEnvironment {
         TypeEnvironment {
             symbol_table: BTreeMap::new(),
+            constraints: BTreeMap::new(),
+            immutables: BTreeSet::new(),
             parent: None,
+            type_changes: Rc::new(Cell::new(0)),
         }
     }
 
@@ -198,20 +776,42 @@ impl TypeEnvironment {
     }
 
     pub fn create_child(parent: Rc<RefCell<TypeEnvironment>>) -> Rc<RefCell<TypeEnvironment>> {
+        let type_changes = parent.borrow().type_changes.clone();
         let env = TypeEnvironment {
             parent: Some(parent),
             symbol_table: BTreeMap::default(),
+            constraints: BTreeMap::default(),
+            immutables: BTreeSet::default(),
+            type_changes: type_changes,
         };
         Rc::new(RefCell::new(env))
     }
 
     pub fn declare(&mut self, id: &str, typ: &Type) {
-        self.symbol_table.insert(id.to_owned(), typ.clone());
+        self.declare_binding(id, typ, BindingType::Mutable, None);
+    }
+
+    pub fn declare_binding(&mut self,
+                           id: &str,
+                           typ: &Type,
+                           binding_type: BindingType,
+                           constraint: Option<ConstraintType>) {
+        let old_type = self.symbol_table.insert(id.to_owned(), typ.clone());
+        self.count_type_change(old_type, typ);
+        match constraint {
+            Some(constraint) => self.constraints.insert(id.to_owned(), constraint),
+            None => self.constraints.remove(id),
+        };
+        match binding_type {
+            BindingType::Mutable => self.immutables.remove(id),
+            BindingType::Immutable => self.immutables.insert(id.to_owned()),
+        };
     }
 
     pub fn set(&mut self, identifier: &str, typ: Type) -> bool {
         if self.symbol_table.contains_key(identifier) {
-            self.symbol_table.insert(identifier.to_owned(), typ);
+            let old_type = self.symbol_table.insert(identifier.to_owned(), typ.clone());
+            self.count_type_change(old_type, &typ);
             true
         } else {
             match self.parent {
@@ -221,6 +821,18 @@ impl TypeEnvironment {
         }
     }
 
+    fn count_type_change(&self, old_type: Option<Type>, new_type: &Type) {
+        if old_type.is_some_and(|old_type| old_type != *new_type) {
+            self.type_changes.set(self.type_changes.get() + 1);
+        }
+    }
+
+    /// The number of times a variable got a different type, in this
+    /// environment or any other with the same root
+    pub fn get_type_changes(&self) -> usize {
+        self.type_changes.get()
+    }
+
     pub fn get_type(&self, identifier: &str) -> Option<Type> {
         if let Some(typ) = self.symbol_table.get(identifier) {
             return Some(typ.clone());
@@ -232,6 +844,28 @@ impl TypeEnvironment {
         }
     }
 
+    pub fn get_constraint(&self, identifier: &str) -> Option<ConstraintType> {
+        if self.symbol_table.contains_key(identifier) {
+            self.constraints.get(identifier).cloned()
+        } else {
+            match self.parent {
+                Some(ref parent) => parent.borrow().get_constraint(identifier),
+                None => None,
+            }
+        }
+    }
+
+    pub fn is_immutable(&self, identifier: &str) -> bool {
+        if self.symbol_table.contains_key(identifier) {
+            self.immutables.contains(identifier)
+        } else {
+            match self.parent {
+                Some(ref parent) => parent.borrow().is_immutable(identifier),
+                None => false,
+            }
+        }
+    }
+
     pub fn get_all_pairs(&self) -> Vec<(String, Type)> {
         let mut pairs = Vec::new();
         for (key, value) in &self.symbol_table {
@@ -254,14 +888,20 @@ pub struct TypeChecker {
     context: Context,
     issues: Vec<TypeCheckerIssueWithPosition>,
     env: Rc<RefCell<TypeEnvironment>>,
+    // the file being checked, which imports are relative to
+    file: Option<String>,
+    modules: Rc<RefCell<ModuleLoader<Type>>>,
 }
 
 impl TypeChecker {
-    pub fn new() -> TypeChecker {
+    pub fn for_file(file_name: &str) -> TypeChecker {
+        let file = Some(file_name.to_owned());
         TypeChecker {
             context: Context::root(),
             issues: Vec::new(),
             env: TypeEnvironment::new_root(),
+            modules: Rc::new(RefCell::new(ModuleLoader::new(&file))),
+            file: file,
         }
     }
 
@@ -285,6 +925,10 @@ impl TypeChecker {
                 self.check_statement_variable_declaration(variable, expr);
                 StmtEffect::None
             }
+            Stmt::StructDecl(ref struct_decl) => {
+                self.check_statement_struct_declaration(struct_decl, s);
+                StmtEffect::None
+            }
             Stmt::Assign(ref lhs_expr, ref expr) => {
                 self.check_statement_assignment(lhs_expr, expr);
                 StmtEffect::None
@@ -293,58 +937,178 @@ impl TypeChecker {
                 self.check_statement_assignment_with_op(lhs_expr, op, expr, s);
                 StmtEffect::None
             }
-            Stmt::Block(ref statements) => {
-                let current_env = self.env.clone();
-                self.env = TypeEnvironment::create_child(current_env.clone());
-                let mut last_effect = StmtEffect::None;
-                for stmt in statements.iter() {
-                    if let StmtEffect::Return = last_effect {
-                        self.issues
-                            .push((TypeCheckerIssue::UnreachableCodeAfterReturn,
-                                   (stmt.pos.0, statements.last().unwrap().pos.1)));
-                        // unreachable code, stop checking
-                        break;
-                    }
-                    last_effect = self.check_statement(stmt);
-                }
-                self.env = current_env;
-                last_effect
-            }
+            Stmt::Block(ref statements, _) => self.check_block(statements).0,
             Stmt::Expr(ref expr) => {
                 self.check_expr(expr);
                 StmtEffect::None
             }
-            Stmt::IfThen(ref if_then_stmt) => self.check_statement_if_then_else(s, if_then_stmt),
-            Stmt::Loop(ref block) => {
-                let old_in_loop_value = self.context.in_loop;
-                self.context.in_loop = true;
-                let effect = self.check_statement(block);
-                self.context.in_loop = old_in_loop_value;
-                effect
+            Stmt::IfThen(ref if_then_stmt) => {
+                self.check_if_then_else(s.pos, if_then_stmt).0
+            }
+            Stmt::Loop(ref label, ref block) => self.check_loop_body(label, block, false),
+            Stmt::While(ref label, ref cond, ref block) => {
+                self.check_expr_as_value(cond);
+                self.check_loop_body(label, block, false);
+                // the body might never run
+                StmtEffect::None
+            }
+            Stmt::ForIn(ref label, ref var_name, ref iterable_expr, ref block) => {
+                self.check_statement_for_in(label, var_name, iterable_expr, block);
+                StmtEffect::None
             }
-            Stmt::Break => {
+            Stmt::Break(ref label, ref possible_expr) => {
                 if self.context.in_loop != true {
                     self.issues
                         .push((RuntimeError::BreakOutsideLoop.into(), s.pos));
                 }
+                self.check_loop_label(label, s);
+                match *possible_expr {
+                    Some(ref expr) => {
+                        self.check_expr_as_value(expr);
+                    }
+                    None => {
+                        if self.is_loop_value_used(label) {
+                            self.issues
+                                .push((TypeCheckerIssue::BreakWithoutValue, s.pos));
+                        }
+                    }
+                }
                 StmtEffect::None
             }
-            Stmt::Continue => {
+            Stmt::Continue(ref label) => {
                 if self.context.in_loop != true {
                     self.issues
                         .push((RuntimeError::ContinueOutsideLoop.into(), s.pos));
                 }
+                self.check_loop_label(label, s);
                 StmtEffect::None
             }
             Stmt::Empty => StmtEffect::None,
             Stmt::Return(ref possible_expr) => self.check_statement_return(possible_expr, s),
+            Stmt::Throw(ref expr) => {
+                self.check_expr_as_value(expr);
+                StmtEffect::None
+            }
+            Stmt::TryCatch(ref try_block, ref error_name, ref catch_block) => {
+                let try_effect = self.check_statement(try_block);
+                let current_env = self.env.clone();
+                self.env = TypeEnvironment::create_child(current_env.clone());
+                // anything can be thrown
+                self.env.borrow_mut().declare(error_name, &Type::Any);
+                let catch_effect = self.check_statement(catch_block);
+                self.env = current_env;
+                if let (StmtEffect::Return, StmtEffect::Return) = (try_effect, catch_effect) {
+                    StmtEffect::Return
+                } else {
+                    StmtEffect::None
+                }
+            }
+            Stmt::Import(ref path, ref name) => {
+                self.check_statement_import(path, name, s);
+                StmtEffect::None
+            }
         }
     }
 
+    /// Checks a statement, also giving the type of the value it evaluates to, which
+    /// is the value of an `if` or block used as an expression
+    fn check_statement_with_value(&mut self, s: &StmtNode) -> (StmtEffect, Option<Type>) {
+        match s.data {
+            Stmt::Expr(ref expr) => (StmtEffect::None, self.check_expr(expr)),
+            Stmt::Block(ref statements, _) => self.check_block(statements),
+            Stmt::IfThen(ref if_then_stmt) => {
+                let (effect, then_type, else_type) = self.check_if_then_else(s.pos, if_then_stmt);
+                match (then_type, else_type) {
+                    (Some(then_type), Some(else_type)) => {
+                        if then_type.is_compatible_with(&else_type) {
+                            (effect, Some(then_type))
+                        } else {
+                            (effect, Some(Type::Any))
+                        }
+                    }
+                    _ => (effect, None),
+                }
+            }
+            // these leave the block, so the missing value is never used
+            Stmt::Break(..) |
+            Stmt::Continue(_) |
+            Stmt::Return(_) |
+            Stmt::Throw(_) => (self.check_statement(s), Some(Type::Any)),
+            _ => (self.check_statement(s), None),
+        }
+    }
+
+    fn check_block(&mut self, statements: &[StmtNode]) -> (StmtEffect, Option<Type>) {
+        let current_env = self.env.clone();
+        self.env = TypeEnvironment::create_child(current_env.clone());
+        let mut last_effect = StmtEffect::None;
+        let mut last_type = None;
+        for stmt in statements.iter() {
+            if let StmtEffect::Return = last_effect {
+                self.issues
+                    .push((TypeCheckerIssue::UnreachableCodeAfterReturn,
+                           (stmt.pos.0, statements.last().unwrap().pos.1)));
+                // unreachable code, stop checking
+                break;
+            }
+            let (effect, typ) = self.check_statement_with_value(stmt);
+            last_effect = effect;
+            last_type = typ;
+        }
+        self.env = current_env;
+        (last_effect, last_type)
+    }
+
+    fn check_statement_import(&mut self, import_path: &str, name: &str, import_stmt: &StmtNode) {
+        let typ = match self.load_module_type(import_path, import_stmt.pos) {
+            Ok(typ) => typ,
+            Err(e) => {
+                self.issues.push((e.into(), import_stmt.pos));
+                Type::Any
+            }
+        };
+        self.env
+            .borrow_mut()
+            .declare_binding(name, &typ, BindingType::Immutable, None);
+    }
+
+    fn load_module_type(&mut self,
+                        import_path: &str,
+                        import_pos: OffsetSpan)
+                        -> Result<Type, RuntimeError> {
+        let module_path = module::resolve_import(&self.file, import_path)?;
+        if let Some(typ) = self.modules.borrow().get(&module_path) {
+            return Ok(typ);
+        }
+        let ast = module::parse_module(&module_path)?;
+        self.modules.borrow_mut().start_loading(&module_path)?;
+        let module_env = TypeEnvironment::create_child(TypeEnvironment::new_root());
+        let mut checker = TypeChecker {
+            context: Context::root(),
+            issues: Vec::new(),
+            env: module_env.clone(),
+            file: Some(module_path.display_path.clone()),
+            modules: self.modules.clone(),
+        };
+        checker.check_program(&ast);
+        for issue in checker.get_issues() {
+            self.issues
+                .push((TypeCheckerIssue::InsideModule(module_path.display_path.clone(),
+                                                      Box::new(issue)),
+                       import_pos));
+        }
+        let typ = Type::Module(module_path.display_path.clone(),
+                               Rc::new(module_env.borrow().symbol_table.clone()));
+        self.modules
+            .borrow_mut()
+            .finish_loading(&module_path, Some(typ.clone()));
+        Ok(typ)
+    }
+
     fn check_expr(&mut self, expr: &ExprNode) -> Option<Type> {
         match expr.data {
             Expr::Literal(ref x) => Some(Type::from(x.data.clone())),
-            Expr::Identifier(ref id) => {
+            Expr::Identifier(ref id, _) => {
                 match self.env.borrow().get_type(id) {
                     Some(t) => Some(t),
                     None => {
@@ -355,6 +1119,8 @@ impl TypeChecker {
                 }
             }
             Expr::Tuple(ref elems) => Some(self.check_expr_tuple(elems)),
+            Expr::List(ref elems) => Some(self.check_expr_list(elems)),
+            Expr::Map(ref entries) => Some(self.check_expr_map(entries)),
             Expr::Unary(ref op, ref expr) => Some(self.check_expr_unary_op(op, expr)),
             Expr::UnaryLogical(ref op, ref expr) => {
                 Some(self.check_expr_unary_logical_op(op, expr))
@@ -365,11 +1131,27 @@ impl TypeChecker {
             Expr::BinaryLogical(ref expr1, ref op, ref expr2) => {
                 Some(self.check_expr_binary_logical_expr(expr1, op, expr2))
             }
-            Expr::FnCall(ref f_expr, ref args) => self.check_expr_function_call(expr, f_expr, args),
-            Expr::FnDef(ref fn_def_expr) => Some(self.check_expr_function_definition(fn_def_expr)),
+            Expr::FnCall(ref f_expr, ref args, ref named_args) => {
+                self.check_expr_function_call(expr, f_expr, args, named_args)
+            }
+            Expr::FnDef(ref fn_def_expr) => {
+                Some(self.check_expr_function_definition(fn_def_expr, expr))
+            }
             Expr::MemberByIdx(ref expr, ref index_expr) => {
                 Some(self.check_expr_member_access_by_index(expr, index_expr))
             }
+            Expr::Loop(ref label, ref block) => {
+                self.check_loop_body(label, block, true);
+                Some(Type::Any)
+            }
+            Expr::IfThen(ref if_then_stmt) => self.check_expr_if_then_else(expr, if_then_stmt),
+            Expr::Block(ref statements, _) => self.check_block(statements).1,
+            Expr::Match(ref scrutinee, ref arms) => self.check_expr_match(expr, scrutinee, arms),
+            Expr::Try(ref result_expr) => Some(self.check_expr_try(expr, result_expr)),
+            Expr::FieldAccess(ref object_expr, ref field) => {
+                let object_type = self.check_expr_as_value(object_expr);
+                Some(self.check_field_access(object_type, field, expr.pos))
+            }
         }
     }
 
@@ -377,33 +1159,259 @@ impl TypeChecker {
     fn check_statement_variable_declaration(&mut self, variable: &Variable, expr: &ExprNode) {
         let checked_type = self.check_expr_as_value(expr);
         match *variable {
-            Variable::Identifier(_, ref id) => {
-                self.env.borrow_mut().declare(id, &checked_type);
+            Variable::Identifier(ref binding_type, ref id, ref constraint, _) => {
+                let typ = self.apply_constraint(id, checked_type, constraint, expr.pos);
+                self.env
+                    .borrow_mut()
+                    .declare_binding(id, &typ, binding_type.clone(), constraint.clone());
+            }
+            Variable::Pattern(ref binding_type, ref pattern) => {
+                if !pattern_can_match(&pattern.data, &checked_type) {
+                    self.issues
+                        .push((RuntimeError::NoMatchingPattern(checked_type.clone()).into(),
+                               expr.pos));
+                }
+                self.check_duplicate_bindings(pattern);
+                self.declare_pattern_bindings(&pattern.data, checked_type, binding_type);
             }
         };
     }
 
+    fn check_duplicate_bindings(&mut self, pattern: &PatternNode) {
+        if let Some(name) = pattern.data.find_duplicate_binding() {
+            self.issues
+                .push((RuntimeError::DuplicateBinding(name.clone()).into(), pattern.pos));
+        }
+    }
+
+    fn declare_pattern_bindings(&mut self,
+                                pattern: &Pattern,
+                                typ: Type,
+                                binding_type: &BindingType) {
+        match *pattern {
+            Pattern::Wildcard |
+            Pattern::Literal(_) => {}
+            Pattern::Binding(ref name, _) => {
+                self.env
+                    .borrow_mut()
+                    .declare_binding(name, &typ, binding_type.clone(), None);
+            }
+            Pattern::Tuple(ref patterns) => {
+                // the types of tuple elements are not tracked
+                for pattern in patterns {
+                    self.declare_pattern_bindings(&pattern.data, Type::Any, binding_type);
+                }
+            }
+        }
+    }
+
+    fn check_expr_match(&mut self,
+                        match_expr: &ExprNode,
+                        scrutinee: &ExprNode,
+                        arms: &[MatchArmNode])
+                        -> Option<Type> {
+        let scrutinee_type = self.check_expr_as_value(scrutinee);
+        // patterns of the arms without guards seen so far
+        let mut covered: Vec<&Pattern> = Vec::new();
+        let mut arm_types = Vec::new();
+        for arm in arms {
+            let pattern = &arm.data.pattern.data;
+            let is_covered = covered
+                .iter()
+                .any(|prev| is_catch_all(prev) || *prev == pattern);
+            if is_covered || covers_bool(&covered) ||
+               !pattern_can_match(pattern, &scrutinee_type) {
+                self.issues
+                    .push((TypeCheckerIssue::UnreachableMatchArm, arm.pos));
+            }
+            self.check_duplicate_bindings(&arm.data.pattern);
+            let current_env = self.env.clone();
+            self.env = TypeEnvironment::create_child(current_env.clone());
+            self.declare_pattern_bindings(pattern, scrutinee_type.clone(), &BindingType::Mutable);
+            if let Some(ref guard) = arm.data.guard {
+                self.check_expr_as_value(guard);
+            }
+            arm_types.push(self.check_expr(&arm.data.body));
+            self.env = current_env;
+            if arm.data.guard.is_none() {
+                covered.push(pattern);
+            }
+        }
+        let is_exhaustive = covers_bool(&covered) ||
+                            covered.iter().any(|pattern| is_catch_all(pattern));
+        if scrutinee_type == Type::Bool && !is_exhaustive {
+            self.issues
+                .push((TypeCheckerIssue::NonExhaustiveMatch, match_expr.pos));
+        }
+        match arm_types.split_first() {
+            Some((first, rest)) if rest.iter().all(|typ| typ == first) => first.clone(),
+            _ => Some(Type::Any),
+        }
+    }
+
+    fn check_statement_struct_declaration(&mut self,
+                                          struct_decl: &StructDecl,
+                                          struct_decl_stmt: &StmtNode) {
+        let &StructDecl {
+                 ref name,
+                 ref fields,
+                 ..
+             } = struct_decl;
+        if let Some(field) = struct_decl.find_duplicate_field() {
+            self.issues
+                .push((RuntimeError::DuplicateField(name.clone(), field.clone()).into(),
+                       struct_decl_stmt.pos));
+        }
+        // constructors behave like a native function taking one argument per field
+        let constructor = FunctionType::NativeReturning(CallSign {
+                                                            min_params: fields.len(),
+                                                            max_params: Some(fields.len()),
+                                                        },
+                                                        vec![Type::Any; fields.len()],
+                                                        Type::Struct(name.clone(),
+                                                                     Rc::new(fields.clone())));
+        self.env
+            .borrow_mut()
+            .declare(name, &Type::Function(Box::new(Some(constructor))));
+    }
+
     fn check_statement_assignment(&mut self, lhs_expr: &LhsExprNode, expr: &ExprNode) {
         let checked_type = self.check_expr_as_value(expr);
         match lhs_expr.data {
-            LhsExpr::Identifier(ref id) => {
-                if !self.env.borrow_mut().set(id, checked_type) {
+            LhsExpr::Identifier(ref id, _) => {
+                if self.env.borrow().is_immutable(id) {
+                    self.issues
+                        .push((RuntimeError::ImmutableAssignment(id.clone()).into(), lhs_expr.pos));
+                }
+                let constraint = self.env.borrow().get_constraint(id);
+                let typ = self.apply_constraint(id, checked_type, &constraint, expr.pos);
+                if !self.env.borrow_mut().set(id, typ) {
                     self.issues
                         .push((RuntimeError::UndeclaredAssignment(id.clone()).into(),
                                lhs_expr.pos));
                 }
             }
+            LhsExpr::Field(..) |
+            LhsExpr::Index(..) => {
+                self.check_lhs_expr(lhs_expr);
+                self.check_lhs_rebinding(lhs_expr);
+            }
         };
     }
 
+    /// Checks a place being updated by an assignment, and returns the type of its
+    /// current value. Fields and elements are assumed to be able to hold any type.
+    fn check_lhs_expr(&mut self, lhs_expr: &LhsExprNode) -> Type {
+        let (lhs_type, possible_issue) = self.check_lhs_path(lhs_expr);
+        if let Some(issue) = possible_issue {
+            self.issues.push(issue);
+        }
+        lhs_type
+    }
+
+    // The type of a part of an assignment target, with the issue that assigning
+    // to it would raise. The parts that are only read on the way to the target,
+    // like the list in `tuple[0][1] = x`, are not assigned to.
+    fn check_lhs_path(&mut self,
+                      lhs_expr: &LhsExprNode)
+                      -> (Type, Option<TypeCheckerIssueWithPosition>) {
+        match lhs_expr.data {
+            LhsExpr::Identifier(ref id, _) => {
+                let possible_type = self.env.borrow().get_type(id);
+                match possible_type {
+                    Some(t) => (t, None),
+                    None => {
+                        self.issues
+                            .push((RuntimeError::ReferenceError(id.to_owned()).into(),
+                                   lhs_expr.pos));
+                        (Type::Any, None)
+                    }
+                }
+            }
+            // setting a field rebuilds the object, which is assigned back
+            LhsExpr::Field(ref object_lhs_expr, ref field) => {
+                let (object_type, possible_issue) = self.check_lhs_path(object_lhs_expr);
+                (self.check_field_access(object_type, field, lhs_expr.pos), possible_issue)
+            }
+            LhsExpr::Index(ref object_lhs_expr, ref index_expr) => {
+                let (object_type, possible_issue) = self.check_lhs_path(object_lhs_expr);
+                let index_type = self.check_expr_as_value(index_expr);
+                let possible_issue = match object_type {
+                    Type::Tuple | Type::String => {
+                        let issue = RuntimeError::IndexAssignmentOnImmutable(object_type).into();
+                        return (Type::Any, Some((issue, object_lhs_expr.pos)));
+                    }
+                    // lists are changed in place, and anything else could be a list
+                    Type::List | Type::Any => None,
+                    _ => possible_issue,
+                };
+                let element_type = self.check_subscript(object_type,
+                                                        index_type,
+                                                        object_lhs_expr.pos,
+                                                        index_expr.pos);
+                (element_type, possible_issue)
+            }
+        }
+    }
+
+    /// Updating a field or a map entry rebuilds the value and assigns it back to
+    /// the variable, which is not allowed for `let` bindings. Lists are shared,
+    /// so updating their elements leaves the variable alone.
+    fn check_lhs_rebinding(&mut self, lhs_expr: &LhsExprNode) {
+        match lhs_expr.data {
+            LhsExpr::Identifier(ref id, _) => {
+                if self.env.borrow().is_immutable(id) {
+                    self.issues
+                        .push((RuntimeError::ImmutableAssignment(id.clone()).into(), lhs_expr.pos));
+                }
+            }
+            LhsExpr::Field(ref object_lhs_expr, ref field) => {
+                let object_type = match object_lhs_expr.data {
+                    LhsExpr::Identifier(ref id, _) => self.env.borrow().get_type(id),
+                    _ => None,
+                };
+                match object_type {
+                    // the bindings of a module can only be changed by the module itself
+                    Some(Type::Module(ref path, _)) => {
+                        let issue = RuntimeError::ModuleMemberAssignment(path.clone(),
+                                                                         field.clone());
+                        self.issues.push((issue.into(), lhs_expr.pos));
+                    }
+                    _ => self.check_lhs_rebinding(object_lhs_expr),
+                }
+            }
+            LhsExpr::Index(ref object_lhs_expr, _) => {
+                // only the type of a variable is known; anything else could be a list
+                let object_type = match object_lhs_expr.data {
+                    LhsExpr::Identifier(ref id, _) => self.env.borrow().get_type(id),
+                    _ => None,
+                };
+                match object_type {
+                    Some(Type::List) | Some(Type::Any) | None => {}
+                    Some(_) => self.check_lhs_rebinding(object_lhs_expr),
+                }
+            }
+        }
+    }
+
     fn check_statement_assignment_with_op(&mut self,
                                           lhs_expr: &LhsExprNode,
                                           op: &BinOp,
                                           expr: &ExprNode,
                                           stmt: &StmtNode) {
         let checked_type = self.check_expr_as_value(expr);
+        // the type of a variable does not tell whether it holds a float
+        self.check_division_by_literal_zero(op, false, expr, stmt.pos);
         match lhs_expr.data {
-            LhsExpr::Identifier(ref id) => {
+            LhsExpr::Field(..) |
+            LhsExpr::Index(..) => {
+                let prev_type = self.check_lhs_expr(lhs_expr);
+                self.check_lhs_rebinding(lhs_expr);
+                if let Err(issue) = check_binary_op_for_types(op, &prev_type, &checked_type) {
+                    self.issues.push((issue, stmt.pos));
+                }
+            }
+            LhsExpr::Identifier(ref id, _) => {
                 let prev_type = match self.env.borrow_mut().get_type(id) {
                     Some(t) => t,
                     None => {
@@ -413,16 +1421,11 @@ impl TypeChecker {
                         Type::Any
                     }
                 };
-                let retval = match *op {
-                    BinOp::Add => check_add_for_types(&prev_type, &checked_type),
-                    ref op @ BinOp::Sub |
-                    ref op @ BinOp::Mul |
-                    ref op @ BinOp::Div |
-                    ref op @ BinOp::Mod => {
-                        check_binary_arithmetic_for_types(op.clone(), &prev_type, &checked_type)
-                    }
-                    _ => unreachable!(),
-                };
+                if self.env.borrow().is_immutable(id) {
+                    self.issues
+                        .push((RuntimeError::ImmutableAssignment(id.clone()).into(), lhs_expr.pos));
+                }
+                let retval = check_binary_op_for_types(op, &prev_type, &checked_type);
                 let new_type = match retval {
                     Ok(t) => t,
                     Err(issue) => {
@@ -430,6 +1433,8 @@ impl TypeChecker {
                         Type::Any
                     }
                 };
+                let constraint = self.env.borrow().get_constraint(id);
+                let new_type = self.apply_constraint(id, new_type, &constraint, stmt.pos);
 
                 // if id does not exist, then error was reported above
                 self.env.borrow_mut().set(id, new_type);
@@ -437,10 +1442,86 @@ impl TypeChecker {
         };
     }
 
-    fn check_statement_if_then_else(&mut self,
-                                    statement: &StmtNode,
-                                    if_then_stmt: &IfThenStmt)
-                                    -> StmtEffect {
+    fn check_loop_body(&mut self,
+                       label: &Option<String>,
+                       block: &StmtNode,
+                       is_value_used: bool)
+                       -> StmtEffect {
+        let old_context = self.context.clone();
+        self.context.in_loop = true;
+        self.context.loops.push((label.clone(), is_value_used));
+        let effect = self.check_statement(block);
+        self.context.in_loop = old_context.in_loop;
+        self.context.loops = old_context.loops;
+        effect
+    }
+
+    fn check_loop_label(&mut self, label: &Option<String>, s: &StmtNode) {
+        if let Some(ref label) = *label {
+            if !self.context.loops.iter().any(|&(ref l, _)| l.as_ref() == Some(label)) {
+                self.issues
+                    .push((RuntimeError::UnknownLabel(label.clone()).into(), s.pos));
+            }
+        }
+    }
+
+    // Whether the value of the loop a `break` with `label` leaves is used
+    fn is_loop_value_used(&self, label: &Option<String>) -> bool {
+        let possible_loop = match *label {
+            Some(_) => self.context.loops.iter().rev().find(|&&(ref l, _)| l == label),
+            None => self.context.loops.last(),
+        };
+        possible_loop.is_some_and(|&(_, is_value_used)| is_value_used)
+    }
+
+    fn check_statement_for_in(&mut self,
+                              label: &Option<String>,
+                              var_name: &str,
+                              iterable_expr: &ExprNode,
+                              block: &StmtNode) {
+        let iterable_type = self.check_expr_as_value(iterable_expr);
+        let elem_type = match iterable_type {
+            Type::String => Type::String,
+            Type::Tuple | Type::List | Type::Any => Type::Any,
+            typ => {
+                self.issues
+                    .push((RuntimeError::NonIterable(typ).into(), iterable_expr.pos));
+                Type::Any
+            }
+        };
+        let current_env = self.env.clone();
+        self.env = TypeEnvironment::create_child(current_env.clone());
+        self.env.borrow_mut().declare(var_name, &elem_type);
+        self.check_loop_body(label, block, false);
+        self.env = current_env;
+    }
+
+    fn check_expr_if_then_else(&mut self,
+                               if_expr: &ExprNode,
+                               if_then_stmt: &IfThenStmt)
+                               -> Option<Type> {
+        let (_, then_type, else_type) = self.check_if_then_else(if_expr.pos, if_then_stmt);
+        match (then_type, else_type) {
+            (Some(then_type), Some(else_type)) => {
+                if then_type.is_compatible_with(&else_type) {
+                    Some(then_type)
+                } else {
+                    self.issues
+                        .push((TypeCheckerIssue::MultipleTypesFromBranchWarning(None),
+                               if_expr.pos));
+                    Some(Type::Any)
+                }
+            }
+            // a branch might not give a value
+            _ => None,
+        }
+    }
+
+    // Gives the types of the values of the `then` and `else` blocks along with the effect
+    fn check_if_then_else(&mut self,
+                          pos: OffsetSpan,
+                          if_then_stmt: &IfThenStmt)
+                          -> (StmtEffect, Option<Type>, Option<Type>) {
         let &IfThenStmt {
                  ref cond,
                  ref then_block,
@@ -450,7 +1531,7 @@ impl TypeChecker {
         let else_block = match *maybe_else_block {
             None => {
                 StmtNode {
-                    data: Stmt::Block(vec![]),
+                    data: Stmt::Block(vec![], 0),
                     pos: (0, 0), // dummy span
                 }
             }
@@ -464,12 +1545,15 @@ impl TypeChecker {
         let else_env = TypeEnvironment::create_clone(current_env.clone());
 
         self.env = then_env;
-        let then_effect = self.check_statement(then_block);
+        let (then_effect, then_type) = self.check_statement_with_value(then_block);
 
         let then_pairs = self.env.borrow().get_all_pairs();
 
         self.env = else_env;
-        let else_effect = self.check_statement(&else_block);
+        let (else_effect, else_type) = match *maybe_else_block {
+            Some(_) => self.check_statement_with_value(&else_block),
+            None => (self.check_statement(&else_block), None),
+        };
 
         let else_pairs = self.env.borrow().get_all_pairs();
 
@@ -482,20 +1566,22 @@ impl TypeChecker {
                 panic!("Unexpected behaviour when iterating through environments!");
             }
             if !else_type.is_compatible_with(then_type) {
+                let name = Some(then_name.clone());
                 self.issues
-                    .push((TypeCheckerIssue::MultipleTypesFromBranchWarning(then_name.clone()),
-                           statement.pos));
+                    .push((TypeCheckerIssue::MultipleTypesFromBranchWarning(name), pos));
                 self.env.borrow_mut().set(then_name, Type::Any);
             } else {
                 self.env.borrow_mut().set(then_name, then_type.clone());
             }
         }
 
-        if let (StmtEffect::Return, StmtEffect::Return) = (then_effect, else_effect) {
+        let effect = if let (StmtEffect::Return, StmtEffect::Return) = (then_effect,
+                                                                         else_effect) {
             StmtEffect::Return
         } else {
             StmtEffect::None
-        }
+        };
+        (effect, then_type, else_type)
     }
 
     fn check_statement_return(&mut self,
@@ -512,40 +1598,19 @@ impl TypeChecker {
             // represents `return foo;`
             Some(ref expr) => {
                 let actual_type = self.check_expr_as_value(expr);
-                self.context.func_ret_type = match self.context.func_ret_type {
-                    None => Some(Some(actual_type)),
-                    Some(ref maybe_type /* : Option<Type> */) => {
-                        match *maybe_type {
-                            // Some(None), non-returning
-                            None => {
-                                // If the function didn't return a value at some point,
-                                // then that's the case we'll stick with because the function
-                                // does not *always* return a value.
-
-                                // But we will complain that this time it did return a value.
-                                self.issues
-                                    .push((TypeCheckerIssue::FunctionReturnsMultipleTypes,
-                                           return_statement.pos));
-                                None
-                            }
-                            // Some(Some(typ)), returning typ
-                            Some(ref typ) => {
-                                if !actual_type.is_compatible_with(typ) {
-                                    self.issues
-                                        .push((TypeCheckerIssue::FunctionReturnsMultipleTypes,
-                                               return_statement.pos));
-                                    Some(Some(Type::Any))
-                                } else {
-                                    Some(Some(typ.clone()))
-                                }
-                            }
-                        }
-                    }
-                };
+                self.check_returned_type(actual_type, return_statement.pos);
                 StmtEffect::Return
             }
             // represents `return;`
             None => {
+                if let Some(ReturnConstraint::Type(ref constraint)) =
+                    self.context.func_ret_constraint {
+                    self.issues
+                        .push((RuntimeError::ReturnTypeError(ReturnConstraint::Type(constraint.clone()),
+                                                             None)
+                                       .into(),
+                               return_statement.pos));
+                }
                 // If the function did return a value previously, then it is returning
                 // "multiple types".
                 self.issues
@@ -559,6 +1624,75 @@ impl TypeChecker {
         }
     }
 
+    // Checks a value of the given type being returned from the function being checked
+    fn check_returned_type(&mut self, actual_type: Type, pos: OffsetSpan) {
+        match self.context.func_ret_constraint {
+            Some(ReturnConstraint::Type(ref constraint)) if
+                !actual_type.satisfies_constraint(constraint) => {
+                self.issues
+                    .push((RuntimeError::ReturnTypeError(ReturnConstraint::Type(constraint.clone()),
+                                                         Some(actual_type.clone()))
+                                   .into(),
+                           pos));
+            }
+            Some(ReturnConstraint::Void) => {
+                self.issues
+                    .push((RuntimeError::ReturnTypeError(ReturnConstraint::Void,
+                                                         Some(actual_type.clone()))
+                                   .into(),
+                           pos));
+            }
+            _ => {}
+        }
+        self.context.func_ret_type = match self.context.func_ret_type {
+            None => Some(Some(actual_type)),
+            Some(ref maybe_type /* : Option<Type> */) => {
+                match *maybe_type {
+                    // Some(None), non-returning
+                    None => {
+                        // If the function didn't return a value at some point,
+                        // then that's the case we'll stick with because the function
+                        // does not *always* return a value.
+
+                        // But we will complain that this time it did return a value.
+                        self.issues
+                            .push((TypeCheckerIssue::FunctionReturnsMultipleTypes, pos));
+                        None
+                    }
+                    // Some(Some(typ)), returning typ
+                    Some(ref typ) => {
+                        if !actual_type.is_compatible_with(typ) {
+                            self.issues
+                                .push((TypeCheckerIssue::FunctionReturnsMultipleTypes, pos));
+                            Some(Some(Type::Any))
+                        } else {
+                            Some(Some(typ.clone()))
+                        }
+                    }
+                }
+            }
+        };
+    }
+
+    fn check_expr_try(&mut self, try_expr: &ExprNode, result_expr: &ExprNode) -> Type {
+        match self.check_expr_as_value(result_expr) {
+            Type::Result | Type::Any => {}
+            typ => {
+                self.issues
+                    .push((RuntimeError::TryOnNonResult(typ).into(), result_expr.pos));
+            }
+        }
+        // an `Err` is returned from the enclosing function, like `return`
+        if self.context.in_func {
+            self.check_returned_type(Type::Result, try_expr.pos);
+        } else {
+            self.issues
+                .push((RuntimeError::ReturnOutsideFunction.into(), try_expr.pos));
+        }
+        // the value inside the `Ok` is not known
+        Type::Any
+    }
+
     fn check_expr_tuple(&mut self, elems: &[ExprNode]) -> Type {
         for elem_expr in elems {
             self.check_expr_as_value(elem_expr);
@@ -566,6 +1700,20 @@ impl TypeChecker {
         Type::Tuple
     }
 
+    fn check_expr_list(&mut self, elems: &[ExprNode]) -> Type {
+        for elem_expr in elems {
+            self.check_expr_as_value(elem_expr);
+        }
+        Type::List
+    }
+
+    fn check_expr_map(&mut self, entries: &[(String, ExprNode)]) -> Type {
+        for &(_, ref value_expr) in entries {
+            self.check_expr_as_value(value_expr);
+        }
+        Type::Map
+    }
+
     fn check_expr_unary_op(&mut self, op: &UnOp, expr: &ExprNode) -> Type {
         let typ = self.check_expr_as_value(expr);
         match *op {
@@ -599,14 +1747,16 @@ impl TypeChecker {
         use ast::BinOp::*;
         let result = match *op {
             Add => check_add_for_types(&checked_type_1, &checked_type_2),
-            ref op @ Sub | ref op @ Mul | ref op @ Div | ref op @ Mod => {
+            ref op @ Sub | ref op @ Mul | ref op @ Div | ref op @ Mod | ref op @ Pow |
+            ref op @ BitAnd | ref op @ BitOr | ref op @ BitXor | ref op @ Shl | ref op @ Shr => {
                 check_binary_arithmetic_for_types(op.clone(), &checked_type_1, &checked_type_2)
             }
             ref op @ Lt | ref op @ Lte | ref op @ Gt | ref op @ Gte => {
                 check_binary_comparison_for_types(op.clone(), &checked_type_1, &checked_type_2)
             }
-            Eq => Ok(Type::Bool),
+            Eq | Neq => Ok(Type::Bool),
         };
+        self.check_division_by_literal_zero(op, is_float_expr(expr1), expr2, binary_expr.pos);
         match result {
             Err(e) => {
                 self.issues.push((e, binary_expr.pos));
@@ -616,6 +1766,27 @@ impl TypeChecker {
         }
     }
 
+    // Only divisors written as a literal 0 are caught, like in `x / 0`. Dividing
+    // a float or by the float 0.0 is not an error, since it gives infinity or NaN.
+    fn check_division_by_literal_zero(&mut self,
+                                      op: &BinOp,
+                                      is_float_dividend: bool,
+                                      divisor_expr: &ExprNode,
+                                      pos: OffsetSpan) {
+        let is_division = match *op {
+            BinOp::Div | BinOp::Mod => true,
+            BinOp::Add | BinOp::Sub | BinOp::Mul | BinOp::Pow | BinOp::BitAnd | BinOp::BitOr |
+            BinOp::BitXor | BinOp::Shl | BinOp::Shr | BinOp::Lt | BinOp::Lte | BinOp::Gt |
+            BinOp::Gte | BinOp::Eq | BinOp::Neq => false,
+        };
+        if !is_division || is_float_dividend {
+            return;
+        }
+        if let Expr::Literal(LiteralNode { data: Literal::Integer(0), .. }) = divisor_expr.data {
+            self.issues.push((RuntimeError::DivisionByZero.into(), pos));
+        }
+    }
+
     fn check_expr_binary_logical_expr(&mut self,
                                       expr1: &ExprNode,
                                       op: &LogicalBinOp,
@@ -634,7 +1805,8 @@ impl TypeChecker {
     fn check_expr_function_call(&mut self,
                                 expr: &ExprNode,
                                 f_expr: &ExprNode,
-                                args: &[ExprNode])
+                                args: &[ExprNode],
+                                named_args: &[(String, ExprNode)])
                                 -> Option<Type> {
         let checked_type = self.check_expr_as_value(f_expr);
 
@@ -642,11 +1814,18 @@ impl TypeChecker {
         for arg in args.iter() {
             arg_types.push(self.check_expr_as_value(arg));
         }
+        let mut named_arg_types = Vec::new();
+        for &(ref name, ref arg) in named_args.iter() {
+            named_arg_types.push((name.clone(), self.check_expr_as_value(arg)));
+        }
+        let fn_name = try_get_name_of_fn(f_expr);
 
         let func_type = match checked_type {
             Type::Function(possible_func) => {
                 match *possible_func {
-                    None => unreachable!(),
+                    // a value only known to be a function, e.g. a parameter
+                    // annotated with `Function`
+                    None => return Some(Type::Any),
                     Some(func_type) => func_type,
                 }
             }
@@ -664,109 +1843,188 @@ impl TypeChecker {
         };
 
         let func_call_sign = func_type.get_call_sign();
-        if !func_call_sign.variadic && args.len() != func_type.get_call_sign().num_params {
+        if !func_call_sign.accepts(args.len() + named_args.len()) {
             self.issues
-                .push((RuntimeError::ArgumentLength(try_get_name_of_fn(f_expr)).into(), expr.pos));
+                .push((RuntimeError::ArgumentLength(fn_name).into(), expr.pos));
             return Some(Type::Any);
         }
+        let param_names = func_type.get_param_names();
+        let mut arranged_arg_types: Vec<Option<Type>> =
+            arg_types.iter().cloned().map(Some).collect();
+        for (name, arg_type) in named_arg_types {
+            let idx = match param_names.iter().position(|param| *param == name) {
+                Some(idx) => idx,
+                None => {
+                    self.issues
+                        .push((RuntimeError::UnknownParameter(fn_name, name).into(), expr.pos));
+                    return Some(Type::Any);
+                }
+            };
+            if idx >= arranged_arg_types.len() {
+                arranged_arg_types.resize(idx + 1, None);
+            }
+            if arranged_arg_types[idx].is_some() {
+                self.issues
+                    .push((RuntimeError::DuplicateArgument(name).into(), expr.pos));
+                return Some(Type::Any);
+            }
+            arranged_arg_types[idx] = Some(arg_type);
+        }
         match func_type {
-            FunctionType::NativeVoid(_) => None,
-            FunctionType::NativeReturning(_) => Some(Type::Any),
+            FunctionType::NativeVoid(_, ref param_types) => {
+                self.check_argument_types(&fn_name, param_types, args, &arg_types);
+                None
+            }
+            FunctionType::NativeReturning(_, ref param_types, ref ret_type) => {
+                self.check_argument_types(&fn_name, param_types, args, &arg_types);
+                Some(ret_type.clone())
+            }
             FunctionType::User {
-                ref param_names,
+                ref params,
+                ref ret_constraint,
                 ref body,
                 ref env,
                 ref already_checked_param_types,
                 ..
             } => {
-                let function_env = TypeEnvironment::create_child(self.env.clone());
-                for (param, arg) in param_names.iter().zip(arg_types.iter()) {
-                    function_env.borrow_mut().declare(param, arg);
+                let function_env = TypeEnvironment::create_child(env.clone());
+                let mut arranged_arg_types = arranged_arg_types.into_iter();
+                // the types the parameters are bound to, which the checked body
+                // is cached by
+                let mut param_types = Vec::new();
+                for param in params.iter() {
+                    let arg_type = if param.is_rest {
+                        Type::Tuple
+                    } else {
+                        match (arranged_arg_types.next(), &param.default) {
+                            (Some(Some(arg_type)), _) => arg_type,
+                            (_, &Some(ref default)) => {
+                                let current_env = self.env.clone();
+                                let old_context = self.context.clone();
+                                self.env = function_env.clone();
+                                self.context = Context::for_function(ret_constraint);
+                                let default_type = self.check_expr_as_value(default);
+                                self.env = current_env;
+                                self.context = old_context;
+                                default_type
+                            }
+                            (_, &None) => {
+                                self.issues
+                                    .push((RuntimeError::MissingArgument(param.name.clone())
+                                               .into(),
+                                           expr.pos));
+                                Type::Any
+                            }
+                        }
+                    };
+                    let typ =
+                        self.apply_constraint(&param.name, arg_type, &param.constraint, expr.pos);
+                    function_env
+                        .borrow_mut()
+                        .declare_binding(&param.name,
+                                         &typ,
+                                         BindingType::Mutable,
+                                         param.constraint.clone());
+                    param_types.push(typ);
                 }
                 let inner_env = TypeEnvironment::create_child(function_env);
 
-                let constraint_types = arg_types.iter().map(|typ| typ.clone().into()).collect();
-                if !already_checked_param_types.contains_key(&constraint_types) {
-                    if let Some(id) = try_get_name_of_fn(f_expr) {
-                        let mut new_checked_param_types = already_checked_param_types.clone();
-                        new_checked_param_types.insert(constraint_types, ());
-                        let new_func_type =
-                            get_function_type_with_updated_already_checked(&func_type,
-                                                                           new_checked_param_types);
-                        env.borrow_mut()
-                            .set(&id, Type::Function(Box::new(Some(new_func_type))));
-
-                        let old_context = self.context.clone();
-                        self.context = Context {
-                            in_loop: false,
-                            in_func: true,
-                            func_ret_type: None,
-                        };
-                        let mut outer_issues = self.issues.clone();
-                        self.issues = Vec::new();
-                        let current_env = self.env.clone();
-                        self.env = inner_env;
-                        let fn_body_effect = self.check_statement(body);
-                        self.env = current_env;
-                        for inner_issue in &self.issues {
-                            outer_issues
-                            .push((
-                                TypeCheckerIssue::InsideFunctionCall(
-                                    Box::new(inner_issue.clone())
-                                ),
-                                expr.pos
-                            ));
+                // the body is checked again if a variable it could refer to
+                // got a different type since it was last checked
+                let type_changes = env.borrow().get_type_changes();
+                let possible_checked_call = already_checked_param_types
+                    .borrow()
+                    .get(&param_types)
+                    .cloned();
+                match possible_checked_call {
+                    Some(Some((checked_type_changes, checked_ret_type))) => {
+                        if checked_type_changes == type_changes {
+                            return apply_return_constraint(checked_ret_type, ret_constraint);
                         }
-                        self.issues = outer_issues;
-                        let ret_type;
-                        if let StmtEffect::None = fn_body_effect {
-                            self.context = old_context;
-                            None
-                        } else {
-                            match self.context.func_ret_type {
-                                // non-returning
-                                None | Some(None) => {
-                                    ret_type = None;
-                                }
-                                Some(ref typ) => {
-                                    ret_type = typ.clone();
-                                }
-                            }
-
-                            self.context = old_context;
-                            ret_type
-                        }
-                    } else {
-                        // TODO
-                        // If the function is anonymous, no typechecking is performed,
-                        // but probably could and should be.
-                        Some(Type::Any)
                     }
-                } else {
-                    // TODO
-                    // If it's been previously checked, remember that and
-                    // use the return type
-                    Some(Type::Any)
+                    // A recursive call with the same argument types returns `Any`
+                    // instead of checking the body again.
+                    Some(None) => return apply_return_constraint(Some(Type::Any), ret_constraint),
+                    None => {}
                 }
+                already_checked_param_types
+                    .borrow_mut()
+                    .insert(param_types.clone(), None);
+
+                let old_context = self.context.clone();
+                self.context = Context::for_function(ret_constraint);
+                let mut outer_issues = self.issues.clone();
+                self.issues = Vec::new();
+                let current_env = self.env.clone();
+                self.env = inner_env;
+                let fn_body_effect = self.check_statement(body);
+                self.env = current_env;
+                for inner_issue in &self.issues {
+                    outer_issues
+                    .push((
+                        TypeCheckerIssue::InsideFunctionCall(
+                            Box::new(inner_issue.clone())
+                        ),
+                        expr.pos
+                    ));
+                }
+                self.issues = outer_issues;
+                let ret_type;
+                let inferred_ret_type = if let StmtEffect::None = fn_body_effect {
+                    if let Some(ReturnConstraint::Type(ref constraint)) = *ret_constraint {
+                        self.issues
+                            .push((RuntimeError::ReturnTypeError(ReturnConstraint::Type(constraint.clone()),
+                                                                 None)
+                                           .into(),
+                                   expr.pos));
+                    }
+                    self.context = old_context;
+                    None
+                } else {
+                    match self.context.func_ret_type {
+                        // non-returning
+                        None | Some(None) => {
+                            ret_type = None;
+                        }
+                        Some(ref typ) => {
+                            ret_type = typ.clone();
+                        }
+                    }
+
+                    self.context = old_context;
+                    ret_type
+                };
+                already_checked_param_types
+                    .borrow_mut()
+                    .insert(param_types, Some((type_changes, inferred_ret_type.clone())));
+                apply_return_constraint(inferred_ret_type, ret_constraint)
             }
         }
     }
 
-    fn check_expr_function_definition(&mut self, fn_def_expr: &FnDefExpr) -> Type {
+    fn check_expr_function_definition(&mut self,
+                                      fn_def_expr: &FnDefExpr,
+                                      fn_expr: &ExprNode)
+                                      -> Type {
         let &FnDefExpr {
                  ref maybe_id,
                  ref params,
+                 ref ret_constraint,
                  ref body,
+                 ..
              } = fn_def_expr;
+        if let Some(param) = fn_def_expr.find_required_param_after_default() {
+            self.issues
+                .push((RuntimeError::RequiredParameterAfterDefault(param.clone()).into(),
+                       fn_expr.pos));
+        }
         let func = FunctionType::User {
-            call_sign: CallSign {
-                num_params: params.len(),
-                variadic: false,
-            },
-            param_names: params.to_vec(),
+            call_sign: CallSign::from_params(params),
+            params: params.to_vec(),
+            ret_constraint: ret_constraint.clone(),
             body: body.clone(),
             env: self.env.clone(),
-            already_checked_param_types: LinearMap::new(),
+            already_checked_param_types: Rc::new(RefCell::new(LinearMap::new())),
         };
         let func_type = Type::Function(Box::new(Some(func)));
         if let Some(ref id) = *maybe_id {
@@ -780,30 +2038,134 @@ impl TypeChecker {
                                          index_expr: &ExprNode)
                                          -> Type {
         let object_type = self.check_expr_as_value(expr);
-        match object_type {
-            Type::Tuple | Type::Any => {}
-            typ => {
+        let index_type = self.check_expr_as_value(index_expr);
+        self.check_subscript(object_type, index_type, expr.pos, index_expr.pos)
+    }
+
+    fn check_subscript(&mut self,
+                       object_type: Type,
+                       index_type: Type,
+                       object_pos: OffsetSpan,
+                       index_pos: OffsetSpan)
+                       -> Type {
+        match (object_type, index_type) {
+            (Type::String, Type::Number) |
+            (Type::String, Type::Any) => return Type::String,
+            (Type::Tuple, Type::Number) |
+            (Type::Tuple, Type::Any) |
+            (Type::List, Type::Number) |
+            (Type::List, Type::Any) |
+            (Type::Map, Type::String) |
+            (Type::Map, Type::Any) |
+            (Type::Any, Type::Number) |
+            (Type::Any, Type::String) |
+            (Type::Any, Type::Any) => {}
+            (Type::Tuple, non_integral_type) |
+            (Type::List, non_integral_type) |
+            (Type::String, non_integral_type) |
+            (Type::Any, non_integral_type) => {
                 self.issues
-                    .push((RuntimeError::SubscriptOnNonSubscriptable(typ).into(), expr.pos));
+                    .push((RuntimeError::NonIntegralSubscript(non_integral_type).into(), index_pos));
             }
-        };
-        let typ = self.check_expr_as_value(index_expr);
-        match typ {
-            Type::Number | Type::Any => {}
-            non_integral_type => {
+            (Type::Map, non_string_type) => {
                 self.issues
-                    .push((RuntimeError::NonIntegralSubscript(non_integral_type).into(),
-                           index_expr.pos));
+                    .push((RuntimeError::NonStringMapKey(non_string_type).into(), index_pos));
+            }
+            (typ, _) => {
+                self.issues
+                    .push((RuntimeError::SubscriptOnNonSubscriptable(typ).into(), object_pos));
             }
         };
         Type::Any
     }
 
+    fn check_field_access(&mut self, object_type: Type, field: &str, pos: OffsetSpan) -> Type {
+        match object_type {
+            Type::Struct(ref name, ref fields) => {
+                if !fields.iter().any(|f| f == field) {
+                    self.issues
+                        .push((TypeCheckerIssue::UnknownField(name.clone(), field.to_owned()),
+                               pos));
+                }
+            }
+            Type::Module(ref path, ref members) => {
+                match members.get(field) {
+                    Some(typ) => return typ.clone(),
+                    None => {
+                        self.issues
+                            .push((RuntimeError::UnknownModuleMember(path.clone(),
+                                                                     field.to_owned())
+                                           .into(),
+                                   pos));
+                    }
+                }
+            }
+            Type::Any => {}
+            typ => {
+                self.issues
+                    .push((RuntimeError::FieldAccessOnNonStruct(typ).into(), pos));
+            }
+        }
+        Type::Any
+    }
+
+    /// Checks each argument against the parameter type a native function declares for it.
+    /// A function passed where a function with a different number of parameters
+    /// is expected is reported as an `ArgumentLength` issue for the passed function.
+    fn check_argument_types(&mut self,
+                            fn_name: &Option<String>,
+                            param_types: &[Type],
+                            args: &[ExprNode],
+                            arg_types: &[Type]) {
+        for ((param_type, arg), arg_type) in param_types.iter().zip(args).zip(arg_types) {
+            if param_type.is_compatible_with(arg_type) {
+                continue;
+            }
+            let issue = match (param_type, arg_type) {
+                (&Type::Function(ref expected), &Type::Function(ref actual)) if
+                    has_mismatched_num_params(expected, actual) => {
+                    RuntimeError::ArgumentLength(try_get_name_of_fn(arg))
+                }
+                _ => {
+                    RuntimeError::ArgumentTypeError(fn_name.clone(),
+                                                    param_type.clone(),
+                                                    arg_type.clone())
+                }
+            };
+            self.issues.push((issue.into(), arg.pos));
+        }
+    }
+
+    /// Checks `typ` against the annotation of the binding `id` (if any), and returns
+    /// the type the binding should be recorded with.
+    fn apply_constraint(&mut self,
+                        id: &str,
+                        typ: Type,
+                        possible_constraint: &Option<ConstraintType>,
+                        pos: OffsetSpan)
+                        -> Type {
+        let constraint = match *possible_constraint {
+            None => return typ,
+            Some(ref constraint) => constraint,
+        };
+        if !typ.satisfies_constraint(constraint) {
+            self.issues
+                .push((RuntimeError::BindingTypeError(id.to_owned(), constraint.clone(), typ)
+                           .into(),
+                       pos));
+            Type::from(constraint.clone())
+        } else if typ == Type::Any {
+            Type::from(constraint.clone())
+        } else {
+            typ
+        }
+    }
+
     fn check_expr_as_value(&mut self, expr: &ExprNode) -> Type {
         let possible_type = self.check_expr(expr);
         if possible_type.is_none() {
-            if let Expr::FnCall(ref f_expr, _) = expr.data {
-                if let Expr::Identifier(ref id) = f_expr.data {
+            if let Expr::FnCall(ref f_expr, ..) = expr.data {
+                if let Expr::Identifier(ref id, _) = f_expr.data {
                     self.issues
                         .push((TypeCheckerIssue::PossibleNoneError(Some(id.clone())), expr.pos));
                 } else {
@@ -811,7 +2173,9 @@ impl TypeChecker {
                         .push((TypeCheckerIssue::PossibleNoneError(None), expr.pos));
                 }
             } else {
-                unreachable!();
+                // an `if` or block whose last statement is not an expression
+                self.issues
+                    .push((RuntimeError::NoneError(None).into(), expr.pos));
             }
             Type::Any
         } else {
@@ -831,6 +2195,7 @@ fn check_unary_minus_for_type(typ: Type) -> Result<Type, TypeCheckerIssue> {
 fn check_add_for_types(t1: &Type, t2: &Type) -> Result<Type, TypeCheckerIssue> {
     match (t1, t2) {
         (&Type::Number, &Type::Number) => Ok(Type::Number),
+        (&Type::List, &Type::List) => Ok(Type::List),
         (&Type::String, _) |
         (_, &Type::String) => Ok(Type::String),
         (&Type::Any, _) | (_, &Type::Any) => Ok(Type::Any),
@@ -838,6 +2203,63 @@ fn check_add_for_types(t1: &Type, t2: &Type) -> Result<Type, TypeCheckerIssue> {
     }
 }
 
+// Whether a value of type `typ` could match `pattern`
+fn pattern_can_match(pattern: &Pattern, typ: &Type) -> bool {
+    match *pattern {
+        Pattern::Wildcard |
+        Pattern::Binding(..) => true,
+        Pattern::Literal(ref literal) => {
+            *typ == Type::Any || *typ == Type::from(literal.clone())
+        }
+        Pattern::Tuple(_) => *typ == Type::Any || *typ == Type::Tuple,
+    }
+}
+
+fn is_catch_all(pattern: &Pattern) -> bool {
+    match *pattern {
+        Pattern::Wildcard |
+        Pattern::Binding(..) => true,
+        Pattern::Literal(_) |
+        Pattern::Tuple(_) => false,
+    }
+}
+
+// Whether the patterns include both `true` and `false`
+fn covers_bool(patterns: &[&Pattern]) -> bool {
+    [true, false]
+        .iter()
+        .all(|b| patterns.contains(&&Pattern::Literal(Literal::Bool(*b))))
+}
+
+/// Checks the operator of a compound assignment such as `+=`
+fn check_binary_op_for_types(op: &BinOp, t1: &Type, t2: &Type) -> Result<Type, TypeCheckerIssue> {
+    match *op {
+        BinOp::Add => check_add_for_types(t1, t2),
+        BinOp::Lt | BinOp::Lte | BinOp::Gt | BinOp::Gte | BinOp::Eq | BinOp::Neq => {
+            unreachable!()
+        }
+        ref op => check_binary_arithmetic_for_types(op.clone(), t1, t2),
+    }
+}
+
+// Whether `expr` is known to give a float, which is the case for float literals
+// and arithmetic on them
+fn is_float_expr(expr: &ExprNode) -> bool {
+    match expr.data {
+        Expr::Literal(LiteralNode { data: Literal::Float(_), .. }) => true,
+        Expr::Unary(UnOp::Neg, ref operand) => is_float_expr(operand),
+        Expr::Binary(ref expr1, BinOp::Add, ref expr2) |
+        Expr::Binary(ref expr1, BinOp::Sub, ref expr2) |
+        Expr::Binary(ref expr1, BinOp::Mul, ref expr2) |
+        Expr::Binary(ref expr1, BinOp::Div, ref expr2) |
+        Expr::Binary(ref expr1, BinOp::Mod, ref expr2) |
+        Expr::Binary(ref expr1, BinOp::Pow, ref expr2) => {
+            is_float_expr(expr1) || is_float_expr(expr2)
+        }
+        _ => false,
+    }
+}
+
 fn check_binary_arithmetic_for_types(op: BinOp,
                                      t1: &Type,
                                      t2: &Type)
@@ -854,39 +2276,47 @@ fn check_binary_comparison_for_types(op: BinOp,
                                      t2: &Type)
                                      -> Result<Type, TypeCheckerIssue> {
     match (t1, t2) {
-        (&Type::Number, &Type::Number) => Ok(Type::Bool),
+        (&Type::Number, &Type::Number) |
+        (&Type::String, &Type::String) |
+        (&Type::Tuple, &Type::Tuple) => Ok(Type::Bool),
         (&Type::Any, _) | (_, &Type::Any) => Ok(Type::Any),
         _ => Err(RuntimeError::BinaryTypeError(op, t1.clone(), t2.clone()).into()),
     }
 }
 
-fn try_get_name_of_fn(expr: &ExprNode) -> Option<String> {
-    if let Expr::Identifier(ref id) = expr.data {
-        Some(id.to_string())
-    } else {
-        None
+fn apply_return_constraint(possible_type: Option<Type>,
+                           possible_constraint: &Option<ReturnConstraint>)
+                           -> Option<Type> {
+    match *possible_constraint {
+        None => possible_type,
+        Some(ReturnConstraint::Void) => None,
+        Some(ReturnConstraint::Type(ref constraint)) => {
+            match possible_type {
+                Some(ref typ) if *typ != Type::Any && typ.satisfies_constraint(constraint) => {
+                    Some(typ.clone())
+                }
+                _ => Some(Type::from(constraint.clone())),
+            }
+        }
     }
 }
 
-fn get_function_type_with_updated_already_checked(old_fn_type: &FunctionType,
-                                                  new_already_checked: LinearMap<Vec<Type>, ()>)
-                                                  -> FunctionType {
-
-    if let FunctionType::User {
-               ref param_names,
-               ref body,
-               ref env,
-               ref call_sign,
-               ..
-           } = *old_fn_type {
-        FunctionType::User {
-            param_names: param_names.clone(),
-            body: body.clone(),
-            env: env.clone(),
-            already_checked_param_types: new_already_checked,
-            call_sign: call_sign.clone(),
+fn has_mismatched_num_params(expected: &Option<FunctionType>,
+                             actual: &Option<FunctionType>)
+                             -> bool {
+    match (expected, actual) {
+        (&Some(ref expected), &Some(ref actual)) => {
+            let (expected_sign, actual_sign) = (expected.get_call_sign(), actual.get_call_sign());
+            !expected_sign.overlaps(&actual_sign)
         }
+        _ => false,
+    }
+}
+
+fn try_get_name_of_fn(expr: &ExprNode) -> Option<String> {
+    if let Expr::Identifier(ref id, _) = expr.data {
+        Some(id.to_string())
     } else {
-        panic!("Not a user function");
+        None
     }
 }
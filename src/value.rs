@@ -1,10 +1,17 @@
 use std::fmt;
 use std::ops;
 use std::cmp;
+use std::rc::Rc;
+use std::cell::RefCell;
+
+use linear_map::LinearMap;
 
 use ast;
+use bigint::BigInt;
 use typechecker::Type;
 use function::*;
+use environment::Environment;
+use runtime::RuntimeError;
 
 #[derive(Clone)]
 pub enum Value {
@@ -13,147 +20,383 @@ pub enum Value {
     Function(Box<Function>),
     String(String),
     Tuple(Vec<Value>),
+    /// A list whose elements are shared between all copies of the value
+    List(Rc<RefCell<Vec<Value>>>),
+    Map(LinearMap<String, Value>),
+    /// An instance of the struct with the given name
+    Struct(String, LinearMap<String, Value>),
+    /// A module imported from the file at the given path, whose top-level
+    /// bindings are kept in the environment
+    Module(String, Rc<RefCell<Environment>>),
+    /// `Ok(value)` or `Err(error)`
+    Result(Result<Box<Value>, Box<Value>>),
 }
 
-#[derive(Debug, Copy, Clone)]
+#[derive(Debug, Clone)]
 pub enum Number {
     Integer(i64),
     Float(f64),
+    /// An integer that does not fit in an `i64`. Integers that fit are always
+    /// represented as `Integer`.
+    BigInt(BigInt),
 }
 
+// The elements of a `Value::List`
+type SharedList = Rc<RefCell<Vec<Value>>>;
+
 impl fmt::Debug for Value {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        match *self {
-            Value::Bool(b) => write!(f, "{}", b),
-            Value::Number(n) => write!(f, "{}", n),
-            Value::Function(_) => write!(f, "<Function>"),
-            Value::String(ref s) => write!(f, "\"{}\"", s),
-            Value::Tuple(ref t) => {
-                if t.is_empty() {
-                    write!(f, "()")
-                } else {
-                    let mut output = "(".to_owned();
-                    for elem in &t[0..t.len() - 1] {
-                        output.push_str(&format!("{:?}", elem));
-                        output.push_str(", ");
-                    }
-                    output.push_str(&format!("{:?}", &t[t.len() - 1]));
-                    output.push_str(")");
-                    write!(f, "{}", output)
-                }
-            }
-        }
+        write!(f, "{}", self.format(true, &mut Vec::new()))
     }
 }
 
 impl fmt::Display for Value {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.format(false, &mut Vec::new()))
+    }
+}
+
+impl Value {
+    // Formats the value as `Debug` does if `debug` is set, and as `Display`
+    // does otherwise. `lists` are the lists this value is nested in, which are
+    // written as `[...]` where a list contains itself.
+    fn format(&self, debug: bool, lists: &mut Vec<SharedList>) -> String {
         match *self {
-            Value::String(ref s) => write!(f, "{}", s),
+            Value::Bool(b) => b.to_string(),
+            Value::Number(ref n) => n.to_string(),
+            Value::Function(_) => "<Function>".to_owned(),
+            Value::String(ref s) if debug => format!("\"{}\"", s),
+            Value::String(ref s) => s.clone(),
             Value::Tuple(ref t) => {
-                if t.is_empty() {
-                    write!(f, "()")
-                } else {
-                    let mut output = "(".to_owned();
-                    for elem in &t[0..t.len() - 1] {
-                        output.push_str(&format!("{}", elem));
-                        output.push_str(", ");
-                    }
-                    output.push_str(&format!("{}", &t[t.len() - 1]));
-                    output.push_str(")");
-                    write!(f, "{}", output)
+                let elems: Vec<String> = t.iter().map(|elem| elem.format(debug, lists)).collect();
+                format!("({})", elems.join(", "))
+            }
+            Value::List(ref l) => {
+                if lists.iter().any(|list| Rc::ptr_eq(list, l)) {
+                    return "[...]".to_owned();
+                }
+                lists.push(l.clone());
+                let elems: Vec<String> = l.borrow()
+                    .iter()
+                    .map(|elem| elem.format(debug, lists))
+                    .collect();
+                lists.pop();
+                format!("[{}]", elems.join(", "))
+            }
+            Value::Map(ref m) => {
+                let entries: Vec<String> = m.iter()
+                    .map(|(key, value)| format!("{:?}: {}", key, value.format(debug, lists)))
+                    .collect();
+                format!("{{{}}}", entries.join(", "))
+            }
+            Value::Struct(ref name, ref fields) => {
+                let fields: Vec<String> = fields
+                    .iter()
+                    .map(|(field, value)| format!(" {}: {}", field, value.format(debug, lists)))
+                    .collect();
+                format!("{} {{{} }}", name, fields.join(","))
+            }
+            Value::Module(ref path, _) => format!("<Module \"{}\">", path),
+            Value::Result(Ok(ref val)) => format!("Ok({})", val.format(debug, lists)),
+            Value::Result(Err(ref err)) => format!("Err({})", err.format(debug, lists)),
+        }
+    }
+
+    // Compares the values, taking the pairs of lists in `comparing`, which are
+    // already being compared further out, to be equal so that comparing lists
+    // that contain themselves ends
+    fn equals(&self, other: &Value, comparing: &mut Vec<(SharedList, SharedList)>) -> bool {
+        match (self, other) {
+            (&Value::Tuple(ref ta), &Value::Tuple(ref tb)) => all_equal(ta, tb, comparing),
+            (&Value::List(ref la), &Value::List(ref lb)) => {
+                if Rc::ptr_eq(la, lb) ||
+                   comparing
+                       .iter()
+                       .any(|&(ref a, ref b)| Rc::ptr_eq(a, la) && Rc::ptr_eq(b, lb)) {
+                    return true;
                 }
+                comparing.push((la.clone(), lb.clone()));
+                let result = all_equal(&la.borrow(), &lb.borrow(), comparing);
+                comparing.pop();
+                result
+            }
+            (&Value::Map(ref ma), &Value::Map(ref mb)) => maps_equal(ma, mb, comparing),
+            (&Value::Struct(ref na, ref fa), &Value::Struct(ref nb, ref fb)) => {
+                na == nb && maps_equal(fa, fb, comparing)
             }
-            ref value => write!(f, "{:?}", value),
+            (&Value::Result(Ok(ref a)), &Value::Result(Ok(ref b))) |
+            (&Value::Result(Err(ref a)), &Value::Result(Err(ref b))) => a.equals(b, comparing),
+            (&Value::Number(ref a), &Value::Number(ref b)) => a == b,
+            (&Value::Bool(a), &Value::Bool(b)) => a == b,
+            (&Value::String(ref sa), &Value::String(ref sb)) => sa == sb,
+            (&Value::Module(_, ref ea), &Value::Module(_, ref eb)) => Rc::ptr_eq(ea, eb),
+            _ => false,
         }
     }
 }
 
+fn all_equal(a: &[Value], b: &[Value], comparing: &mut Vec<(SharedList, SharedList)>) -> bool {
+    a.len() == b.len() && a.iter().zip(b).all(|(x, y)| x.equals(y, comparing))
+}
+
+fn maps_equal(a: &LinearMap<String, Value>,
+              b: &LinearMap<String, Value>,
+              comparing: &mut Vec<(SharedList, SharedList)>)
+              -> bool {
+    a.len() == b.len() &&
+    a.iter()
+        .all(|(key, x)| b.get(key).is_some_and(|y| x.equals(y, comparing)))
+}
+
+// Integer arithmetic that overflows an `i64` gives a `BigInt`. Mixing integers
+// and floats gives a float.
 impl Number {
     pub fn signum(&self) -> Number {
         match *self {
             Number::Integer(x) => Number::Integer(x.signum()),
             Number::Float(x) => Number::Float(x.signum()),
+            Number::BigInt(ref x) if x.is_negative() => Number::Integer(-1),
+            Number::BigInt(_) => Number::Integer(1),
         }
     }
-}
 
-impl fmt::Display for Number {
-    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    pub fn as_float(&self) -> f64 {
         match *self {
-            Number::Integer(x) => write!(f, "{}", x),
-            Number::Float(x) => write!(f, "{}", x),
+            Number::Integer(x) => x as f64,
+            Number::Float(x) => x,
+            Number::BigInt(ref x) => x.to_f64(),
         }
     }
-}
 
-impl ops::Add for Number {
-    type Output = Number;
+    pub fn is_zero(&self) -> bool {
+        match *self {
+            Number::Integer(x) => x == 0,
+            Number::Float(x) => x == 0.0,
+            Number::BigInt(ref x) => x.is_zero(),
+        }
+    }
 
-    fn add(self, other: Number) -> Number {
+    // Floats are not integers, even if they have no fractional part
+    fn is_integer(&self) -> bool {
+        match *self {
+            Number::Integer(_) | Number::BigInt(_) => true,
+            Number::Float(_) => false,
+        }
+    }
+
+    fn as_bigint(&self) -> Option<BigInt> {
+        match *self {
+            Number::Integer(x) => Some(BigInt::from(x)),
+            Number::Float(_) => None,
+            Number::BigInt(ref x) => Some(x.clone()),
+        }
+    }
+
+    /// Dividing integers gives an integer if the division is exact, and a float
+    /// otherwise. Only integers cannot be divided by zero: floats give infinity
+    /// or NaN.
+    pub fn checked_div(self, other: Number) -> Result<Number, RuntimeError> {
+        if self.is_integer() && other.is_integer() && other.is_zero() {
+            return Err(RuntimeError::DivisionByZero);
+        }
+        if let (Number::Integer(x), Number::Integer(y)) = (&self, &other) {
+            // the remainder only overflows for `i64::MIN / -1`, which is left to BigInt
+            match x.checked_rem(*y) {
+                Some(0) => return Ok(Number::Integer(x / y)),
+                Some(_) => return Ok(Number::Float((*x as f64) / (*y as f64))),
+                None => {}
+            }
+        }
+        match (self.as_bigint(), other.as_bigint()) {
+            (Some(x), Some(y)) => {
+                let (quotient, remainder) = x.div_rem(&y).unwrap();
+                if remainder.is_zero() {
+                    Ok(Number::from(quotient))
+                } else {
+                    Ok(Number::Float(x.to_f64() / y.to_f64()))
+                }
+            }
+            _ => Ok(Number::Float(self.as_float() / other.as_float())),
+        }
+    }
+
+    pub fn checked_rem(self, other: Number) -> Result<Number, RuntimeError> {
+        if self.is_integer() && other.is_integer() && other.is_zero() {
+            return Err(RuntimeError::DivisionByZero);
+        }
         match (self, other) {
-            (Number::Integer(x), Number::Integer(y)) => Number::Integer(x + y),
-            (Number::Float(x), Number::Float(y)) => Number::Float(x + y),
-            (Number::Float(x), Number::Integer(y)) => Number::Float(x + (y as f64)),
-            (Number::Integer(x), Number::Float(y)) => Number::Float((x as f64) + y),
+            // `i64::MIN % -1` is 0, even though computing it overflows
+            (Number::Integer(x), Number::Integer(y)) => Ok(Number::Integer(x.wrapping_rem(y))),
+            (x, y) => {
+                match (x.as_bigint(), y.as_bigint()) {
+                    (Some(bx), Some(by)) => Ok(Number::from(bx.div_rem(&by).unwrap().1)),
+                    _ => Ok(Number::Float(x.as_float() % y.as_float())),
+                }
+            }
+        }
+    }
+
+    /// Raises to a power, giving an integer when both are integers and the
+    /// exponent is not negative
+    pub fn pow(self, exponent: Number) -> Result<Number, RuntimeError> {
+        match (self.as_bigint(), exponent.as_bigint()) {
+            (Some(base), Some(e)) if !e.is_negative() => {
+                let e = match e.to_i64() {
+                    Some(e) if e <= u32::MAX as i64 => e as u32,
+                    _ => return Err(RuntimeError::IntegerOverflow),
+                };
+                match base.to_i64().and_then(|b| b.checked_pow(e)) {
+                    Some(result) => Ok(Number::Integer(result)),
+                    None => Ok(Number::from(checked_bigint_pow(base, e)?)),
+                }
+            }
+            _ => Ok(Number::Float(self.as_float().powf(exponent.as_float()))),
+        }
+    }
+
+    /// Applies `&`, `|` or `^` to integers
+    pub fn bitwise(self, op: ast::BinOp, other: Number) -> Result<Number, RuntimeError> {
+        if let (&Number::Integer(x), &Number::Integer(y)) = (&self, &other) {
+            return match op {
+                ast::BinOp::BitAnd => Ok(Number::Integer(x & y)),
+                ast::BinOp::BitOr => Ok(Number::Integer(x | y)),
+                ast::BinOp::BitXor => Ok(Number::Integer(x ^ y)),
+                _ => unreachable!(),
+            };
+        }
+        let word_op: fn(u32, u32) -> u32 = match op {
+            ast::BinOp::BitAnd => |a, b| a & b,
+            ast::BinOp::BitOr => |a, b| a | b,
+            ast::BinOp::BitXor => |a, b| a ^ b,
+            _ => unreachable!(),
+        };
+        match (self.as_bigint(), other.as_bigint()) {
+            (Some(x), Some(y)) => Ok(Number::from(x.bitwise(&y, word_op))),
+            _ => Err(RuntimeError::NonIntegerOperand(op)),
+        }
+    }
+
+    pub fn shift_left(self, amount: Number) -> Result<Number, RuntimeError> {
+        if let (&Number::Integer(x), &Number::Integer(n)) = (&self, &amount) {
+            if (0..64).contains(&n) && (x << n) >> n == x {
+                return Ok(Number::Integer(x << n));
+            }
+        }
+        let (x, n) = get_shift_operands(ast::BinOp::Shl, &self, &amount)?;
+        if x.is_zero() {
+            return Ok(Number::Integer(0));
+        }
+        if n > u32::MAX as u64 {
+            return Err(RuntimeError::IntegerOverflow);
+        }
+        let factor = checked_bigint_pow(BigInt::from(2), n as u32)?;
+        Ok(Number::from(x.mul(&factor)))
+    }
+
+    /// Shifts right, rounding towards negative infinity
+    pub fn shift_right(self, amount: Number) -> Result<Number, RuntimeError> {
+        if let (&Number::Integer(x), &Number::Integer(n)) = (&self, &amount) {
+            if n >= 0 {
+                return Ok(Number::Integer(x >> cmp::min(n, 63)));
+            }
         }
+        let (x, n) = get_shift_operands(ast::BinOp::Shr, &self, &amount)?;
+        Ok(Number::from(x.shr(n)))
     }
 }
 
-impl ops::Sub for Number {
-    type Output = Number;
+// The most digits an integer computed by `**` or `<<` can have, since computing
+// larger ones could take forever
+const MAX_COMPUTED_DIGITS: f64 = 1_000_000.0;
 
-    fn sub(self, other: Number) -> Number {
-        match (self, other) {
-            (Number::Integer(x), Number::Integer(y)) => Number::Integer(x - y),
-            (Number::Float(x), Number::Float(y)) => Number::Float(x - y),
-            (Number::Float(x), Number::Integer(y)) => Number::Float(x - (y as f64)),
-            (Number::Integer(x), Number::Float(y)) => Number::Float((x as f64) - y),
+fn checked_bigint_pow(base: BigInt, exponent: u32) -> Result<BigInt, RuntimeError> {
+    if base.to_f64().abs().log10() * (exponent as f64) > MAX_COMPUTED_DIGITS {
+        Err(RuntimeError::IntegerOverflow)
+    } else {
+        Ok(base.pow(exponent))
+    }
+}
+
+// Shift amounts too large for a `u64` are rounded down to `u64::MAX`
+fn get_shift_operands(op: ast::BinOp,
+                      x: &Number,
+                      amount: &Number)
+                      -> Result<(BigInt, u64), RuntimeError> {
+    match (x.as_bigint(), amount.as_bigint()) {
+        (Some(_), Some(ref n)) if n.is_negative() => Err(RuntimeError::NegativeShiftAmount),
+        (Some(x), Some(n)) => Ok((x, n.to_i64().map_or(u64::MAX, |n| n as u64))),
+        _ => Err(RuntimeError::NonIntegerOperand(op)),
+    }
+}
+
+impl From<BigInt> for Number {
+    fn from(from: BigInt) -> Self {
+        match from.to_i64() {
+            Some(x) => Number::Integer(x),
+            None => Number::BigInt(from),
         }
     }
 }
 
-impl ops::Mul for Number {
+impl ops::Add for Number {
     type Output = Number;
 
-    fn mul(self, other: Number) -> Number {
+    fn add(self, other: Number) -> Number {
         match (self, other) {
-            (Number::Integer(x), Number::Integer(y)) => Number::Integer(x * y),
-            (Number::Float(x), Number::Float(y)) => Number::Float(x * y),
-            (Number::Float(x), Number::Integer(y)) => Number::Float(x * (y as f64)),
-            (Number::Integer(x), Number::Float(y)) => Number::Float((x as f64) * y),
+            (Number::Integer(x), Number::Integer(y)) => {
+                match x.checked_add(y) {
+                    Some(sum) => Number::Integer(sum),
+                    None => Number::from(BigInt::from(x).add(&BigInt::from(y))),
+                }
+            }
+            (x, y) => {
+                match (x.as_bigint(), y.as_bigint()) {
+                    (Some(bx), Some(by)) => Number::from(bx.add(&by)),
+                    _ => Number::Float(x.as_float() + y.as_float()),
+                }
+            }
         }
     }
 }
 
-impl ops::Div for Number {
+impl ops::Sub for Number {
     type Output = Number;
 
-    fn div(self, other: Number) -> Number {
+    fn sub(self, other: Number) -> Number {
         match (self, other) {
             (Number::Integer(x), Number::Integer(y)) => {
-                if x % y == 0 {
-                    Number::Integer(x / y)
-                } else {
-                    Number::Float((x as f64) / (y as f64))
+                match x.checked_sub(y) {
+                    Some(difference) => Number::Integer(difference),
+                    None => Number::from(BigInt::from(x).sub(&BigInt::from(y))),
+                }
+            }
+            (x, y) => {
+                match (x.as_bigint(), y.as_bigint()) {
+                    (Some(bx), Some(by)) => Number::from(bx.sub(&by)),
+                    _ => Number::Float(x.as_float() - y.as_float()),
                 }
             }
-            (Number::Float(x), Number::Float(y)) => Number::Float(x / y),
-            (Number::Float(x), Number::Integer(y)) => Number::Float(x / (y as f64)),
-            (Number::Integer(x), Number::Float(y)) => Number::Float((x as f64) / y),
         }
     }
 }
 
-impl ops::Rem for Number {
+impl ops::Mul for Number {
     type Output = Number;
 
-    fn rem(self, other: Number) -> Number {
+    fn mul(self, other: Number) -> Number {
         match (self, other) {
-            (Number::Integer(x), Number::Integer(y)) => Number::Integer(x % y),
-            (Number::Float(x), Number::Float(y)) => Number::Float(x % y),
-            (Number::Float(x), Number::Integer(y)) => Number::Float(x % (y as f64)),
-            (Number::Integer(x), Number::Float(y)) => Number::Float((x as f64) % y),
+            (Number::Integer(x), Number::Integer(y)) => {
+                match x.checked_mul(y) {
+                    Some(product) => Number::Integer(product),
+                    None => Number::from(BigInt::from(x).mul(&BigInt::from(y))),
+                }
+            }
+            (x, y) => {
+                match (x.as_bigint(), y.as_bigint()) {
+                    (Some(bx), Some(by)) => Number::from(bx.mul(&by)),
+                    _ => Number::Float(x.as_float() * y.as_float()),
+                }
+            }
         }
     }
 }
@@ -163,46 +406,88 @@ impl ops::Neg for Number {
 
     fn neg(self) -> Number {
         match self {
-            Number::Integer(x) => Number::Integer(-x),
+            Number::Integer(x) => {
+                match x.checked_neg() {
+                    Some(negated) => Number::Integer(negated),
+                    None => Number::from(BigInt::from(x).neg()),
+                }
+            }
             Number::Float(x) => Number::Float(-x),
+            Number::BigInt(x) => Number::from(x.neg()),
+        }
+    }
+}
+
+impl fmt::Display for Number {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Number::Integer(x) => write!(f, "{}", x),
+            Number::Float(x) => write!(f, "{}", x),
+            Number::BigInt(ref x) => write!(f, "{}", x),
         }
     }
 }
 
 impl PartialEq for Value {
     fn eq(&self, other: &Value) -> bool {
-        match (self, other) {
-            (&Value::Number(a), &Value::Number(b)) => a == b,
-            (&Value::Bool(a), &Value::Bool(b)) => a == b,
-            (&Value::String(ref sa), &Value::String(ref sb)) => sa == sb,
-            (&Value::Tuple(ref ta), &Value::Tuple(ref tb)) => ta == tb,
-            _ => false,
-        }
+        self.equals(other, &mut Vec::new())
     }
 }
 
 impl PartialEq for Number {
     fn eq(&self, other: &Number) -> bool {
-        match (*self, *other) {
-            (Number::Integer(x), Number::Integer(y)) => x == y,
-            (Number::Float(x), Number::Float(y)) => x == y,
-            (Number::Float(x), Number::Integer(y)) => (x == x.trunc()) && (x as i64) == y,
-            (Number::Integer(x), Number::Float(y)) => (y == y.trunc()) && (y as i64) == x,
+        match (self, other) {
+            (&Number::Integer(x), &Number::Integer(y)) => x == y,
+            (&Number::Float(x), &Number::Float(y)) => x == y,
+            (&Number::Float(x), &Number::Integer(y)) => (x == x.trunc()) && (x as i64) == y,
+            (&Number::Integer(x), &Number::Float(y)) => (y == y.trunc()) && (y as i64) == x,
+            (&Number::BigInt(ref x), &Number::BigInt(ref y)) => x == y,
+            (&Number::BigInt(ref x), &Number::Integer(y)) |
+            (&Number::Integer(y), &Number::BigInt(ref x)) => *x == BigInt::from(y),
+            (&Number::BigInt(ref x), &Number::Float(y)) |
+            (&Number::Float(y), &Number::BigInt(ref x)) => BigInt::from_f64(y).as_ref() == Some(x),
         }
     }
 }
 
 impl PartialOrd for Number {
     fn partial_cmp(&self, other: &Number) -> Option<cmp::Ordering> {
-        match (*self, *other) {
-            (Number::Integer(x), Number::Integer(y)) => x.partial_cmp(&y),
-            (Number::Float(x), Number::Float(y)) => x.partial_cmp(&y),
-            (Number::Float(x), Number::Integer(y)) => x.partial_cmp(&(y as f64)),
-            (Number::Integer(x), Number::Float(y)) => (x as f64).partial_cmp(&y),
+        match (self, other) {
+            (&Number::Integer(x), &Number::Integer(y)) => x.partial_cmp(&y),
+            (&Number::Float(x), &Number::Float(y)) => x.partial_cmp(&y),
+            (&Number::Float(x), &Number::Integer(y)) => x.partial_cmp(&(y as f64)),
+            (&Number::Integer(x), &Number::Float(y)) => (x as f64).partial_cmp(&y),
+            (&Number::BigInt(ref x), &Number::BigInt(ref y)) => x.partial_cmp(y),
+            (&Number::BigInt(ref x), &Number::Integer(y)) => x.partial_cmp(&BigInt::from(y)),
+            (&Number::Integer(x), &Number::BigInt(ref y)) => BigInt::from(x).partial_cmp(y),
+            (&Number::Float(x), &Number::BigInt(ref y)) => compare_float_with_bigint(x, y),
+            (&Number::BigInt(ref x), &Number::Float(y)) => {
+                compare_float_with_bigint(y, x).map(|ordering| ordering.reverse())
+            }
         }
     }
 }
 
+// Compares exactly, since large integers cannot be converted to floats without
+// losing precision
+fn compare_float_with_bigint(x: f64, y: &BigInt) -> Option<cmp::Ordering> {
+    if x.is_nan() {
+        return None;
+    }
+    if x.is_infinite() {
+        return Some(if x > 0.0 {
+                        cmp::Ordering::Greater
+                    } else {
+                        cmp::Ordering::Less
+                    });
+    }
+    let floor = BigInt::from_f64(x.floor()).unwrap();
+    match floor.cmp(y) {
+        cmp::Ordering::Equal if x.fract() != 0.0 => Some(cmp::Ordering::Greater),
+        ordering => Some(ordering),
+    }
+}
+
 impl Value {
     pub fn get_type(&self) -> Type {
         match *self {
@@ -211,21 +496,39 @@ impl Value {
             Value::Function(_) => Type::Function(Box::new(None)),
             Value::String(_) => Type::String,
             Value::Tuple(_) => Type::Tuple,
+            Value::List(_) => Type::List,
+            Value::Map(_) => Type::Map,
+            Value::Struct(ref name, ref fields) => {
+                Type::Struct(name.clone(), Rc::new(fields.keys().cloned().collect()))
+            }
+            Value::Module(ref path, ref env) => {
+                Type::Module(path.clone(), Rc::new(env.borrow().get_own_types()))
+            }
+            Value::Result(_) => Type::Result,
+        }
+    }
+
+    /// Returns the integer that this value represents when used as a subscript
+    pub fn as_index(&self) -> Option<i64> {
+        match *self {
+            Value::Number(Number::Integer(i)) => Some(i),
+            Value::Number(Number::Float(f)) if f.fract() == 0.0 => Some(f.trunc() as i64),
+            _ => None,
         }
     }
 
     pub fn is_truthy(&self) -> bool {
         match *self {
-            Value::Number(n) => {
-                match n {
-                    Number::Integer(i) => i != 0,
-                    Number::Float(f) => f != 0.0,
-                }
-            }
+            Value::Number(ref n) => !n.is_zero(),
             Value::Bool(b) => b,
             Value::String(ref s) => s != "",
             Value::Function(_) => true,
             Value::Tuple(ref t) => !t.is_empty(),
+            Value::List(ref l) => !l.borrow().is_empty(),
+            Value::Map(ref m) => !m.is_empty(),
+            Value::Struct(..) |
+            Value::Module(..) |
+            Value::Result(_) => true,
         }
     }
 }
@@ -235,6 +538,7 @@ impl From<ast::Literal> for Value {
         match from {
             ast::Literal::Integer(x) => Value::Number(Number::Integer(x)),
             ast::Literal::Float(x) => Value::Number(Number::Float(x)),
+            ast::Literal::BigInt(x) => Value::Number(Number::BigInt(x)),
             ast::Literal::Bool(x) => Value::Bool(x),
             ast::Literal::String(s) => Value::String(s),
         }